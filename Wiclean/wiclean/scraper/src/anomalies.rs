@@ -0,0 +1,471 @@
+//! Isolation forest anomaly scoring over the weekly features,
+//! replacing the scikit-learn model in `03_anomaly_detection.ipynb`.
+//!
+//! Scores follow scikit-learn's `IsolationForest.decision_function`:
+//! negative scores are anomalies, and the offset is chosen so that a
+//! `contamination` fraction of the article-weeks is flagged.
+
+use crate::dataset;
+use crate::week::IsoWeek;
+use arrow::array::{
+    Array, AsArray, BooleanBuilder, Float64Builder, Int64Builder, RecordBatch, StringBuilder,
+};
+use arrow::datatypes::{DataType, Field, Float64Type, Int64Type, Schema, SchemaRef};
+use compact_str::CompactString;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rayon::prelude::*;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Instant;
+
+/// Columns of `weekly_feats.parquet` the model is trained on by
+/// default, those of the notebook.
+pub const FEATURE_COLUMNS: [&str; 6] = [
+    "total_edits",
+    "adds",
+    "removes",
+    "unique_targets",
+    "revert_count",
+    "odd_hour_edits",
+];
+
+#[derive(Debug, clap::Args)]
+pub struct AnomaliesArgs {
+    /// Weekly features written by `features`.
+    #[clap(long, default_value = "data/weekly_feats.parquet")]
+    features: PathBuf,
+    /// Columns of the weekly features to train on, e.g. with
+    /// `bytes_added,unsourced_addition,blankings,bursts` added to score
+    /// edits beyond link changes too.
+    #[clap(long, value_delimiter = ',', default_values_t = FEATURE_COLUMNS.map(String::from))]
+    feature_columns: Vec<String>,
+    /// Wiki to score the article-weeks of, by database name.
+    #[clap(long, default_value = dataset::DEFAULT_WIKI)]
+    wiki: String,
+    /// Only score these articles, given as the `article_id` column of a
+    /// Parquet file such as `top10k_typed_pages.parquet`.
+    #[clap(long)]
+    articles: Option<PathBuf>,
+    /// Article titles written by `postprocess-to-parquet`.
+    #[clap(long, default_value = dataset::ARTICLES_PATH)]
+    titles: PathBuf,
+    #[clap(long, default_value = "data/anomalies.parquet")]
+    output: PathBuf,
+    /// Expected fraction of anomalous article-weeks.
+    #[clap(long, default_value_t = 0.003)]
+    contamination: f64,
+    #[clap(long, default_value_t = 42)]
+    seed: u64,
+    #[clap(long, default_value_t = 100)]
+    trees: usize,
+    /// Article-weeks sampled to build each tree.
+    #[clap(long, default_value_t = 256)]
+    max_samples: usize,
+    /// Write all scored article-weeks instead of only the anomalies.
+    #[clap(long)]
+    all: bool,
+}
+
+pub fn score_anomalies(args: &AnomaliesArgs) -> anyhow::Result<()> {
+    let start = Instant::now();
+    anyhow::ensure!(
+        args.contamination > 0.0 && args.contamination <= 0.5,
+        "contamination must be in (0, 0.5]"
+    );
+    anyhow::ensure!(
+        !args.feature_columns.is_empty(),
+        "no feature columns to train on"
+    );
+    let articles = args
+        .articles
+        .as_ref()
+        .map(|path| dataset::read_id_set(path, "article_id", &args.wiki))
+        .transpose()?;
+
+    let mut keys = Vec::new();
+    let mut data = Vec::new();
+    for batch in dataset::open_reader(&args.features)? {
+        let batch = batch?;
+        let wikis = dataset::WikiColumn::new(&batch)?;
+        let src_articles = dataset::primitive_column::<Int64Type>(&batch, "src_article")?;
+        let weeks = dataset::string_column(&batch, "week")?;
+        let columns = args
+            .feature_columns
+            .iter()
+            .map(|name| dataset::primitive_column::<Int64Type>(&batch, name))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        for i in 0..batch.num_rows() {
+            let src_article = src_articles.value(i);
+            if wikis.value(i) != args.wiki
+                || articles
+                    .as_ref()
+                    .is_some_and(|articles| !articles.contains(&src_article))
+            {
+                continue;
+            }
+            keys.push((src_article, weeks.value(i).to_owned()));
+            data.extend(columns.iter().map(|column| column.value(i) as f64));
+        }
+    }
+    anyhow::ensure!(!keys.is_empty(), "no article-weeks to score");
+    tracing::info!("scoring {} article-weeks", keys.len());
+
+    let samples = Matrix::new(data, args.feature_columns.len()).standardized();
+    let forest = IsolationForest::fit(&samples, args.trees, args.max_samples, args.seed);
+    let scores = forest.score_samples(&samples);
+    let offset = percentile(&scores, args.contamination * 100.0);
+
+    let mut rows: Vec<(i64, String, f64)> = keys
+        .into_iter()
+        .zip(scores)
+        .map(|((src_article, week), score)| (src_article, week, score - offset))
+        .filter(|(_, _, score)| args.all || *score < 0.0)
+        .collect();
+    rows.sort_by(|a, b| a.2.total_cmp(&b.2));
+
+    let ids: foldhash::HashSet<i64> = rows.iter().map(|(id, _, _)| *id).collect();
+    let titles = dataset::read_article_titles(&args.titles, &args.wiki, |id| ids.contains(&id))?;
+    let display_titles =
+        dataset::read_display_titles(&args.titles, &args.wiki, |id| ids.contains(&id))?;
+
+    let mut writer = dataset::create_writer(&args.output, anomalies_schema())?;
+    for chunk in rows.chunks(16384) {
+        let mut wikis = StringBuilder::new();
+        let mut src_articles = Int64Builder::with_capacity(chunk.len());
+        let mut article_titles = StringBuilder::new();
+        let mut article_display_titles = StringBuilder::new();
+        let mut weeks = StringBuilder::new();
+        let mut anomaly_scores = Float64Builder::with_capacity(chunk.len());
+        let mut anomalies = BooleanBuilder::with_capacity(chunk.len());
+        for (src_article, week, score) in chunk {
+            wikis.append_value(&args.wiki);
+            src_articles.append_value(*src_article);
+            article_titles.append_option(titles.get(src_article));
+            article_display_titles.append_option(display_titles.get(src_article));
+            weeks.append_value(week);
+            anomaly_scores.append_value(*score);
+            anomalies.append_value(*score < 0.0);
+        }
+        writer.write(&RecordBatch::try_new(
+            SchemaRef::new(anomalies_schema()),
+            vec![
+                Arc::new(wikis.finish()),
+                Arc::new(src_articles.finish()),
+                Arc::new(article_titles.finish()),
+                Arc::new(article_display_titles.finish()),
+                Arc::new(weeks.finish()),
+                Arc::new(anomaly_scores.finish()),
+                Arc::new(anomalies.finish()),
+            ],
+        )?)?;
+    }
+    writer.close()?;
+
+    tracing::info!(
+        "wrote {} article-weeks to {} in {:.2?}",
+        rows.len(),
+        args.output.display(),
+        start.elapsed()
+    );
+    Ok(())
+}
+
+pub fn anomalies_schema() -> Schema {
+    Schema::new(vec![
+        Field::new("wiki", DataType::Utf8, false),
+        Field::new("src_article", DataType::Int64, false),
+        Field::new("title", DataType::Utf8, true),
+        Field::new("display_title", DataType::Utf8, true),
+        Field::new("week", DataType::Utf8, false),
+        Field::new("anomaly_score", DataType::Float64, false),
+        Field::new("anomaly", DataType::Boolean, false),
+    ])
+}
+
+/// One row of `anomalies.parquet`.
+#[derive(Debug, Clone)]
+pub struct ScoredWeek {
+    pub wiki: CompactString,
+    pub src_article: i64,
+    /// Normalized title, as in labels.
+    pub title: Option<CompactString>,
+    /// Title as written, which the temp DB is keyed by.
+    pub display_title: Option<CompactString>,
+    pub week: IsoWeek,
+    pub score: f64,
+    pub anomaly: bool,
+}
+
+impl ScoredWeek {
+    /// Title to look the article up by in the temp DB. Files written
+    /// before the `display_title` column existed only have the
+    /// normalized title, which may resolve to a redirect.
+    pub fn lookup_title(&self) -> Option<&str> {
+        self.display_title.as_deref().or(self.title.as_deref())
+    }
+}
+
+/// Reads `anomalies.parquet`, most anomalous first.
+pub fn read_anomalies(path: impl AsRef<Path>) -> anyhow::Result<Vec<ScoredWeek>> {
+    let mut rows = Vec::new();
+    for batch in dataset::open_reader(path)? {
+        let batch = batch?;
+        let wikis = dataset::WikiColumn::new(&batch)?;
+        let src_articles = dataset::primitive_column::<Int64Type>(&batch, "src_article")?;
+        let titles = dataset::string_column(&batch, "title")?;
+        let display_titles = batch
+            .column_by_name("display_title")
+            .and_then(|column| column.as_string_opt::<i32>());
+        let weeks = dataset::string_column(&batch, "week")?;
+        let scores = dataset::primitive_column::<Float64Type>(&batch, "anomaly_score")?;
+        let anomalies = batch
+            .column_by_name("anomaly")
+            .and_then(|column| column.as_boolean_opt());
+        for i in 0..batch.num_rows() {
+            let score = scores.value(i);
+            rows.push(ScoredWeek {
+                wiki: wikis.value(i).into(),
+                src_article: src_articles.value(i),
+                title: (!titles.is_null(i)).then(|| titles.value(i).into()),
+                display_title: display_titles
+                    .filter(|titles| !titles.is_null(i))
+                    .map(|titles| titles.value(i).into()),
+                week: weeks.value(i).parse()?,
+                score,
+                anomaly: anomalies.map_or(score < 0.0, |anomalies| anomalies.value(i)),
+            });
+        }
+    }
+    rows.sort_by(|a, b| a.score.total_cmp(&b.score));
+    Ok(rows)
+}
+
+/// Row-major sample matrix.
+struct Matrix {
+    data: Vec<f64>,
+    num_features: usize,
+}
+
+impl Matrix {
+    fn new(data: Vec<f64>, num_features: usize) -> Self {
+        Self { data, num_features }
+    }
+
+    fn len(&self) -> usize {
+        self.data.len() / self.num_features
+    }
+
+    fn row(&self, i: usize) -> &[f64] {
+        &self.data[i * self.num_features..(i + 1) * self.num_features]
+    }
+
+    /// Scales every feature to zero mean and unit variance, like
+    /// scikit-learn's `StandardScaler`.
+    fn standardized(mut self) -> Self {
+        let n = self.len() as f64;
+        for feature in 0..self.num_features {
+            let values = || self.data.iter().skip(feature).step_by(self.num_features);
+            let mean = values().sum::<f64>() / n;
+            let std = (values().map(|v| (v - mean).powi(2)).sum::<f64>() / n).sqrt();
+            let scale = if std == 0.0 { 1.0 } else { std };
+            for value in self
+                .data
+                .iter_mut()
+                .skip(feature)
+                .step_by(self.num_features)
+            {
+                *value = (*value - mean) / scale;
+            }
+        }
+        self
+    }
+}
+
+enum Node {
+    Split {
+        feature: usize,
+        threshold: f64,
+        left: usize,
+        right: usize,
+    },
+    Leaf {
+        size: usize,
+    },
+}
+
+struct IsolationTree {
+    nodes: Vec<Node>,
+}
+
+struct IsolationForest {
+    trees: Vec<IsolationTree>,
+    max_samples: usize,
+}
+
+impl IsolationForest {
+    fn fit(samples: &Matrix, num_trees: usize, max_samples: usize, seed: u64) -> Self {
+        let max_samples = max_samples.min(samples.len());
+        let max_depth = (max_samples.max(2) as f64).log2().ceil() as usize;
+        let mut rng = StdRng::seed_from_u64(seed);
+        let tree_seeds: Vec<u64> = (0..num_trees).map(|_| rng.random()).collect();
+        let trees = tree_seeds
+            .into_par_iter()
+            .map(|seed| {
+                let mut rng = StdRng::seed_from_u64(seed);
+                let indices =
+                    rand::seq::index::sample(&mut rng, samples.len(), max_samples).into_vec();
+                let mut tree = IsolationTree { nodes: Vec::new() };
+                tree.grow(samples, indices, 0, max_depth, &mut rng);
+                tree
+            })
+            .collect();
+        Self { trees, max_samples }
+    }
+
+    /// scikit-learn's `score_samples`: the negated anomaly score of
+    /// Liu et al., between -1 (most anomalous) and 0.
+    fn score_samples(&self, samples: &Matrix) -> Vec<f64> {
+        let normalization = average_path_length(self.max_samples);
+        (0..samples.len())
+            .into_par_iter()
+            .map(|i| {
+                let row = samples.row(i);
+                let mean_depth = self
+                    .trees
+                    .iter()
+                    .map(|tree| tree.path_length(row))
+                    .sum::<f64>()
+                    / self.trees.len() as f64;
+                -(2f64.powf(-mean_depth / normalization))
+            })
+            .collect()
+    }
+}
+
+impl IsolationTree {
+    /// Adds the subtree isolating `indices` and returns its node index.
+    fn grow(
+        &mut self,
+        samples: &Matrix,
+        indices: Vec<usize>,
+        depth: usize,
+        max_depth: usize,
+        rng: &mut StdRng,
+    ) -> usize {
+        let node = self.nodes.len();
+        self.nodes.push(Node::Leaf {
+            size: indices.len(),
+        });
+        if depth >= max_depth || indices.len() <= 1 {
+            return node;
+        }
+
+        // split on a random non-constant feature at a random threshold
+        let ranges: Vec<(usize, f64, f64)> = (0..samples.num_features)
+            .filter_map(|feature| {
+                let (min, max) = indices.iter().fold((f64::MAX, f64::MIN), |(min, max), &i| {
+                    let value = samples.row(i)[feature];
+                    (min.min(value), max.max(value))
+                });
+                (min < max).then_some((feature, min, max))
+            })
+            .collect();
+        if ranges.is_empty() {
+            return node;
+        }
+        let (feature, min, max) = ranges[rng.random_range(0..ranges.len())];
+        let threshold = rng.random_range(min..max);
+
+        let (left, right): (Vec<usize>, Vec<usize>) = indices
+            .into_iter()
+            .partition(|&i| samples.row(i)[feature] < threshold);
+        let left = self.grow(samples, left, depth + 1, max_depth, rng);
+        let right = self.grow(samples, right, depth + 1, max_depth, rng);
+        self.nodes[node] = Node::Split {
+            feature,
+            threshold,
+            left,
+            right,
+        };
+        node
+    }
+
+    fn path_length(&self, row: &[f64]) -> f64 {
+        let mut node = 0;
+        let mut depth = 0.0;
+        loop {
+            match self.nodes[node] {
+                Node::Split {
+                    feature,
+                    threshold,
+                    left,
+                    right,
+                } => {
+                    node = if row[feature] < threshold {
+                        left
+                    } else {
+                        right
+                    };
+                    depth += 1.0;
+                }
+                // unresolved samples are expected to need this many more splits
+                Node::Leaf { size } => return depth + average_path_length(size),
+            }
+        }
+    }
+}
+
+/// Average path length of an unsuccessful binary search tree
+/// lookup among `n` samples, `c(n)` in Liu et al.
+fn average_path_length(n: usize) -> f64 {
+    match n {
+        0 | 1 => 0.0,
+        2 => 1.0,
+        n => {
+            let n = n as f64;
+            2.0 * ((n - 1.0).ln() + 0.577_215_664_901_532_9) - 2.0 * (n - 1.0) / n
+        }
+    }
+}
+
+/// Percentile with linear interpolation, like `numpy.percentile`.
+fn percentile(values: &[f64], percent: f64) -> f64 {
+    let mut sorted = values.to_vec();
+    sorted.sort_by(f64::total_cmp);
+    let rank = percent / 100.0 * (sorted.len() - 1) as f64;
+    let (lower, upper) = (rank.floor() as usize, rank.ceil() as usize);
+    sorted[lower] + (sorted[upper] - sorted[lower]) * (rank - lower as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_isolates_outlier() {
+        let mut data = Vec::new();
+        for i in 0..500 {
+            data.extend([(i % 7) as f64, (i % 5) as f64]);
+        }
+        data.extend([100.0, 90.0]);
+        let samples = Matrix::new(data, 2).standardized();
+
+        let forest = IsolationForest::fit(&samples, 100, 256, 7);
+        let scores = forest.score_samples(&samples);
+        let most_anomalous = (0..scores.len())
+            .min_by(|&a, &b| scores[a].total_cmp(&scores[b]))
+            .unwrap();
+        assert_eq!(most_anomalous, 500);
+
+        // same seed, same scores
+        let again = IsolationForest::fit(&samples, 100, 256, 7).score_samples(&samples);
+        assert_eq!(scores, again);
+    }
+
+    #[test]
+    fn test_percentile() {
+        assert_eq!(percentile(&[4.0, 1.0, 3.0, 2.0], 50.0), 2.5);
+        assert_eq!(percentile(&[4.0, 1.0, 3.0, 2.0], 0.0), 1.0);
+    }
+}
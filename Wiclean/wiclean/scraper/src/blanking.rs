@@ -0,0 +1,106 @@
+//! Detection of page blanking and redirect hijacking: revisions that
+//! delete most of an article or replace it with a redirect, often to
+//! an unrelated page. Text lengths and redirect targets are extracted
+//! while parsing, so this only compares consecutive revisions.
+
+use crate::ingest::reverts;
+use crate::ingest::temp_db::TempArticleRevision;
+
+/// Revisions shorter than this, in bytes, are not content pages.
+/// Blanking or redirecting such stubs is usually a merge. This also
+/// keeps redirects ingested before redirect targets were kept from
+/// counting as content.
+const MIN_CONTENT_LEN: u64 = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlagKind {
+    /// Most of the text was deleted.
+    Blanking,
+    /// A content page was turned into a redirect.
+    Redirect,
+}
+
+impl FlagKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            FlagKind::Blanking => "blanking",
+            FlagKind::Redirect => "redirect",
+        }
+    }
+}
+
+/// A flagged revision, by index into the revisions passed to
+/// [`detect`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Flag {
+    pub revision: usize,
+    pub kind: FlagKind,
+    /// The later revision that restored the text from before the
+    /// flagged one, if any.
+    pub reverted_by: Option<usize>,
+}
+
+/// Flags the revisions of an article, sorted oldest first, that turn
+/// a content page into a redirect or shrink it by more than
+/// `min_drop`, a fraction of its length.
+pub fn detect(revisions: &[TempArticleRevision], min_drop: f64) -> Vec<Flag> {
+    let mut flags = Vec::new();
+    for (i, pair) in revisions.windows(2).enumerate() {
+        let (parent, revision) = (&pair[0], &pair[1]);
+        let (Some(parent_len), Some(len)) = (parent.text_len, revision.text_len) else {
+            continue;
+        };
+        if parent_len < MIN_CONTENT_LEN || parent.redirect.is_some() {
+            continue;
+        }
+        let kind = if revision.redirect.is_some() {
+            FlagKind::Redirect
+        } else if (len as f64) < parent_len as f64 * (1.0 - min_drop) {
+            FlagKind::Blanking
+        } else {
+            continue;
+        };
+        flags.push(Flag {
+            revision: i + 1,
+            kind,
+            reverted_by: reverts::find_restore(revisions, i, i + 1),
+        });
+    }
+    flags
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jiff::Timestamp;
+
+    #[test]
+    fn test_detect() {
+        let revision = |text| TempArticleRevision::from_text(0, 1, Timestamp::default(), text);
+        let article = "Detroit is the largest city in Michigan. ".repeat(20);
+        let revisions = vec![
+            revision(&article),
+            revision("Detroit is"),
+            revision(&article),
+            revision("#REDIRECT [[Adult Swim]]"),
+            revision("#REDIRECT [[Cartoon Network]]"),
+            revision(&format!("{article} It is on the Detroit River.")),
+        ];
+        assert_eq!(
+            detect(&revisions, 0.5),
+            [
+                Flag {
+                    revision: 1,
+                    kind: FlagKind::Blanking,
+                    reverted_by: Some(2),
+                },
+                Flag {
+                    revision: 3,
+                    kind: FlagKind::Redirect,
+                    reverted_by: None,
+                },
+            ]
+        );
+        assert!(detect(&revisions[..2], 0.999).is_empty());
+    }
+}
@@ -0,0 +1,122 @@
+//! Detection of edit bursts: runs of consecutive revisions of an
+//! article by one user in quick succession, such as a change pushed
+//! through in many small edits.
+
+use crate::ingest::temp_db::TempArticleRevision;
+use crate::ingest::{parser, reverts};
+use jiff::SignedDuration;
+
+/// A burst of revisions `first..=last`, by index into the revisions
+/// passed to [`detect`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Run {
+    pub first: usize,
+    pub last: usize,
+    /// Links added plus links removed by the revisions of the run.
+    pub link_churn: usize,
+    /// The later revision that restored the text from before the run,
+    /// if any.
+    pub reverted_by: Option<usize>,
+}
+
+impl Run {
+    pub fn edits(&self) -> usize {
+        self.last - self.first + 1
+    }
+}
+
+/// Finds the runs of at least `min_edits` consecutive revisions by the
+/// same user, each within `max_gap` of the one before. Revisions with
+/// user ID 0, whose contributor is hidden or unknown, are in no run.
+/// Revisions must be sorted oldest first.
+pub fn detect(
+    revisions: &[TempArticleRevision],
+    max_gap: SignedDuration,
+    min_edits: usize,
+) -> Vec<Run> {
+    let mut runs = Vec::new();
+    let mut first = 0;
+    for i in 1..=revisions.len() {
+        let continues = i < revisions.len()
+            && revisions[first].user_id != 0
+            && revisions[i].user_id == revisions[first].user_id
+            && revisions[i]
+                .timestamp
+                .duration_since(revisions[i - 1].timestamp)
+                <= max_gap;
+        if continues {
+            continue;
+        }
+        let last = i - 1;
+        if i - first >= min_edits && revisions[first].user_id != 0 {
+            runs.push(Run {
+                first,
+                last,
+                // the first revision of an article has no parent to
+                // compare with
+                link_churn: (first.max(1)..=last)
+                    .map(|j| link_churn(&revisions[j - 1], &revisions[j]))
+                    .sum(),
+                reverted_by: first
+                    .checked_sub(1)
+                    .and_then(|before| reverts::find_restore(revisions, before, last)),
+            });
+        }
+        first = i;
+    }
+    runs
+}
+
+/// Links of either revision missing from the other, not counting
+/// category links. Links are sorted by [`parser::find_links`].
+fn link_churn(parent: &TempArticleRevision, revision: &TempArticleRevision) -> usize {
+    let missing_from = |a: &TempArticleRevision, b: &TempArticleRevision| {
+        a.links
+            .iter()
+            .filter(|link| {
+                parser::category_name(link).is_none() && b.links.binary_search(link).is_err()
+            })
+            .count()
+    };
+    missing_from(revision, parent) + missing_from(parent, revision)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jiff::Timestamp;
+
+    #[test]
+    fn test_detect() {
+        let revision = |minute: i64, user_id, text, links| TempArticleRevision {
+            timestamp: Timestamp::from_second(minute * 60).unwrap(),
+            text_hash: TempArticleRevision::hash_text(text),
+            ..TempArticleRevision::for_test(user_id, "1970-01-01T00:00:00Z", links)
+        };
+        let revisions = vec![
+            revision(0, 1, "Russia", &["Moscow"]),
+            revision(60, 2, "Russia a", &["Kyiv", "Moscow"]),
+            revision(62, 2, "Russia ab", &["Kyiv"]),
+            revision(70, 2, "Russia abc", &["Category:Cities", "Kyiv", "Minsk"]),
+            // too long after the edit before to continue the burst
+            revision(90, 2, "Russia abcd", &["Kyiv", "Minsk"]),
+            revision(91, 3, "Russia", &["Moscow"]),
+            revision(92, 3, "Russia e", &["Moscow"]),
+            // hidden contributors are not one user
+            revision(93, 0, "Russia f", &["Moscow"]),
+            revision(94, 0, "Russia g", &["Moscow"]),
+            revision(95, 0, "Russia h", &["Moscow"]),
+        ];
+        let max_gap = SignedDuration::from_mins(10);
+        assert_eq!(
+            detect(&revisions, max_gap, 3),
+            [Run {
+                first: 1,
+                last: 3,
+                link_churn: 3,
+                reverted_by: Some(5),
+            }]
+        );
+        assert_eq!(detect(&revisions, max_gap, 2).len(), 2);
+    }
+}
@@ -0,0 +1,520 @@
+//! Readers and writers for the Parquet files produced by
+//! `postprocess-to-parquet` and consumed by the analysis subcommands.
+
+use crate::week::IsoWeek;
+use anyhow::Context;
+use arrow::array::{Array, ArrowPrimitiveType, AsArray, PrimitiveArray, RecordBatch, StringArray};
+use arrow::datatypes::{Int64Type, Schema, SchemaRef, TimestampSecondType};
+use compact_str::CompactString;
+use foldhash::{HashMap, HashSet};
+use jiff::Timestamp;
+use jiff::civil::Date;
+use jiff::tz::TimeZone;
+use parquet::arrow::ArrowWriter;
+use parquet::arrow::arrow_reader::{ParquetRecordBatchReader, ParquetRecordBatchReaderBuilder};
+use parquet::basic::{Compression, ZstdLevel};
+use parquet::file::properties::WriterProperties;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::iter::Peekable;
+use std::path::Path;
+use std::vec;
+
+/// Database name of the wiki analyzed unless told otherwise, and of
+/// every row in files written before multi-wiki support.
+pub const DEFAULT_WIKI: &str = "enwiki";
+
+pub const ARTICLES_PATH: &str = "data/articles.parquet";
+pub const LINKS_PATH: &str = "data/links.parquet";
+pub const REVISIONS_PATH: &str = "data/revisions.parquet";
+pub const BLANKINGS_PATH: &str = "data/blankings.parquet";
+pub const BURSTS_PATH: &str = "data/bursts.parquet";
+/// IDs of every title seen by `postprocess-to-parquet`, including link
+/// targets without an article.
+pub const ARTICLE_IDS_PATH: &str = "data/article_ids.parquet";
+
+const READ_BATCH_SIZE: usize = 65536;
+
+pub fn writer_properties() -> WriterProperties {
+    WriterProperties::builder()
+        .set_compression(Compression::ZSTD(ZstdLevel::try_new(3).unwrap()))
+        .build()
+}
+
+pub fn create_writer(path: impl AsRef<Path>, schema: Schema) -> anyhow::Result<ArrowWriter<File>> {
+    let path = path.as_ref();
+    let file =
+        File::create(path).with_context(|| format!("failed to create {}", path.display()))?;
+    Ok(ArrowWriter::try_new(
+        file,
+        SchemaRef::new(schema),
+        Some(writer_properties()),
+    )?)
+}
+
+pub fn open_reader(path: impl AsRef<Path>) -> anyhow::Result<ParquetRecordBatchReader> {
+    let path = path.as_ref();
+    let file = File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+    Ok(ParquetRecordBatchReaderBuilder::try_new(file)?
+        .with_batch_size(READ_BATCH_SIZE)
+        .build()?)
+}
+
+pub fn primitive_column<'a, T: ArrowPrimitiveType>(
+    batch: &'a RecordBatch,
+    name: &str,
+) -> anyhow::Result<&'a PrimitiveArray<T>> {
+    batch
+        .column_by_name(name)
+        .with_context(|| format!("missing column {name}"))?
+        .as_primitive_opt::<T>()
+        .with_context(|| format!("column {name} has an unexpected type"))
+}
+
+pub fn string_column<'a>(batch: &'a RecordBatch, name: &str) -> anyhow::Result<&'a StringArray> {
+    batch
+        .column_by_name(name)
+        .with_context(|| format!("missing column {name}"))?
+        .as_string_opt::<i32>()
+        .with_context(|| format!("column {name} has an unexpected type"))
+}
+
+/// The `wiki` column of a batch. Files written before multi-wiki
+/// support lack it and hold enwiki rows only.
+pub struct WikiColumn<'a>(Option<&'a StringArray>);
+
+impl<'a> WikiColumn<'a> {
+    pub fn new(batch: &'a RecordBatch) -> anyhow::Result<Self> {
+        match batch.column_by_name("wiki") {
+            Some(_) => Ok(Self(Some(string_column(batch, "wiki")?))),
+            None => Ok(Self(None)),
+        }
+    }
+
+    pub fn value(&self, i: usize) -> &'a str {
+        self.0.map_or(DEFAULT_WIKI, |wikis| wikis.value(i))
+    }
+}
+
+/// Time range of link events to analyze. Defaults to the
+/// two years the notebooks look at.
+#[derive(Debug, Clone, clap::Args)]
+pub struct Period {
+    /// Ignore link events before this date (UTC).
+    #[clap(long, default_value = "2023-01-01")]
+    pub from: Date,
+    /// Ignore link events on or after this date (UTC).
+    #[clap(long, default_value = "2025-01-01")]
+    pub to: Date,
+}
+
+impl Period {
+    pub fn bounds(&self) -> anyhow::Result<(Timestamp, Timestamp)> {
+        Ok((
+            self.from.to_zoned(TimeZone::UTC)?.timestamp(),
+            self.to.to_zoned(TimeZone::UTC)?.timestamp(),
+        ))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    Add,
+    Remove,
+}
+
+impl Action {
+    /// Symbol used for the action in the notebooks' `events` table
+    /// and in pattern items.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Action::Add => "+",
+            Action::Remove => "-",
+        }
+    }
+}
+
+/// One row of `links.parquet`: a link from the grouping article
+/// to `dst_article` that existed during `[created_at, removed_at)`.
+#[derive(Debug, Clone)]
+pub struct LinkInterval {
+    pub dst_article: i64,
+    pub created_at: Timestamp,
+    pub removed_at: Option<Timestamp>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct LinkEvent {
+    pub dst_article: i64,
+    pub timestamp: Timestamp,
+    pub action: Action,
+}
+
+impl LinkInterval {
+    /// The addition and, if any, removal of this link.
+    pub fn events(&self) -> impl Iterator<Item = LinkEvent> {
+        let added = LinkEvent {
+            dst_article: self.dst_article,
+            timestamp: self.created_at,
+            action: Action::Add,
+        };
+        let removed = self.removed_at.map(|timestamp| LinkEvent {
+            dst_article: self.dst_article,
+            timestamp,
+            action: Action::Remove,
+        });
+        std::iter::once(added).chain(removed)
+    }
+}
+
+/// Buckets the link events of one article within `[from, to)` by
+/// ISO week, ordered by time within each week.
+pub fn weekly_events(
+    intervals: &[LinkInterval],
+    from: Timestamp,
+    to: Timestamp,
+) -> BTreeMap<IsoWeek, Vec<LinkEvent>> {
+    let mut weeks = BTreeMap::<IsoWeek, Vec<LinkEvent>>::new();
+    for event in intervals.iter().flat_map(LinkInterval::events) {
+        if event.timestamp >= from && event.timestamp < to {
+            weeks
+                .entry(IsoWeek::of(event.timestamp))
+                .or_default()
+                .push(event);
+        }
+    }
+    for events in weeks.values_mut() {
+        events.sort_by_key(|event| event.timestamp);
+    }
+    weeks
+}
+
+/// Streams the IDs of the articles of `wiki` in `articles.parquet`.
+/// Every other file of `postprocess-to-parquet` lists its articles in
+/// this order, skipping those it has no rows for.
+pub fn article_ids(path: impl AsRef<Path>, wiki: &str) -> anyhow::Result<ArticleGroups<()>> {
+    Ok(ArticleGroups::new(
+        open_reader(path)?,
+        wiki,
+        decode_article_ids,
+    ))
+}
+
+fn decode_article_ids(batch: &RecordBatch, wiki: &str) -> anyhow::Result<Vec<(i64, ())>> {
+    let wikis = WikiColumn::new(batch)?;
+    let ids = primitive_column::<Int64Type>(batch, "id")?;
+    Ok((0..batch.num_rows())
+        .filter(|&i| wikis.value(i) == wiki)
+        .map(|i| (ids.value(i), ()))
+        .collect())
+}
+
+/// Streams the links of `wiki` in `links.parquet` one source article
+/// at a time.
+pub fn link_intervals(
+    path: impl AsRef<Path>,
+    wiki: &str,
+) -> anyhow::Result<ArticleGroups<LinkInterval>> {
+    Ok(ArticleGroups::new(
+        open_reader(path)?,
+        wiki,
+        decode_link_intervals,
+    ))
+}
+
+fn decode_link_intervals(
+    batch: &RecordBatch,
+    wiki: &str,
+) -> anyhow::Result<Vec<(i64, LinkInterval)>> {
+    let wikis = WikiColumn::new(batch)?;
+    let src_articles = primitive_column::<Int64Type>(batch, "src_article")?;
+    let dst_articles = primitive_column::<Int64Type>(batch, "dst_article")?;
+    let created_ats = primitive_column::<TimestampSecondType>(batch, "created_at")?;
+    let removed_ats = primitive_column::<TimestampSecondType>(batch, "removed_at")?;
+
+    let mut rows = Vec::with_capacity(batch.num_rows());
+    for i in 0..batch.num_rows() {
+        if wikis.value(i) != wiki {
+            continue;
+        }
+        rows.push((
+            src_articles.value(i),
+            LinkInterval {
+                dst_article: dst_articles.value(i),
+                created_at: Timestamp::from_second(created_ats.value(i))?,
+                removed_at: if removed_ats.is_null(i) {
+                    None
+                } else {
+                    Some(Timestamp::from_second(removed_ats.value(i))?)
+                },
+            },
+        ));
+    }
+    Ok(rows)
+}
+
+/// One row of `revisions.parquet`: a revision of the grouping article
+/// with what it changed relative to its parent, if known.
+#[derive(Debug, Clone)]
+pub struct RevisionRow {
+    pub timestamp: Timestamp,
+    pub bytes_added: Option<i64>,
+    pub citations_added: Option<i64>,
+    pub unsourced_addition: Option<i64>,
+}
+
+/// Streams the revisions of `wiki` in `revisions.parquet` one article
+/// at a time, in the same article order as `links.parquet`.
+pub fn revisions(path: impl AsRef<Path>, wiki: &str) -> anyhow::Result<ArticleGroups<RevisionRow>> {
+    Ok(ArticleGroups::new(
+        open_reader(path)?,
+        wiki,
+        decode_revisions,
+    ))
+}
+
+fn decode_revisions(batch: &RecordBatch, wiki: &str) -> anyhow::Result<Vec<(i64, RevisionRow)>> {
+    let wikis = WikiColumn::new(batch)?;
+    let articles = primitive_column::<Int64Type>(batch, "article")?;
+    let timestamps = primitive_column::<TimestampSecondType>(batch, "timestamp")?;
+    let bytes_added = primitive_column::<Int64Type>(batch, "bytes_added")?;
+    let citations_added = primitive_column::<Int64Type>(batch, "citations_added")?;
+    let unsourced_additions = primitive_column::<Int64Type>(batch, "unsourced_addition")?;
+    let optional =
+        |column: &PrimitiveArray<Int64Type>, i| (!column.is_null(i)).then(|| column.value(i));
+
+    let mut rows = Vec::with_capacity(batch.num_rows());
+    for i in 0..batch.num_rows() {
+        if wikis.value(i) != wiki {
+            continue;
+        }
+        rows.push((
+            articles.value(i),
+            RevisionRow {
+                timestamp: Timestamp::from_second(timestamps.value(i))?,
+                bytes_added: optional(bytes_added, i),
+                citations_added: optional(citations_added, i),
+                unsourced_addition: optional(unsourced_additions, i),
+            },
+        ));
+    }
+    Ok(rows)
+}
+
+/// One row of `blankings.parquet`: a revision that blanked most of an
+/// article or turned it into a redirect.
+#[derive(Debug, Clone)]
+pub struct BlankingRow {
+    pub timestamp: Timestamp,
+    /// Whether the revision turned the article into a redirect rather
+    /// than deleting text.
+    pub redirect: bool,
+}
+
+/// Streams the flagged revisions of `wiki` in `blankings.parquet`
+/// one article at a time.
+pub fn blankings(path: impl AsRef<Path>, wiki: &str) -> anyhow::Result<ArticleGroups<BlankingRow>> {
+    Ok(ArticleGroups::new(
+        open_reader(path)?,
+        wiki,
+        decode_blankings,
+    ))
+}
+
+fn decode_blankings(batch: &RecordBatch, wiki: &str) -> anyhow::Result<Vec<(i64, BlankingRow)>> {
+    let wikis = WikiColumn::new(batch)?;
+    let articles = primitive_column::<Int64Type>(batch, "article")?;
+    let timestamps = primitive_column::<TimestampSecondType>(batch, "timestamp")?;
+    let kinds = string_column(batch, "kind")?;
+
+    let mut rows = Vec::with_capacity(batch.num_rows());
+    for i in 0..batch.num_rows() {
+        if wikis.value(i) == wiki {
+            rows.push((
+                articles.value(i),
+                BlankingRow {
+                    timestamp: Timestamp::from_second(timestamps.value(i))?,
+                    redirect: kinds.value(i) == "redirect",
+                },
+            ));
+        }
+    }
+    Ok(rows)
+}
+
+/// One row of `bursts.parquet`: a run of quick consecutive revisions
+/// of the grouping article by one user.
+#[derive(Debug, Clone)]
+pub struct BurstRow {
+    pub started_at: Timestamp,
+    pub edits: i64,
+    pub link_churn: i64,
+    pub reverted: bool,
+}
+
+/// Streams the edit bursts of `wiki` in `bursts.parquet` one article
+/// at a time.
+pub fn bursts(path: impl AsRef<Path>, wiki: &str) -> anyhow::Result<ArticleGroups<BurstRow>> {
+    Ok(ArticleGroups::new(open_reader(path)?, wiki, decode_bursts))
+}
+
+fn decode_bursts(batch: &RecordBatch, wiki: &str) -> anyhow::Result<Vec<(i64, BurstRow)>> {
+    let wikis = WikiColumn::new(batch)?;
+    let articles = primitive_column::<Int64Type>(batch, "article")?;
+    let started_ats = primitive_column::<TimestampSecondType>(batch, "started_at")?;
+    let edits = primitive_column::<Int64Type>(batch, "edits")?;
+    let link_churns = primitive_column::<Int64Type>(batch, "link_churn")?;
+    let reverted_ats = primitive_column::<TimestampSecondType>(batch, "reverted_at")?;
+
+    let mut rows = Vec::with_capacity(batch.num_rows());
+    for i in 0..batch.num_rows() {
+        if wikis.value(i) != wiki {
+            continue;
+        }
+        rows.push((
+            articles.value(i),
+            BurstRow {
+                started_at: Timestamp::from_second(started_ats.value(i))?,
+                edits: edits.value(i),
+                link_churn: link_churns.value(i),
+                reverted: !reverted_ats.is_null(i),
+            },
+        ));
+    }
+    Ok(rows)
+}
+
+/// Reads the normalized titles of the articles of `wiki` in
+/// `articles.parquet`, keeping only the IDs accepted by `keep`.
+pub fn read_article_titles(
+    path: impl AsRef<Path>,
+    wiki: &str,
+    keep: impl FnMut(i64) -> bool,
+) -> anyhow::Result<HashMap<i64, CompactString>> {
+    read_titles(path, wiki, "title", keep)
+}
+
+/// Like [`read_article_titles`], but reads the titles as written, which
+/// the temp DB is keyed by. Files written before the `display_title`
+/// column existed fall back to the normalized titles.
+pub fn read_display_titles(
+    path: impl AsRef<Path>,
+    wiki: &str,
+    keep: impl FnMut(i64) -> bool,
+) -> anyhow::Result<HashMap<i64, CompactString>> {
+    read_titles(path, wiki, "display_title", keep)
+}
+
+fn read_titles(
+    path: impl AsRef<Path>,
+    wiki: &str,
+    column: &str,
+    mut keep: impl FnMut(i64) -> bool,
+) -> anyhow::Result<HashMap<i64, CompactString>> {
+    let mut titles = HashMap::default();
+    for batch in open_reader(path)? {
+        let batch = batch?;
+        let wikis = WikiColumn::new(&batch)?;
+        let ids = primitive_column::<Int64Type>(&batch, "id")?;
+        let column = match batch.column_by_name(column) {
+            Some(_) => column,
+            None => "title",
+        };
+        let batch_titles = string_column(&batch, column)?;
+        for i in 0..batch.num_rows() {
+            if wikis.value(i) == wiki && keep(ids.value(i)) {
+                titles.insert(ids.value(i), batch_titles.value(i).into());
+            }
+        }
+    }
+    Ok(titles)
+}
+
+/// Reads an `Int64` column of a wiki's rows of a Parquet file into a
+/// set, e.g. the `article_id`s of `top10k_typed_pages.parquet`.
+pub fn read_id_set(
+    path: impl AsRef<Path>,
+    column: &str,
+    wiki: &str,
+) -> anyhow::Result<HashSet<i64>> {
+    let mut ids = HashSet::default();
+    for batch in open_reader(path)? {
+        let batch = batch?;
+        let wikis = WikiColumn::new(&batch)?;
+        let values = primitive_column::<Int64Type>(&batch, column)?;
+        ids.extend(
+            (0..batch.num_rows())
+                .filter(|&i| wikis.value(i) == wiki)
+                .map(|i| values.value(i)),
+        );
+    }
+    Ok(ids)
+}
+
+/// Decodes the rows of a wiki in a record batch, tagged with their
+/// article ID.
+pub type DecodeFn<T> = fn(&RecordBatch, &str) -> anyhow::Result<Vec<(i64, T)>>;
+
+/// Groups consecutive rows of a Parquet file by article ID.
+///
+/// `postprocess-to-parquet` writes all rows belonging to one article
+/// contiguously, so this lets us process files much larger than
+/// memory one article at a time.
+pub struct ArticleGroups<T> {
+    reader: ParquetRecordBatchReader,
+    wiki: CompactString,
+    decode: DecodeFn<T>,
+    rows: Peekable<vec::IntoIter<(i64, T)>>,
+}
+
+impl<T> ArticleGroups<T> {
+    pub fn new(reader: ParquetRecordBatchReader, wiki: &str, decode: DecodeFn<T>) -> Self {
+        Self {
+            reader,
+            wiki: wiki.into(),
+            decode,
+            rows: Vec::new().into_iter().peekable(),
+        }
+    }
+
+    /// Article ID of the next group, without consuming it.
+    pub fn peek_article(&mut self) -> anyhow::Result<Option<i64>> {
+        if !self.fill()? {
+            return Ok(None);
+        }
+        Ok(self.rows.peek().map(|(article, _)| *article))
+    }
+
+    fn fill(&mut self) -> anyhow::Result<bool> {
+        while self.rows.peek().is_none() {
+            match self.reader.next() {
+                Some(batch) => {
+                    self.rows = (self.decode)(&batch?, &self.wiki)?.into_iter().peekable()
+                }
+                None => return Ok(false),
+            }
+        }
+        Ok(true)
+    }
+
+    fn next_group(&mut self) -> anyhow::Result<Option<(i64, Vec<T>)>> {
+        let Some(article) = self.peek_article()? else {
+            return Ok(None);
+        };
+        let mut group = Vec::new();
+        while self.peek_article()? == Some(article) {
+            group.push(self.rows.next().unwrap().1);
+        }
+        Ok(Some((article, group)))
+    }
+}
+
+impl<T> Iterator for ArticleGroups<T> {
+    type Item = anyhow::Result<(i64, Vec<T>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_group().transpose()
+    }
+}
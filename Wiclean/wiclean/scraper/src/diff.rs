@@ -0,0 +1,274 @@
+//! Word-level wikitext diffs and link diffs between two revisions of
+//! an article. Text diffs need the revision text, which is only kept
+//! for articles archived by `ingest --archive-titles`.
+
+use crate::dataset;
+use crate::ingest::parser;
+use crate::ingest::temp_db::{TempArticle, TempArticleRevision, TempDb};
+use crate::postprocess_to_parquet::normalize_title;
+use anyhow::Context;
+use compact_str::CompactString;
+use foldhash::{HashMap, HashSet};
+use serde::Serialize;
+use similar::{ChangeTag, TextDiff};
+use std::fmt::Write;
+use std::time::{Duration, Instant};
+
+/// Unchanged characters kept around each change when rendering.
+const DEFAULT_CONTEXT: usize = 80;
+
+/// Upper bound on the time spent diffing one pair of revisions, after
+/// which the diff gets coarser rather than slower.
+const DIFF_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(Debug, clap::Args)]
+pub struct DiffArgs {
+    /// Article title, matched case-insensitively if there is no
+    /// exact match.
+    title: String,
+    /// Revision ID to diff from.
+    rev_a: u64,
+    /// Revision ID to diff to.
+    rev_b: u64,
+    /// Wiki of the article, by database name.
+    #[clap(long, default_value = dataset::DEFAULT_WIKI)]
+    wiki: String,
+    /// Unchanged characters shown around each change.
+    #[clap(long, default_value_t = DEFAULT_CONTEXT)]
+    context: usize,
+    /// Print the diff as JSON instead of text.
+    #[clap(long)]
+    json: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SegmentKind {
+    Equal,
+    Insert,
+    Delete,
+}
+
+/// A run of words that is unchanged, inserted or deleted.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Segment {
+    pub kind: SegmentKind,
+    pub text: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RevisionDiff {
+    pub from: u64,
+    pub to: u64,
+    /// Links of `to` missing from `from`, as written in `to`.
+    pub links_added: Vec<CompactString>,
+    /// Links of `from` missing from `to`, as written in `from`.
+    pub links_removed: Vec<CompactString>,
+    /// `None` if the text of either revision is not archived.
+    pub words: Option<Vec<Segment>>,
+}
+
+pub fn show_diff(temp_db: &TempDb, args: &DiffArgs) -> anyhow::Result<()> {
+    let temp_db = temp_db.for_wiki(&args.wiki);
+    let article = temp_db
+        .get_article(&args.title)?
+        .with_context(|| format!("no article titled {:?}", args.title))?;
+    let diff = diff_revisions(&temp_db, &article, args.rev_a, args.rev_b)?;
+
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&diff)?);
+        return Ok(());
+    }
+    println!("{}: revision {} → {}", article.title, diff.from, diff.to);
+    for link in &diff.links_removed {
+        println!("- [[{link}]]");
+    }
+    for link in &diff.links_added {
+        println!("+ [[{link}]]");
+    }
+    match &diff.words {
+        Some(words) => {
+            println!();
+            println!("{}", render_words(words, args.context));
+        }
+        None => println!("text of these revisions is not archived"),
+    }
+    Ok(())
+}
+
+/// Diffs two revisions of `article` by ID.
+pub fn diff_revisions(
+    temp_db: &TempDb,
+    article: &TempArticle,
+    from: u64,
+    to: u64,
+) -> anyhow::Result<RevisionDiff> {
+    let revision = |id| {
+        article
+            .revisions
+            .iter()
+            .find(|revision| revision.id == id)
+            .with_context(|| format!("{} has no revision {id}", article.title))
+    };
+    let (links_added, links_removed) = link_diff(revision(from)?, revision(to)?);
+    let words = match (temp_db.revision_text(from)?, temp_db.revision_text(to)?) {
+        (Some(old), Some(new)) => Some(word_diff(&old, &new)),
+        _ => None,
+    };
+    Ok(RevisionDiff {
+        from,
+        to,
+        links_added,
+        links_removed,
+        words,
+    })
+}
+
+/// Diffs each of `ids` against the revision before it, keeping only
+/// the diffs with archived text.
+pub fn text_diffs(
+    temp_db: &TempDb,
+    article: &TempArticle,
+    ids: impl IntoIterator<Item = u64>,
+) -> anyhow::Result<Vec<RevisionDiff>> {
+    let mut revisions: Vec<&TempArticleRevision> = article.revisions.iter().collect();
+    revisions.sort_by_key(|revision| revision.timestamp);
+    let previous: HashMap<u64, u64> = revisions
+        .windows(2)
+        .map(|pair| (pair[1].id, pair[0].id))
+        .collect();
+
+    let mut diffs = Vec::new();
+    for id in ids {
+        let Some(&from) = previous.get(&id) else {
+            continue;
+        };
+        if id == 0 || from == 0 {
+            continue;
+        }
+        let diff = diff_revisions(temp_db, article, from, id)?;
+        if diff.words.is_some() {
+            diffs.push(diff);
+        }
+    }
+    Ok(diffs)
+}
+
+/// Links added and removed between two revisions, compared by
+/// normalized title. Category links are left out.
+pub fn link_diff(
+    from: &TempArticleRevision,
+    to: &TempArticleRevision,
+) -> (Vec<CompactString>, Vec<CompactString>) {
+    fn links(revision: &TempArticleRevision) -> impl Iterator<Item = &CompactString> {
+        revision
+            .links
+            .iter()
+            .filter(|link| parser::category_name(link).is_none())
+    }
+    let normalized = |revision: &TempArticleRevision| -> HashSet<CompactString> {
+        links(revision).map(|link| normalize_title(link)).collect()
+    };
+    let (old, new) = (normalized(from), normalized(to));
+    let missing_from = |revision: &TempArticleRevision, other: &HashSet<CompactString>| {
+        let mut seen = HashSet::default();
+        let mut links: Vec<CompactString> = links(revision)
+            .filter(|link| {
+                let normalized = normalize_title(link);
+                !other.contains(&normalized) && seen.insert(normalized)
+            })
+            .cloned()
+            .collect();
+        links.sort_unstable();
+        links
+    };
+    (missing_from(to, &old), missing_from(from, &new))
+}
+
+/// Word-level diff of two texts, with adjacent words of the same kind
+/// merged into one segment.
+pub fn word_diff(old: &str, new: &str) -> Vec<Segment> {
+    let diff = TextDiff::configure()
+        .deadline(Instant::now() + DIFF_TIMEOUT)
+        .diff_words(old, new);
+    let mut segments = Vec::<Segment>::new();
+    for change in diff.iter_all_changes() {
+        let kind = match change.tag() {
+            ChangeTag::Equal => SegmentKind::Equal,
+            ChangeTag::Insert => SegmentKind::Insert,
+            ChangeTag::Delete => SegmentKind::Delete,
+        };
+        match segments.last_mut() {
+            Some(last) if last.kind == kind => last.text.push_str(change.value()),
+            _ => segments.push(Segment {
+                kind,
+                text: change.value().to_owned(),
+            }),
+        }
+    }
+    segments
+}
+
+/// Renders a word diff like `git diff --word-diff=plain`, marking
+/// deletions `[-like this-]` and insertions `{+like this+}`. Unchanged
+/// text further than `context` characters from a change is elided.
+pub fn render_words(segments: &[Segment], context: usize) -> String {
+    let mut out = String::new();
+    for (i, segment) in segments.iter().enumerate() {
+        match segment.kind {
+            SegmentKind::Delete => write!(out, "[-{}-]", segment.text).unwrap(),
+            SegmentKind::Insert => write!(out, "{{+{}+}}", segment.text).unwrap(),
+            SegmentKind::Equal => {
+                let after_change = if i > 0 { context } else { 0 };
+                let before_change = if i + 1 < segments.len() { context } else { 0 };
+                out.push_str(&elide(&segment.text, after_change, before_change));
+            }
+        }
+    }
+    out
+}
+
+/// Keeps the first `head` and last `tail` characters of `text`.
+fn elide(text: &str, head: usize, tail: usize) -> String {
+    let len = text.chars().count();
+    if len <= head + tail {
+        return text.to_owned();
+    }
+    let head: String = text.chars().take(head).collect();
+    let tail: String = text.chars().skip(len - tail).collect();
+    match (head.is_empty(), tail.is_empty()) {
+        (true, true) => "…".to_owned(),
+        (true, false) => format!("… {tail}"),
+        (false, true) => format!("{head} …"),
+        (false, false) => format!("{head} … {tail}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_word_diff() {
+        let old = "Macy's is an American department store. It was founded in 1858.";
+        let new = "Macy's was an American department store. It was founded in 1858.";
+        let segments = word_diff(old, new);
+        assert_eq!(
+            segments
+                .iter()
+                .filter(|segment| segment.kind != SegmentKind::Equal)
+                .map(|segment| segment.text.as_str())
+                .collect::<Vec<_>>(),
+            ["is", "was"]
+        );
+        assert_eq!(render_words(&segments, 7), "Macy's [-is-]{+was+} an Ame …");
+
+        let revision = |links| TempArticleRevision::for_test(0, "2024-01-01T00:00:00Z", links);
+        let (added, removed) = link_diff(
+            &revision(&["New York City", "Department store"]),
+            &revision(&["new York City", "Retail", "Category:Macy's"]),
+        );
+        assert_eq!(added, ["Retail"]);
+        assert_eq!(removed, ["Department store"]);
+    }
+}
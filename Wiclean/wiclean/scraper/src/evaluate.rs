@@ -0,0 +1,320 @@
+//! Scores the anomaly detector against hand-labelled article-weeks.
+//!
+//! A label is `valid` when the article-week really is anomalous. An
+//! article-week counts as flagged when `anomalies.parquet` marks it as
+//! an anomaly; article-weeks missing from the file were not flagged.
+
+use crate::anomalies;
+use crate::dataset;
+use crate::labels::{self, Label, LabelKey};
+use compact_str::CompactString;
+use foldhash::HashMap;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, clap::Args)]
+pub struct EvaluateArgs {
+    /// Labelled article-weeks, either in the `examples.txt` format or
+    /// as a `.tsv` file written by `label`.
+    #[clap(long, default_value = "examples.txt")]
+    labels: PathBuf,
+    /// Scores written by `anomalies`, preferably with `--all`.
+    #[clap(long, default_value = "data/anomalies.parquet")]
+    anomalies: PathBuf,
+    /// Wiki to evaluate on, by database name.
+    #[clap(long, default_value = dataset::DEFAULT_WIKI)]
+    wiki: String,
+    /// Cutoffs for precision@k.
+    #[clap(long, value_delimiter = ',', default_value = "10,50,100,500")]
+    k: Vec<usize>,
+    /// Print the report as JSON instead of a table.
+    #[clap(long)]
+    json: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct Report {
+    labels: usize,
+    valid: usize,
+    invalid: usize,
+    /// Labelled article-weeks present in the anomalies file.
+    scored: usize,
+    confusion: Confusion,
+    precision: Option<f64>,
+    recall: Option<f64>,
+    precision_at_k: Vec<PrecisionAtK>,
+    examples: Vec<Example>,
+}
+
+#[derive(Debug, Default, Serialize)]
+struct Confusion {
+    true_positives: usize,
+    false_positives: usize,
+    false_negatives: usize,
+    true_negatives: usize,
+}
+
+#[derive(Debug, Serialize)]
+struct PrecisionAtK {
+    k: usize,
+    /// Labelled article-weeks among the top k.
+    labelled: usize,
+    valid: usize,
+    precision: Option<f64>,
+}
+
+#[derive(Debug, Serialize)]
+struct Example {
+    title: CompactString,
+    week: String,
+    valid: bool,
+    /// 1-based position in the anomalies file, most anomalous first.
+    rank: Option<usize>,
+    score: Option<f64>,
+    flagged: bool,
+}
+
+struct Scored {
+    rank: usize,
+    score: f64,
+    anomaly: bool,
+}
+
+pub fn evaluate(args: &EvaluateArgs) -> anyhow::Result<()> {
+    let mut labels = labels::read_labels(&args.labels)?;
+    labels.retain(|label| label.wiki == args.wiki);
+    let labels = resolve_labels(labels);
+    anyhow::ensure!(
+        !labels.is_empty(),
+        "no labels of {} in {}",
+        args.wiki,
+        args.labels.display()
+    );
+    let scored = read_scores(&args.anomalies, &args.wiki)?;
+    let report = build_report(&labels, &scored, &args.k);
+
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        print_report(&report);
+    }
+    Ok(())
+}
+
+/// Collapses several labels of the same article-week by majority
+/// vote, dropping ties. Only the latest label of each labeller counts.
+fn resolve_labels(labels: Vec<Label>) -> Vec<Label> {
+    let mut latest = HashMap::<(LabelKey, Option<CompactString>), usize>::default();
+    for (i, label) in labels.iter().enumerate() {
+        latest.insert((label.key(), label.labeller.clone()), i);
+    }
+
+    let mut votes = HashMap::<LabelKey, (Label, i32)>::default();
+    let mut order = Vec::new();
+    for (i, label) in labels.into_iter().enumerate() {
+        let key = label.key();
+        if latest[&(key.clone(), label.labeller.clone())] != i {
+            continue;
+        }
+        let vote = if label.valid { 1 } else { -1 };
+        match votes.get_mut(&key) {
+            Some((_, total)) => *total += vote,
+            None => {
+                order.push(key.clone());
+                votes.insert(key, (label, vote));
+            }
+        }
+    }
+    order
+        .into_iter()
+        .filter_map(|key| {
+            let (mut label, total) = votes.remove(&key)?;
+            if total == 0 {
+                tracing::warn!("conflicting labels for {} {}", label.title, label.week);
+                return None;
+            }
+            label.valid = total > 0;
+            Some(label)
+        })
+        .collect()
+}
+
+fn read_scores(path: &Path, wiki: &str) -> anyhow::Result<HashMap<LabelKey, Scored>> {
+    Ok(anomalies::read_anomalies(path)?
+        .into_iter()
+        .filter(|row| row.wiki == wiki)
+        .enumerate()
+        .filter_map(|(i, row)| {
+            let scored = Scored {
+                rank: i + 1,
+                score: row.score,
+                anomaly: row.anomaly,
+            };
+            Some(((row.wiki, row.title?, row.week), scored))
+        })
+        .collect())
+}
+
+fn build_report(labels: &[Label], scored: &HashMap<LabelKey, Scored>, ks: &[usize]) -> Report {
+    let mut confusion = Confusion::default();
+    let mut examples = Vec::with_capacity(labels.len());
+    for label in labels {
+        let scored = scored.get(&label.key());
+        let flagged = scored.is_some_and(|scored| scored.anomaly);
+        match (label.valid, flagged) {
+            (true, true) => confusion.true_positives += 1,
+            (false, true) => confusion.false_positives += 1,
+            (true, false) => confusion.false_negatives += 1,
+            (false, false) => confusion.true_negatives += 1,
+        }
+        examples.push(Example {
+            title: label.title.clone(),
+            week: label.week.to_string(),
+            valid: label.valid,
+            rank: scored.map(|scored| scored.rank),
+            score: scored.map(|scored| scored.score),
+            flagged,
+        });
+    }
+
+    let precision_at_k = ks
+        .iter()
+        .map(|&k| {
+            let top: Vec<&Example> = examples
+                .iter()
+                .filter(|example| example.rank.is_some_and(|rank| rank <= k))
+                .collect();
+            let valid = top.iter().filter(|example| example.valid).count();
+            PrecisionAtK {
+                k,
+                labelled: top.len(),
+                valid,
+                precision: ratio(valid, top.len()),
+            }
+        })
+        .collect();
+
+    let valid = labels.iter().filter(|label| label.valid).count();
+    Report {
+        labels: labels.len(),
+        valid,
+        invalid: labels.len() - valid,
+        scored: examples
+            .iter()
+            .filter(|example| example.rank.is_some())
+            .count(),
+        precision: ratio(
+            confusion.true_positives,
+            confusion.true_positives + confusion.false_positives,
+        ),
+        recall: ratio(
+            confusion.true_positives,
+            confusion.true_positives + confusion.false_negatives,
+        ),
+        confusion,
+        precision_at_k,
+        examples,
+    }
+}
+
+fn ratio(numerator: usize, denominator: usize) -> Option<f64> {
+    (denominator > 0).then(|| numerator as f64 / denominator as f64)
+}
+
+fn format_ratio(value: Option<f64>) -> String {
+    value.map_or_else(|| "-".to_owned(), |value| format!("{value:.3}"))
+}
+
+fn print_report(report: &Report) {
+    println!(
+        "{} labels ({} valid, {} invalid), {} found in the anomalies file",
+        report.labels, report.valid, report.invalid, report.scored
+    );
+    println!();
+    println!("{:<10}{:>10}{:>14}", "", "flagged", "not flagged");
+    println!(
+        "{:<10}{:>10}{:>14}",
+        "valid", report.confusion.true_positives, report.confusion.false_negatives
+    );
+    println!(
+        "{:<10}{:>10}{:>14}",
+        "invalid", report.confusion.false_positives, report.confusion.true_negatives
+    );
+    println!();
+    println!(
+        "precision {}  recall {}",
+        format_ratio(report.precision),
+        format_ratio(report.recall)
+    );
+    println!();
+    println!(
+        "{:>8}{:>10}{:>8}{:>13}",
+        "k", "labelled", "valid", "precision@k"
+    );
+    for at_k in &report.precision_at_k {
+        println!(
+            "{:>8}{:>10}{:>8}{:>13}",
+            at_k.k,
+            at_k.labelled,
+            at_k.valid,
+            format_ratio(at_k.precision)
+        );
+    }
+    println!();
+    for example in &report.examples {
+        println!(
+            "{:<8}{:>8}  {:<8}{} {}",
+            if example.valid { "valid" } else { "invalid" },
+            example
+                .rank
+                .map_or_else(|| "-".to_owned(), |rank| rank.to_string()),
+            if example.flagged { "flagged" } else { "" },
+            example.title,
+            example.week,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build_report() {
+        let labels = labels::parse_examples(
+            "1. Detroit, 2024-45: valid\n\
+             2. PBS, 2024-3: valid\n\
+             3. United States, 2023-20: invalid\n\
+             4. Atlantic Ocean, 2024-31: invalid\n",
+        )
+        .unwrap();
+        let scored: HashMap<_, _> = [
+            ("detroit", "2024-45", 1, true),
+            ("united states", "2023-20", 2, true),
+            ("atlantic ocean", "2024-31", 3, false),
+        ]
+        .into_iter()
+        .map(|(title, week, rank, anomaly)| {
+            let scored = Scored {
+                rank,
+                score: rank as f64 - 2.5,
+                anomaly,
+            };
+            (
+                ("enwiki".into(), title.into(), week.parse().unwrap()),
+                scored,
+            )
+        })
+        .collect();
+
+        let report = build_report(&labels, &scored, &[1, 2]);
+        assert_eq!(report.scored, 3);
+        assert_eq!(report.confusion.true_positives, 1);
+        assert_eq!(report.confusion.false_positives, 1);
+        assert_eq!(report.confusion.false_negatives, 1);
+        assert_eq!(report.confusion.true_negatives, 1);
+        assert_eq!(report.precision, Some(0.5));
+        assert_eq!(report.precision_at_k[0].precision, Some(1.0));
+        assert_eq!(report.precision_at_k[1].precision, Some(0.5));
+    }
+}
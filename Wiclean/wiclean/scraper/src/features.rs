@@ -0,0 +1,629 @@
+//! Weekly per-article link edit features, replacing the Spark
+//! feature extraction in `01_preprocess.ipynb`.
+//!
+//! The output uses the notebook's column names, so it can be read
+//! in place of `weekly_feats_v23.parquet`. Columns after those of the
+//! notebook aggregate `revisions.parquet`, `blankings.parquet` and
+//! `bursts.parquet` over the same article-weeks.
+
+use crate::dataset::{
+    self, Action, ArticleGroups, BlankingRow, BurstRow, LinkInterval, Period, RevisionRow,
+};
+use crate::week::IsoWeek;
+use anyhow::bail;
+use arrow::array::{Int64Builder, RecordBatch, StringBuilder};
+use arrow::datatypes::{DataType, Field, Schema, SchemaRef};
+use foldhash::HashMap;
+use jiff::Timestamp;
+use jiff::tz::TimeZone;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Instant;
+
+const BATCH_SIZE: usize = 16384;
+
+#[derive(Debug, clap::Args)]
+pub struct FeaturesArgs {
+    /// Articles written by `postprocess-to-parquet`, in whose order
+    /// the other files are read.
+    #[clap(long, default_value = dataset::ARTICLES_PATH)]
+    articles: PathBuf,
+    /// Link intervals written by `postprocess-to-parquet`.
+    #[clap(long, default_value = dataset::LINKS_PATH)]
+    links: PathBuf,
+    /// Revisions written by `postprocess-to-parquet`. Without it, the
+    /// text addition columns are zero.
+    #[clap(long, default_value = dataset::REVISIONS_PATH)]
+    revisions: PathBuf,
+    /// Blanked and redirected revisions written by
+    /// `postprocess-to-parquet`. Without it, the blanking columns are
+    /// zero.
+    #[clap(long, default_value = dataset::BLANKINGS_PATH)]
+    blankings: PathBuf,
+    /// Edit bursts written by `postprocess-to-parquet`. Without it, the
+    /// burst columns are zero.
+    #[clap(long, default_value = dataset::BURSTS_PATH)]
+    bursts: PathBuf,
+    /// Also write article-weeks without link changes in which text was
+    /// added, the article was blanked or a burst started. By default
+    /// rows are the weeks with link changes, as in the notebook, so
+    /// text-only anomalies such as a long plagiarized addition that
+    /// changes no links are dropped.
+    #[clap(long)]
+    all_weeks: bool,
+    /// Wiki to compute features for, by database name.
+    #[clap(long, default_value = dataset::DEFAULT_WIKI)]
+    wiki: String,
+    #[clap(long, default_value = "data/weekly_feats.parquet")]
+    output: PathBuf,
+    #[clap(flatten)]
+    period: Period,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WeeklyFeatures {
+    pub src_article: i64,
+    pub week: IsoWeek,
+    pub total_edits: i64,
+    pub adds: i64,
+    pub removes: i64,
+    pub unique_targets: i64,
+    /// Targets that were both added and removed during the week.
+    pub revert_count: i64,
+    /// Events between 23:00 and 06:00 UTC.
+    pub odd_hour_edits: i64,
+    pub edits: EditFeatures,
+}
+
+impl WeeklyFeatures {
+    fn new(src_article: i64, week: IsoWeek) -> Self {
+        Self {
+            src_article,
+            week,
+            total_edits: 0,
+            adds: 0,
+            removes: 0,
+            unique_targets: 0,
+            revert_count: 0,
+            odd_hour_edits: 0,
+            edits: EditFeatures::default(),
+        }
+    }
+}
+
+/// Features of an article-week beyond its link changes, from
+/// `revisions.parquet`, `blankings.parquet` and `bursts.parquet`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EditFeatures {
+    /// Text added by the week's revisions, ignoring removals.
+    pub bytes_added: i64,
+    pub citations_added: i64,
+    /// Largest unsourced addition of a single revision.
+    pub unsourced_addition: i64,
+    /// Revisions that deleted most of the text.
+    pub blankings: i64,
+    /// Revisions that turned the article into a redirect.
+    pub redirect_hijacks: i64,
+    /// Edit bursts starting this week.
+    pub bursts: i64,
+    /// Revisions in the longest of those bursts.
+    pub max_burst_edits: i64,
+    /// Links added and removed by those bursts.
+    pub burst_link_churn: i64,
+    pub reverted_bursts: i64,
+}
+
+/// [`EditFeatures`] of one article in the period by week. Only weeks
+/// with something to count get an entry.
+#[derive(Debug, Clone)]
+struct WeeklyEdits {
+    from: Timestamp,
+    to: Timestamp,
+    weeks: BTreeMap<IsoWeek, EditFeatures>,
+}
+
+impl WeeklyEdits {
+    fn new(from: Timestamp, to: Timestamp) -> Self {
+        Self {
+            from,
+            to,
+            weeks: BTreeMap::new(),
+        }
+    }
+
+    fn week(&mut self, timestamp: Timestamp) -> Option<&mut EditFeatures> {
+        if timestamp < self.from || timestamp >= self.to {
+            return None;
+        }
+        Some(self.weeks.entry(IsoWeek::of(timestamp)).or_default())
+    }
+
+    fn add_revision(&mut self, revision: &RevisionRow) {
+        let (Some(bytes_added), Some(citations_added), Some(unsourced_addition)) = (
+            revision.bytes_added,
+            revision.citations_added,
+            revision.unsourced_addition,
+        ) else {
+            return;
+        };
+        if bytes_added <= 0 && citations_added == 0 && unsourced_addition <= 0 {
+            return;
+        }
+        if let Some(week) = self.week(revision.timestamp) {
+            week.bytes_added += bytes_added.max(0);
+            week.citations_added += citations_added;
+            week.unsourced_addition = week.unsourced_addition.max(unsourced_addition);
+        }
+    }
+
+    fn add_blanking(&mut self, blanking: &BlankingRow) {
+        if let Some(week) = self.week(blanking.timestamp) {
+            if blanking.redirect {
+                week.redirect_hijacks += 1;
+            } else {
+                week.blankings += 1;
+            }
+        }
+    }
+
+    fn add_burst(&mut self, burst: &BurstRow) {
+        if let Some(week) = self.week(burst.started_at) {
+            week.bursts += 1;
+            week.max_burst_edits = week.max_burst_edits.max(burst.edits);
+            week.burst_link_churn += burst.link_churn;
+            week.reverted_bursts += burst.reverted as i64;
+        }
+    }
+
+    /// Completes the link features of the article with its edit
+    /// features. Weeks without link changes are added as rows of their
+    /// own if `all_weeks` is set, and dropped otherwise; the second
+    /// value counts the dropped weeks.
+    fn join(
+        mut self,
+        src_article: i64,
+        mut rows: Vec<WeeklyFeatures>,
+        all_weeks: bool,
+    ) -> (Vec<WeeklyFeatures>, usize) {
+        for row in &mut rows {
+            if let Some(edits) = self.weeks.remove(&row.week) {
+                row.edits = edits;
+            }
+        }
+        if !all_weeks {
+            return (rows, self.weeks.len());
+        }
+        rows.extend(self.weeks.into_iter().map(|(week, edits)| WeeklyFeatures {
+            edits,
+            ..WeeklyFeatures::new(src_article, week)
+        }));
+        rows.sort_unstable_by_key(|row| row.week);
+        (rows, 0)
+    }
+}
+
+/// A file of `postprocess-to-parquet` walked in the order of
+/// `articles.parquet`, one article at a time.
+struct Lockstep<T> {
+    path: PathBuf,
+    /// `None` if the file is missing, whose features are then zero.
+    groups: Option<ArticleGroups<T>>,
+}
+
+impl<T> Lockstep<T> {
+    fn new(path: &Path, groups: ArticleGroups<T>) -> Self {
+        Self {
+            path: path.to_owned(),
+            groups: Some(groups),
+        }
+    }
+
+    fn optional(
+        path: &Path,
+        features: &str,
+        open: impl FnOnce(&Path) -> anyhow::Result<ArticleGroups<T>>,
+    ) -> anyhow::Result<Self> {
+        if path.exists() {
+            return Ok(Self::new(path, open(path)?));
+        }
+        tracing::warn!(
+            "{} not found, {features} features will be zero",
+            path.display()
+        );
+        Ok(Self {
+            path: path.to_owned(),
+            groups: None,
+        })
+    }
+
+    /// Rows of `src_article`, which are next if the file has any.
+    fn take(&mut self, src_article: i64) -> anyhow::Result<Vec<T>> {
+        let Some(groups) = &mut self.groups else {
+            return Ok(Vec::new());
+        };
+        if groups.peek_article()? != Some(src_article) {
+            return Ok(Vec::new());
+        }
+        match groups.next() {
+            Some(group) => Ok(group?.1),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Fails if rows are left, which means the file lists its articles
+    /// in another order than `articles.parquet`.
+    fn finish(mut self) -> anyhow::Result<()> {
+        let Some(groups) = &mut self.groups else {
+            return Ok(());
+        };
+        if let Some(article) = groups.peek_article()? {
+            bail!(
+                "{} lists article {article} out of the order of the articles, \
+                 were they written by different runs?",
+                self.path.display()
+            );
+        }
+        Ok(())
+    }
+}
+
+pub fn compute_weekly_features(args: &FeaturesArgs) -> anyhow::Result<()> {
+    let start = Instant::now();
+    let (from, to) = args.period.bounds()?;
+
+    // every file lists its articles in the order of articles.parquet,
+    // so they are read side by side without holding any in memory
+    let mut links = Lockstep::new(
+        &args.links,
+        dataset::link_intervals(&args.links, &args.wiki)?,
+    );
+    let mut revisions = Lockstep::optional(&args.revisions, "text addition", |path| {
+        dataset::revisions(path, &args.wiki)
+    })?;
+    let mut blankings = Lockstep::optional(&args.blankings, "blanking", |path| {
+        dataset::blankings(path, &args.wiki)
+    })?;
+    let mut bursts = Lockstep::optional(&args.bursts, "burst", |path| {
+        dataset::bursts(path, &args.wiki)
+    })?;
+
+    let mut writer = dataset::create_writer(&args.output, weekly_features_schema())?;
+    let mut rows = Vec::with_capacity(BATCH_SIZE);
+    let mut num_rows = 0;
+    let mut num_dropped = 0;
+    for group in dataset::article_ids(&args.articles, &args.wiki)? {
+        let (src_article, _) = group?;
+        let mut edits = WeeklyEdits::new(from, to);
+        for revision in &revisions.take(src_article)? {
+            edits.add_revision(revision);
+        }
+        for blanking in &blankings.take(src_article)? {
+            edits.add_blanking(blanking);
+        }
+        for burst in &bursts.take(src_article)? {
+            edits.add_burst(burst);
+        }
+        let link_rows = article_weekly_features(src_article, &links.take(src_article)?, from, to);
+        let (article_rows, dropped) = edits.join(src_article, link_rows, args.all_weeks);
+        rows.extend(article_rows);
+        num_dropped += dropped;
+        if rows.len() >= BATCH_SIZE {
+            writer.write(&weekly_features_batch(&args.wiki, &rows)?)?;
+            num_rows += rows.len();
+            rows.clear();
+        }
+    }
+    links.finish()?;
+    revisions.finish()?;
+    blankings.finish()?;
+    bursts.finish()?;
+    if !rows.is_empty() {
+        writer.write(&weekly_features_batch(&args.wiki, &rows)?)?;
+        num_rows += rows.len();
+    }
+    writer.close()?;
+
+    if num_dropped > 0 {
+        tracing::info!(
+            "dropped {num_dropped} article-weeks with text additions, blankings or bursts \
+             but no link changes, pass --all-weeks to keep them"
+        );
+    }
+    tracing::info!(
+        "wrote {num_rows} article-weeks to {} in {:.2?}",
+        args.output.display(),
+        start.elapsed()
+    );
+    Ok(())
+}
+
+/// Link edit features of the weeks in which links of the article
+/// changed.
+fn article_weekly_features(
+    src_article: i64,
+    intervals: &[LinkInterval],
+    from: Timestamp,
+    to: Timestamp,
+) -> Vec<WeeklyFeatures> {
+    let mut features = Vec::new();
+    // whether each target was (added, removed) this week
+    let mut targets = HashMap::<i64, (bool, bool)>::default();
+    for (week, events) in dataset::weekly_events(intervals, from, to) {
+        let mut row = WeeklyFeatures::new(src_article, week);
+        row.total_edits = events.len() as i64;
+        targets.clear();
+        for event in events {
+            let target = targets.entry(event.dst_article).or_default();
+            match event.action {
+                Action::Add => {
+                    row.adds += 1;
+                    target.0 = true;
+                }
+                Action::Remove => {
+                    row.removes += 1;
+                    target.1 = true;
+                }
+            }
+            let hour = TimeZone::UTC.to_datetime(event.timestamp).hour();
+            if !(6..=22).contains(&hour) {
+                row.odd_hour_edits += 1;
+            }
+        }
+        row.unique_targets = targets.len() as i64;
+        row.revert_count = targets
+            .values()
+            .filter(|(added, removed)| *added && *removed)
+            .count() as i64;
+        features.push(row);
+    }
+    features
+}
+
+pub fn weekly_features_schema() -> Schema {
+    Schema::new(vec![
+        Field::new("wiki", DataType::Utf8, false),
+        Field::new("src_article", DataType::Int64, false),
+        Field::new("week", DataType::Utf8, false),
+        Field::new("total_edits", DataType::Int64, false),
+        Field::new("adds", DataType::Int64, false),
+        Field::new("removes", DataType::Int64, false),
+        Field::new("unique_targets", DataType::Int64, false),
+        Field::new("revert_count", DataType::Int64, false),
+        Field::new("odd_hour_edits", DataType::Int64, false),
+        Field::new("bytes_added", DataType::Int64, false),
+        Field::new("citations_added", DataType::Int64, false),
+        Field::new("unsourced_addition", DataType::Int64, false),
+        Field::new("blankings", DataType::Int64, false),
+        Field::new("redirect_hijacks", DataType::Int64, false),
+        Field::new("bursts", DataType::Int64, false),
+        Field::new("max_burst_edits", DataType::Int64, false),
+        Field::new("burst_link_churn", DataType::Int64, false),
+        Field::new("reverted_bursts", DataType::Int64, false),
+    ])
+}
+
+fn weekly_features_batch(wiki: &str, rows: &[WeeklyFeatures]) -> anyhow::Result<RecordBatch> {
+    let mut wikis = StringBuilder::with_capacity(rows.len(), rows.len() * wiki.len());
+    let mut src_articles = Int64Builder::with_capacity(rows.len());
+    let mut weeks = StringBuilder::with_capacity(rows.len(), rows.len() * 7);
+    let mut total_edits = Int64Builder::with_capacity(rows.len());
+    let mut adds = Int64Builder::with_capacity(rows.len());
+    let mut removes = Int64Builder::with_capacity(rows.len());
+    let mut unique_targets = Int64Builder::with_capacity(rows.len());
+    let mut revert_counts = Int64Builder::with_capacity(rows.len());
+    let mut odd_hour_edits = Int64Builder::with_capacity(rows.len());
+    let mut bytes_added = Int64Builder::with_capacity(rows.len());
+    let mut citations_added = Int64Builder::with_capacity(rows.len());
+    let mut unsourced_additions = Int64Builder::with_capacity(rows.len());
+    let mut blankings = Int64Builder::with_capacity(rows.len());
+    let mut redirect_hijacks = Int64Builder::with_capacity(rows.len());
+    let mut bursts = Int64Builder::with_capacity(rows.len());
+    let mut max_burst_edits = Int64Builder::with_capacity(rows.len());
+    let mut burst_link_churn = Int64Builder::with_capacity(rows.len());
+    let mut reverted_bursts = Int64Builder::with_capacity(rows.len());
+
+    for row in rows {
+        wikis.append_value(wiki);
+        src_articles.append_value(row.src_article);
+        weeks.append_value(row.week.to_string());
+        total_edits.append_value(row.total_edits);
+        adds.append_value(row.adds);
+        removes.append_value(row.removes);
+        unique_targets.append_value(row.unique_targets);
+        revert_counts.append_value(row.revert_count);
+        odd_hour_edits.append_value(row.odd_hour_edits);
+        bytes_added.append_value(row.edits.bytes_added);
+        citations_added.append_value(row.edits.citations_added);
+        unsourced_additions.append_value(row.edits.unsourced_addition);
+        blankings.append_value(row.edits.blankings);
+        redirect_hijacks.append_value(row.edits.redirect_hijacks);
+        bursts.append_value(row.edits.bursts);
+        max_burst_edits.append_value(row.edits.max_burst_edits);
+        burst_link_churn.append_value(row.edits.burst_link_churn);
+        reverted_bursts.append_value(row.edits.reverted_bursts);
+    }
+
+    Ok(RecordBatch::try_new(
+        SchemaRef::new(weekly_features_schema()),
+        vec![
+            Arc::new(wikis.finish()),
+            Arc::new(src_articles.finish()),
+            Arc::new(weeks.finish()),
+            Arc::new(total_edits.finish()),
+            Arc::new(adds.finish()),
+            Arc::new(removes.finish()),
+            Arc::new(unique_targets.finish()),
+            Arc::new(revert_counts.finish()),
+            Arc::new(odd_hour_edits.finish()),
+            Arc::new(bytes_added.finish()),
+            Arc::new(citations_added.finish()),
+            Arc::new(unsourced_additions.finish()),
+            Arc::new(blankings.finish()),
+            Arc::new(redirect_hijacks.finish()),
+            Arc::new(bursts.finish()),
+            Arc::new(max_burst_edits.finish()),
+            Arc::new(burst_link_churn.finish()),
+            Arc::new(reverted_bursts.finish()),
+        ],
+    )?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ts(s: &str) -> Timestamp {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_article_weekly_features() {
+        let intervals = vec![
+            // added and reverted within ISO week 2025-1, which starts in 2024
+            LinkInterval {
+                dst_article: 1,
+                created_at: ts("2024-12-30T23:30:00Z"),
+                removed_at: Some(ts("2024-12-31T12:00:00Z")),
+            },
+            LinkInterval {
+                dst_article: 2,
+                created_at: ts("2024-12-31T12:00:00Z"),
+                removed_at: Some(ts("2025-02-01T00:00:00Z")),
+            },
+            // outside of the analysis period
+            LinkInterval {
+                dst_article: 3,
+                created_at: ts("2022-06-01T12:00:00Z"),
+                removed_at: None,
+            },
+        ];
+
+        let features = article_weekly_features(
+            7,
+            &intervals,
+            ts("2023-01-01T00:00:00Z"),
+            ts("2025-01-01T00:00:00Z"),
+        );
+        assert_eq!(
+            features,
+            vec![WeeklyFeatures {
+                src_article: 7,
+                week: "2025-1".parse().unwrap(),
+                total_edits: 3,
+                adds: 2,
+                removes: 1,
+                unique_targets: 2,
+                revert_count: 1,
+                odd_hour_edits: 1,
+                edits: EditFeatures::default(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_weekly_edits() {
+        let revision = |timestamp, bytes_added, citations_added, unsourced_addition| RevisionRow {
+            timestamp: ts(timestamp),
+            bytes_added,
+            citations_added,
+            unsourced_addition,
+        };
+        let mut edits = WeeklyEdits::new(ts("2023-01-01T00:00:00Z"), ts("2025-01-01T00:00:00Z"));
+        for revision in [
+            // a large unsourced addition in a week without link changes
+            revision("2024-12-12T12:00:00Z", Some(5000), Some(0), Some(5000)),
+            // a citation added before any text in the week
+            revision("2024-12-30T10:00:00Z", Some(-10), Some(1), Some(0)),
+            revision("2024-12-30T23:30:00Z", Some(100), Some(1), Some(50)),
+            revision("2024-12-31T12:00:00Z", Some(-20), Some(0), Some(0)),
+            // first revision, without a parent to compare with
+            revision("2024-12-01T00:00:00Z", None, None, None),
+        ] {
+            edits.add_revision(&revision);
+        }
+        edits.add_blanking(&BlankingRow {
+            timestamp: ts("2024-12-31T13:00:00Z"),
+            redirect: true,
+        });
+        edits.add_burst(&BurstRow {
+            started_at: ts("2024-12-31T11:00:00Z"),
+            edits: 6,
+            link_churn: 2,
+            reverted: true,
+        });
+
+        let link_rows = vec![WeeklyFeatures {
+            total_edits: 3,
+            ..WeeklyFeatures::new(7, "2025-1".parse().unwrap())
+        }];
+        let week_1 = WeeklyFeatures {
+            total_edits: 3,
+            edits: EditFeatures {
+                bytes_added: 100,
+                citations_added: 2,
+                unsourced_addition: 50,
+                redirect_hijacks: 1,
+                bursts: 1,
+                max_burst_edits: 6,
+                burst_link_churn: 2,
+                reverted_bursts: 1,
+                ..EditFeatures::default()
+            },
+            ..WeeklyFeatures::new(7, "2025-1".parse().unwrap())
+        };
+        let week_50 = WeeklyFeatures {
+            edits: EditFeatures {
+                bytes_added: 5000,
+                unsourced_addition: 5000,
+                ..EditFeatures::default()
+            },
+            ..WeeklyFeatures::new(7, "2024-50".parse().unwrap())
+        };
+        assert_eq!(
+            edits.clone().join(7, link_rows.clone(), true),
+            (vec![week_50, week_1.clone()], 0)
+        );
+        assert_eq!(edits.join(7, link_rows, false), (vec![week_1], 1));
+    }
+
+    #[test]
+    fn test_lockstep() {
+        let path =
+            std::env::temp_dir().join(format!("lockstep-test-{}.parquet", std::process::id()));
+        let schema = Schema::new(vec![
+            Field::new("wiki", DataType::Utf8, false),
+            Field::new("id", DataType::Int64, false),
+        ]);
+        let mut writer = dataset::create_writer(&path, schema.clone()).unwrap();
+        writer
+            .write(
+                &RecordBatch::try_new(
+                    SchemaRef::new(schema),
+                    vec![
+                        Arc::new(arrow::array::StringArray::from(vec!["enwiki"; 3])),
+                        Arc::new(arrow::array::Int64Array::from(vec![1, 2, 3])),
+                    ],
+                )
+                .unwrap(),
+            )
+            .unwrap();
+        writer.close().unwrap();
+        let lockstep = || Lockstep::new(&path, dataset::article_ids(&path, "enwiki").unwrap());
+
+        // articles without rows in the file are skipped
+        let mut file = lockstep();
+        for src_article in [1, 5, 2, 3] {
+            let rows = file.take(src_article).unwrap();
+            assert_eq!(rows.len(), (src_article != 5) as usize);
+        }
+        file.finish().unwrap();
+
+        let mut file = lockstep();
+        for src_article in [1, 3, 2] {
+            file.take(src_article).unwrap();
+        }
+        assert!(file.finish().is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,154 @@
+//! Refreshing the history of a single article through the Action API,
+//! for when re-downloading a dump chunk is overkill.
+
+use crate::dataset;
+use crate::ingest::temp_db::{TempArticle, TempDb};
+use crate::wiki_api::{self, WikiApi};
+use compact_str::CompactString;
+use jiff::Timestamp;
+use jiff::civil::Date;
+use jiff::tz::TimeZone;
+
+#[derive(Debug, clap::Args)]
+pub struct FetchArticleArgs {
+    /// Article title as on the wiki.
+    title: String,
+    /// Wiki of the article, by database name.
+    #[clap(long, default_value = dataset::DEFAULT_WIKI)]
+    wiki: String,
+    /// `api.php` endpoint of the wiki. Defaults to that of the
+    /// Wikipedia of `--wiki`.
+    #[clap(long)]
+    api_url: Option<String>,
+    /// Fetch revisions from this date on, by default the same cutoff
+    /// as dump ingestion.
+    #[clap(long, default_value = "2023-01-01")]
+    since: Date,
+    /// Also archive the revision text, for `diff`.
+    #[clap(long)]
+    keep_text: bool,
+}
+
+pub async fn fetch_article(temp_db: TempDb, args: &FetchArticleArgs) -> anyhow::Result<()> {
+    let api_url = match &args.api_url {
+        Some(url) => url.clone(),
+        None => wiki_api::api_url(&args.wiki)?,
+    };
+    let api = WikiApi::new(wiki_api::client()?, api_url);
+    let since = args.since.to_zoned(TimeZone::UTC)?.timestamp();
+    let temp_db = temp_db.for_wiki(&args.wiki);
+    let fetched = fetch_into(&api, &temp_db, &args.title, since, args.keep_text).await?;
+    tracing::info!("fetched {fetched} revisions of {}", args.title);
+    Ok(())
+}
+
+/// Fetches the history of `title` since `since` and merges it into
+/// `temp_db`. Returns the number of revisions fetched.
+async fn fetch_into(
+    api: &WikiApi,
+    temp_db: &TempDb,
+    title: &str,
+    since: Timestamp,
+    keep_text: bool,
+) -> anyhow::Result<usize> {
+    let revisions = api.article_history(title, since).await?;
+    let Some(first) = revisions.first() else {
+        return Ok(0);
+    };
+    let article = TempArticle {
+        // as normalized by the API
+        title: first.title.clone(),
+        revisions: revisions
+            .iter()
+            .map(|revision| revision.to_temp_revision())
+            .collect(),
+    };
+    let texts: Vec<(u64, CompactString)> = if keep_text {
+        revisions
+            .iter()
+            .map(|revision| (revision.id, revision.text.as_str().into()))
+            .collect()
+    } else {
+        Vec::new()
+    };
+
+    let temp_db = temp_db.clone();
+    let merged = tokio::task::spawn_blocking(move || {
+        temp_db.insert_revision_texts(texts)?;
+        temp_db.merge_article_batch([article])
+    })
+    .await??;
+    tracing::info!("{merged} of them were new");
+    Ok(revisions.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::Router;
+    use axum::extract::Query;
+    use axum::response::IntoResponse;
+    use axum::routing::get;
+    use foldhash::HashMap;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    /// Serves recorded `prop=revisions` responses by continuation,
+    /// answering the first request with a `maxlag` error.
+    async fn stand_in() -> String {
+        let responses: HashMap<String, serde_json::Value> =
+            serde_json::from_str(include_str!("../test_api_history.json")).unwrap();
+        let lagged = Arc::new(AtomicBool::new(false));
+        let app = Router::new().route(
+            "/api.php",
+            get(
+                move |Query(params): Query<HashMap<String, String>>| async move {
+                    assert_eq!(params["maxlag"], "5");
+                    if !lagged.swap(true, Ordering::Relaxed) {
+                        let error = serde_json::json!({
+                            "error": {
+                                "code": "maxlag",
+                                "info": "Waiting for 10.64.16.8: 6 seconds lagged."
+                            }
+                        });
+                        return ([("retry-after", "0")], axum::Json(error)).into_response();
+                    }
+                    let page = params.get("rvcontinue").map_or("", String::as_str);
+                    axum::Json(responses[page].clone()).into_response()
+                },
+            ),
+        );
+        format!("{}/api.php", wiki_api::serve_stand_in(app).await)
+    }
+
+    #[tokio::test]
+    async fn test_fetch_fixture() {
+        let url = stand_in().await;
+        let api = WikiApi::new(wiki_api::client().unwrap(), url);
+        let path = std::env::temp_dir().join(format!("fetch-test-{}.redb", std::process::id()));
+        let (temp_db, _shutdown) = TempDb::open_at(&path).unwrap();
+
+        let since = "2023-01-01T00:00:00Z".parse().unwrap();
+        let fetched = fetch_into(&api, &temp_db, "macy's", since, true)
+            .await
+            .unwrap();
+        assert_eq!(fetched, 3);
+
+        let article = temp_db.get_article("Macy's").unwrap().unwrap();
+        assert_eq!(
+            article
+                .revisions
+                .iter()
+                .map(|revision| revision.id)
+                .collect::<Vec<_>>(),
+            [1150000000, 1160000000, 1170000000]
+        );
+        assert_eq!(article.revisions[2].links, ["Herald Square"]);
+        assert_eq!(
+            temp_db.revision_text(1160000000).unwrap().as_deref(),
+            Some("'''Macy's''' is a [[department store]] on [[Herald Square]].")
+        );
+        drop(temp_db);
+        std::fs::remove_file(path).ok();
+    }
+}
@@ -0,0 +1,259 @@
+//! Link history of a single article, read straight from `TempDb`
+//! instead of scanning `links.parquet`.
+
+use crate::dataset::{self, Action};
+use crate::ingest::temp_db::{TempArticle, TempDb};
+use crate::ingest::{parser, reverts};
+use crate::postprocess_to_parquet::normalize_title;
+use anyhow::Context;
+use compact_str::CompactString;
+use foldhash::HashMap;
+use jiff::Timestamp;
+use jiff::civil::Date;
+use jiff::tz::TimeZone;
+use serde::Serialize;
+
+#[derive(Debug, clap::Args)]
+pub struct HistoryArgs {
+    /// Article title, matched case-insensitively if there is no
+    /// exact match.
+    title: String,
+    /// Wiki of the article, by database name.
+    #[clap(long, default_value = dataset::DEFAULT_WIKI)]
+    wiki: String,
+    /// Only show events on or after this date.
+    #[clap(long)]
+    from: Option<Date>,
+    /// Only show events before this date.
+    #[clap(long)]
+    to: Option<Date>,
+    /// Print the history as JSON instead of a table.
+    #[clap(long)]
+    json: bool,
+}
+
+/// The addition or removal of a link by one revision.
+#[derive(Debug, Clone, Serialize)]
+pub struct TimelineEvent {
+    pub timestamp: Timestamp,
+    pub action: Action,
+    /// Link target as written by the revision that added it.
+    pub target: CompactString,
+    pub user_id: i64,
+}
+
+#[derive(Debug, Serialize)]
+struct History<'a> {
+    title: &'a str,
+    revisions: usize,
+    events: &'a [TimelineEvent],
+}
+
+pub fn show_history(temp_db: &TempDb, args: &HistoryArgs) -> anyhow::Result<()> {
+    let article = temp_db
+        .for_wiki(&args.wiki)
+        .get_article(&args.title)?
+        .with_context(|| format!("no article titled {:?}", args.title))?;
+    let from = args
+        .from
+        .map(|date| date.to_zoned(TimeZone::UTC))
+        .transpose()?
+        .map(|zoned| zoned.timestamp());
+    let to = args
+        .to
+        .map(|date| date.to_zoned(TimeZone::UTC))
+        .transpose()?
+        .map(|zoned| zoned.timestamp());
+
+    let mut events = link_timeline(&article);
+    events.retain(|event| {
+        from.is_none_or(|from| event.timestamp >= from) && to.is_none_or(|to| event.timestamp < to)
+    });
+
+    if args.json {
+        let history = History {
+            title: &article.title,
+            revisions: article.revisions.len(),
+            events: &events,
+        };
+        println!("{}", serde_json::to_string_pretty(&history)?);
+    } else {
+        println!(
+            "{}: {} revisions, {} link events",
+            article.title,
+            article.revisions.len(),
+            events.len()
+        );
+        for event in &events {
+            println!(
+                "{:<22}{:>21}  {} {}",
+                event.timestamp.strftime("%Y-%m-%d %H:%M:%S"),
+                event.user_id,
+                event.action.as_str(),
+                event.target
+            );
+        }
+    }
+    Ok(())
+}
+
+/// The links one revision added and removed.
+#[derive(Debug, Clone, Serialize)]
+pub struct RevisionChange {
+    /// Revision ID on the wiki, 0 if unknown.
+    pub id: u64,
+    pub timestamp: Timestamp,
+    pub user_id: i64,
+    /// Number of links after the revision.
+    pub links: usize,
+    pub added: Vec<CompactString>,
+    pub removed: Vec<CompactString>,
+    /// The earlier revision whose text this one restored, if any.
+    pub reverts_to: Option<u64>,
+}
+
+/// Replays the revisions of an article in order and records which
+/// links appear or disappear, like `postprocess-to-parquet` does
+/// when building link intervals. Category links are left out, as
+/// they are not links to other articles.
+pub fn revision_changes(article: &TempArticle) -> Vec<RevisionChange> {
+    let mut revisions: Vec<_> = article.revisions.iter().collect();
+    revisions.sort_by_key(|revision| revision.timestamp);
+
+    let mut changes = Vec::<RevisionChange>::with_capacity(revisions.len());
+    // normalized title -> title as written when added
+    let mut current = HashMap::<CompactString, CompactString>::default();
+    let mut this_revision = HashMap::<CompactString, &CompactString>::default();
+    for (i, revision) in revisions.iter().enumerate() {
+        this_revision.clear();
+        for link in &revision.links {
+            if parser::category_name(link).is_none() {
+                this_revision.entry(normalize_title(link)).or_insert(link);
+            }
+        }
+        let mut added = Vec::new();
+        for (normalized, link) in &this_revision {
+            if !current.contains_key(normalized) {
+                current.insert(normalized.clone(), (*link).clone());
+                added.push((*link).clone());
+            }
+        }
+        let mut removed = Vec::new();
+        current.retain(|normalized, link| {
+            let keep = this_revision.contains_key(normalized);
+            if !keep {
+                removed.push(link.clone());
+            }
+            keep
+        });
+        // hash map iteration order is arbitrary
+        added.sort_unstable();
+        removed.sort_unstable();
+        changes.push(RevisionChange {
+            id: revision.id,
+            timestamp: revision.timestamp,
+            user_id: revision.user_id,
+            links: current.len(),
+            added,
+            removed,
+            reverts_to: reverts::find_reverted(&revisions, i),
+        });
+    }
+    changes
+}
+
+/// Every link addition and removal of an article in time order.
+pub fn link_timeline(article: &TempArticle) -> Vec<TimelineEvent> {
+    let mut events = Vec::new();
+    for change in revision_changes(article) {
+        let event = |action, target: CompactString| TimelineEvent {
+            timestamp: change.timestamp,
+            action,
+            target,
+            user_id: change.user_id,
+        };
+        events.extend(
+            change
+                .removed
+                .iter()
+                .map(|target| event(Action::Remove, target.clone())),
+        );
+        events.extend(
+            change
+                .added
+                .iter()
+                .map(|target| event(Action::Add, target.clone())),
+        );
+    }
+    events
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ingest::temp_db::TempArticleRevision;
+
+    #[test]
+    fn test_link_timeline() {
+        let article = TempArticle {
+            title: "Detroit".into(),
+            revisions: vec![
+                TempArticleRevision::for_test(2, "2024-11-05T00:00:00Z", &[]),
+                TempArticleRevision::for_test(
+                    1,
+                    "2024-11-04T00:00:00Z",
+                    &["Michigan", "Ford", "Category:Cities in Michigan"],
+                ),
+                TempArticleRevision::for_test(3, "2024-11-06T00:00:00Z", &["michigan", "Ford"]),
+            ],
+        };
+
+        let events: Vec<_> = link_timeline(&article)
+            .into_iter()
+            .map(|event| (event.user_id, event.action, event.target))
+            .collect();
+        assert_eq!(
+            events,
+            vec![
+                (1, Action::Add, "Ford".into()),
+                (1, Action::Add, "Michigan".into()),
+                (2, Action::Remove, "Ford".into()),
+                (2, Action::Remove, "Michigan".into()),
+                (3, Action::Add, "Ford".into()),
+                (3, Action::Add, "michigan".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_reverts() {
+        let revision = |id, text: &str| TempArticleRevision {
+            id,
+            user_id: 1,
+            timestamp: Timestamp::from_second(id as i64).unwrap(),
+            links: Vec::new(),
+            text_hash: TempArticleRevision::hash_text(text),
+            ..Default::default()
+        };
+        let article = TempArticle {
+            title: "PBS".into(),
+            revisions: vec![
+                revision(1, "article"),
+                revision(2, ""),
+                revision(3, "article"),
+                revision(4, "article"),
+                revision(5, ""),
+                revision(6, "article"),
+            ],
+        };
+        let reverts: Vec<_> = revision_changes(&article)
+            .into_iter()
+            .map(|change| change.reverts_to)
+            .collect();
+        assert_eq!(
+            reverts,
+            [None, None, Some(1), None, Some(2), Some(4)],
+            "null edits are no reverts"
+        );
+    }
+}
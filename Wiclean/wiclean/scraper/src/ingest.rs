@@ -4,11 +4,19 @@
 //! Aggressively parallelized and pipelined to speed up
 //! ingestion of the huge dump dataset.
 
+use crate::dataset;
 use crate::ingest::temp_db::TempDb;
+use crate::postprocess_to_parquet::normalize_title;
+use anyhow::Context;
 use bytes::Bytes;
+use bzip2::bufread::MultiBzDecoder;
+use compact_str::CompactString;
+use foldhash::HashSet;
 use reqwest::Client;
 use sevenz_rust2::{Password, SevenZReader};
+use std::fs;
 use std::io::{BufRead, BufReader, Cursor, Read};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::time::Duration;
 use std::{io, thread};
@@ -17,7 +25,8 @@ use tokio::sync::Semaphore;
 use tokio::{select, task};
 
 mod index;
-mod parser;
+pub mod parser;
+pub mod reverts;
 pub mod temp_db;
 
 /// Maximum number of parallel HTTP requests to the dump
@@ -28,16 +37,66 @@ const DOWNLOADED_CHUNK_BUFFER_SIZE: usize = 2;
 
 const NUM_INGEST_WORKERS: usize = 32;
 
-pub async fn ingest(temp_db: TempDb) -> anyhow::Result<()> {
+#[derive(Debug, clap::Args)]
+pub struct IngestArgs {
+    /// Wiki to ingest the dump of, by database name.
+    #[clap(long, default_value = dataset::DEFAULT_WIKI)]
+    wiki: String,
+    /// Date of the dump to ingest, as `YYYYMMDD`.
+    #[clap(long, default_value = "20250301")]
+    dump_date: String,
+    /// Keep the revision text of the titles listed in this file, one
+    /// per line.
+    #[clap(long)]
+    archive_titles: Option<PathBuf>,
+    /// Keep the revision text of the articles with the most revisions
+    /// in data/temp-db, as left by an earlier ingest.
+    #[clap(long)]
+    archive_top: Option<usize>,
+    /// Ingest chunks again even if they were ingested before, e.g. to
+    /// archive revision text of articles already ingested.
+    #[clap(long)]
+    redownload: bool,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct IngestIncrementalArgs {
+    /// Adds-changes dumps (`pages-meta-hist-incr.xml.bz2`) to merge, in
+    /// the order they were published. Uncompressed `.xml` files work too.
+    #[clap(required = true)]
+    files: Vec<PathBuf>,
+    /// Wiki the dumps are of, by database name.
+    #[clap(long, default_value = dataset::DEFAULT_WIKI)]
+    wiki: String,
+    /// Keep the revision text of the titles listed in this file, one
+    /// per line.
+    #[clap(long)]
+    archive_titles: Option<PathBuf>,
+    /// Merge files again even if they were merged before.
+    #[clap(long)]
+    redownload: bool,
+}
+
+pub async fn ingest(temp_db: TempDb, args: &IngestArgs) -> anyhow::Result<()> {
+    let temp_db = temp_db.for_wiki(&args.wiki);
+    let archived = Arc::new(archived_titles(
+        &temp_db,
+        args.archive_titles.as_deref(),
+        args.archive_top,
+    )?);
+    if !archived.is_empty() {
+        tracing::info!("archiving revision text of {} articles", archived.len());
+    }
+
     let client = reqwest::Client::builder()
         .connect_timeout(Duration::from_secs(10))
         .read_timeout(Duration::from_secs(10))
         .build()?;
 
     tracing::info!("fetching chunk URLs from index");
-    let mut chunk_urls = index::get_download_urls(&client).await?;
+    let mut chunk_urls = index::get_download_urls(&client, &args.wiki, &args.dump_date).await?;
     chunk_urls.retain(|url| {
-        if temp_db.has_downloaded_url(url).unwrap() {
+        if !args.redownload && temp_db.has_downloaded_url(url).unwrap() {
             tracing::debug!("skipping already downloaded URL {url}");
             false
         } else {
@@ -65,8 +124,9 @@ pub async fn ingest(temp_db: TempDb) -> anyhow::Result<()> {
         thread_handles.push(thread::spawn({
             let chunks = chunks.clone();
             let temp_db = temp_db.clone();
+            let archived = archived.clone();
             move || {
-                if let Err(e) = run_ingest_worker(&chunks, &temp_db) {
+                if let Err(e) = run_ingest_worker(&chunks, &temp_db, &archived) {
                     tracing::error!("ingest worker failed: {e:?}");
                 }
             }
@@ -107,6 +167,99 @@ pub async fn ingest(temp_db: TempDb) -> anyhow::Result<()> {
     Ok(())
 }
 
+/// Merges the revisions of daily adds-changes dumps into the articles
+/// of an earlier full ingest. Changed articles are marked dirty, so
+/// `postprocess-to-parquet` can recompute only them.
+pub fn ingest_incremental(temp_db: &TempDb, args: &IngestIncrementalArgs) -> anyhow::Result<()> {
+    let temp_db = &temp_db.for_wiki(&args.wiki);
+    let archived = archived_titles(temp_db, args.archive_titles.as_deref(), None)?;
+    for path in &args.files {
+        // files are keyed by name, as the same dump may be downloaded
+        // to different directories
+        let key = format!(
+            "incr:{}",
+            path.file_name()
+                .context("not a file path")?
+                .to_string_lossy()
+        );
+        if !args.redownload && temp_db.has_downloaded_url(&key)? {
+            tracing::info!("skipping already merged {}", path.display());
+            continue;
+        }
+
+        tracing::info!("merging {}", path.display());
+        let file = BufReader::new(fs::File::open(path)?);
+        let mut bytes_read = 0;
+        let reader: Box<dyn BufRead> = if path.extension().is_some_and(|ext| ext == "bz2") {
+            Box::new(BufReader::new(MultiBzDecoder::new(file)))
+        } else {
+            Box::new(file)
+        };
+        let xml = quick_xml::Reader::from_reader(TrackingReader {
+            reader,
+            bytes_read: &mut bytes_read,
+        });
+
+        let mut batch = Vec::new();
+        let mut texts = Vec::new();
+        let mut merged = 0;
+        parser::parse(
+            xml,
+            |title| !archived.is_empty() && archived.contains(&normalize_title(title)),
+            |article, article_texts| {
+                // skip special articles
+                if !article.title.contains(':') {
+                    batch.push(article);
+                    texts.extend(article_texts);
+                    if batch.len() >= 4096 {
+                        temp_db.insert_revision_texts(texts.drain(..))?;
+                        merged += temp_db.merge_article_batch(batch.drain(..))?;
+                    }
+                }
+                Ok(())
+            },
+        )
+        .with_context(|| format!("failed to parse {}", path.display()))?;
+        temp_db.insert_revision_texts(texts.drain(..))?;
+        merged += temp_db.merge_article_batch(batch.drain(..))?;
+
+        temp_db.mark_url_downloaded(&key, bytes_read)?;
+        tracing::info!("merged {merged} new revisions from {}", path.display());
+    }
+    tracing::info!(
+        "{} articles awaiting postprocessing",
+        temp_db.dirty_count()?
+    );
+    Ok(())
+}
+
+/// Normalized titles of the articles whose revision text to keep.
+fn archived_titles(
+    temp_db: &TempDb,
+    archive_titles: Option<&Path>,
+    archive_top: Option<usize>,
+) -> anyhow::Result<HashSet<CompactString>> {
+    let mut titles = HashSet::default();
+    if let Some(path) = archive_titles {
+        let list = fs::read_to_string(path)?;
+        titles.extend(
+            list.lines()
+                .filter(|line| !line.trim().is_empty())
+                .map(normalize_title),
+        );
+    }
+    if let Some(n) = archive_top {
+        tracing::info!("finding the {n} articles with the most revisions");
+        titles.extend(
+            temp_db
+                .most_revised_titles(n)?
+                .iter()
+                .map(|title| normalize_title(title)),
+        );
+    }
+    Ok(titles)
+}
+
 struct DownloadedChunk {
     archive_bytes: Bytes,
     url: String,
@@ -154,6 +307,7 @@ async fn run_chunk_downloader(
 fn run_ingest_worker(
     chunks: &flume::Receiver<DownloadedChunk>,
     temp_db: &TempDb,
+    archived: &HashSet<CompactString>,
 ) -> anyhow::Result<()> {
     for chunk in chunks {
         tracing::info!("ingesting chunk {}", chunk.url);
@@ -171,26 +325,36 @@ fn run_ingest_worker(
             }));
 
             let mut batch = Vec::new();
-            parser::parse(xml, |article| {
-                // skip special articles
-                if !article.title.contains(':') {
-                    batch.push(article);
-                    if batch.len() >= 4096 {
-                        temp_db.insert_article_batch(batch.drain(..))?;
+            let mut texts = Vec::new();
+            parser::parse(
+                xml,
+                |title| !archived.is_empty() && archived.contains(&normalize_title(title)),
+                |article, article_texts| {
+                    // skip special articles
+                    if !article.title.contains(':') {
+                        batch.push(article);
+                        texts.extend(article_texts);
+                        if batch.len() >= 4096 {
+                            temp_db.insert_revision_texts(texts.drain(..))?;
+                            temp_db.insert_article_batch(batch.drain(..))?;
+                        }
                     }
-                }
-                Ok(())
-            })
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+                    Ok(())
+                },
+            )
+            .map_err(io::Error::other)?;
             if !batch.is_empty() {
+                temp_db
+                    .insert_revision_texts(texts.drain(..))
+                    .map_err(io::Error::other)?;
                 temp_db
                     .insert_article_batch(batch.drain(..))
-                    .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+                    .map_err(io::Error::other)?;
             }
 
             temp_db
                 .mark_url_downloaded(&chunk.url, bytes_read)
-                .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+                .map_err(io::Error::other)?;
             tracing::info!("ingested chunk {}", chunk.url);
             Ok(false)
         })?;
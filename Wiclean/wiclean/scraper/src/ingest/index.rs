@@ -4,17 +4,28 @@ use scraper::{Html, Selector};
 use std::sync::LazyLock;
 use url::Url;
 
-/// Index page for the chunk downloads. Date must be in the past 5 dumps.
-const INDEX_URL: &str = "https://wikimedia.bringyour.com/enwiki/20250301/";
+/// Mirror hosting the chunk downloads, with an index page per wiki
+/// and dump date. The date must be in the past 5 dumps.
+const MIRROR_URL: &str = "https://wikimedia.bringyour.com";
 
-pub async fn get_download_urls(client: &Client) -> anyhow::Result<Vec<String>> {
-    let html = client.get(INDEX_URL).send().await?.text().await?;
+pub async fn get_download_urls(
+    client: &Client,
+    wiki: &str,
+    dump_date: &str,
+) -> anyhow::Result<Vec<String>> {
+    let index_url = Url::parse(&format!("{MIRROR_URL}/{wiki}/{dump_date}/"))?;
+    let html = client
+        .get(index_url.clone())
+        .send()
+        .await?
+        .error_for_status()?
+        .text()
+        .await?;
     let urls = find_download_urls(&html);
     Ok(urls
         .into_iter()
         .map(|url| {
-            let url = Url::parse(INDEX_URL)
-                .unwrap()
+            let url = index_url
                 .join(&url)
                 .expect("failed to join URL")
                 .as_str()
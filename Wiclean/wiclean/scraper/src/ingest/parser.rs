@@ -1,12 +1,13 @@
-use crate::ingest::temp_db::{TempArticle, TempArticleRevision};
+use crate::ingest::temp_db::{Reference, TempArticle, TempArticleRevision};
 use anyhow::{Context, bail};
-use compact_str::CompactString;
+use compact_str::{CompactString, format_compact};
 use jiff::Timestamp;
 use jiff::civil::DateTime;
 use jiff::tz::TimeZone;
 use quick_xml::Reader;
 use quick_xml::events::Event;
 use regex::Regex;
+use std::collections::BTreeMap;
 use std::io::BufRead;
 use std::str::FromStr;
 use std::sync::LazyLock;
@@ -23,9 +24,13 @@ fn earliest_revision_time() -> Timestamp {
 /// documented [here](https://www.mediawiki.org/wiki/Help:Export).
 /// Since the XML documents are massive (upwards of 20 GiB each),
 /// we have to write a streaming event-based parser.
+///
+/// The callback also receives the revision texts of articles for which
+/// `keep_text` returns true, as pairs of revision ID and wikitext.
 pub fn parse<R: BufRead>(
     mut reader: Reader<R>,
-    mut article_callback: impl FnMut(TempArticle) -> anyhow::Result<()>,
+    keep_text: impl Fn(&str) -> bool,
+    mut article_callback: impl FnMut(TempArticle, Vec<(u64, CompactString)>) -> anyhow::Result<()>,
 ) -> anyhow::Result<()> {
     let mut buf = Vec::new();
 
@@ -36,6 +41,7 @@ pub fn parse<R: BufRead>(
                 // parse an article
                 let mut title: Option<CompactString> = None;
                 let mut revisions: Vec<TempArticleRevision> = Vec::new();
+                let mut texts: Vec<(u64, CompactString)> = Vec::new();
                 loop {
                     let event = reader.read_event_into(&mut buf)?;
                     match event {
@@ -47,6 +53,7 @@ pub fn parse<R: BufRead>(
                             let mut timestamp: Option<Timestamp> = None;
                             let mut text: Option<CompactString> = None;
                             let mut user_id: Option<i64> = None;
+                            let mut id: Option<u64> = None;
                             loop {
                                 let event = reader.read_event_into(&mut buf)?;
                                 match event {
@@ -56,6 +63,10 @@ pub fn parse<R: BufRead>(
                                         let timestamp_str = read_text(&mut reader, &mut buf)?;
                                         timestamp = Some(Timestamp::from_str(&timestamp_str)?);
                                     }
+                                    Event::Start(tag) if tag.name().into_inner() == b"id" => {
+                                        id =
+                                            Some(read_text(&mut reader, &mut buf)?.trim().parse()?);
+                                    }
                                     Event::Start(tag) if tag.name().into_inner() == b"text" => {
                                         text = Some(read_text(&mut reader, &mut buf)?);
                                     }
@@ -77,15 +88,8 @@ pub fn parse<R: BufRead>(
                                                 Event::Start(tag)
                                                     if tag.name().into_inner() == b"ip" =>
                                                 {
-                                                    // IP users lack a name.
-                                                    // Make fake user ID by hashing the IP address.
-                                                    let hash = blake3::hash(
-                                                        read_text(&mut reader, &mut buf)?
-                                                            .trim()
-                                                            .as_bytes(),
-                                                    );
-                                                    user_id = Some(i64::from_be_bytes(
-                                                        hash.as_bytes()[..8].try_into().unwrap(),
+                                                    user_id = Some(ip_user_id(
+                                                        read_text(&mut reader, &mut buf)?.trim(),
                                                     ));
                                                 }
                                                 Event::Start(tag)
@@ -123,11 +127,18 @@ pub fn parse<R: BufRead>(
                                         title
                                     );
                                 } else {
-                                    revisions.push(TempArticleRevision {
+                                    let text = text.unwrap_or_default();
+                                    let id = id.context("missing revision id")?;
+                                    revisions.push(TempArticleRevision::from_text(
+                                        id,
+                                        user_id.unwrap_or(0),
                                         timestamp,
-                                        links: find_links(&text.unwrap_or_default()),
-                                        user_id: user_id.unwrap_or(0),
-                                    });
+                                        &text,
+                                    ));
+                                    // the title precedes the revisions in the dump
+                                    if title.as_deref().is_some_and(&keep_text) {
+                                        texts.push((id, text));
+                                    }
                                 }
                             }
                         }
@@ -140,10 +151,13 @@ pub fn parse<R: BufRead>(
                     buf.clear();
                 }
                 if !revisions.is_empty() {
-                    article_callback(TempArticle {
-                        title: title.context("missing article title")?,
-                        revisions,
-                    })?;
+                    article_callback(
+                        TempArticle {
+                            title: title.context("missing article title")?,
+                            revisions,
+                        },
+                        texts,
+                    )?;
                 }
             }
             Event::Eof => break,
@@ -174,9 +188,16 @@ fn read_text<R: BufRead>(
     Ok(all_text)
 }
 
+/// IP users lack a name.
+/// Make fake user ID by hashing the IP address.
+pub fn ip_user_id(ip: &str) -> i64 {
+    let hash = blake3::hash(ip.as_bytes());
+    i64::from_be_bytes(hash.as_bytes()[..8].try_into().unwrap())
+}
+
 /// Primitive regex-based solution. Does not
 /// correctly handle escapes.
-fn find_links(wikitext: &str) -> Vec<CompactString> {
+pub fn find_links(wikitext: &str) -> Vec<CompactString> {
     static REGEX: LazyLock<Regex> =
         LazyLock::new(|| Regex::new(r#"\[\[([^\]|]+)(?:\|[^\]]+)?\]\]"#).unwrap());
     let mut links = Vec::new();
@@ -189,6 +210,193 @@ fn find_links(wikitext: &str) -> Vec<CompactString> {
     links
 }
 
+/// Namespace names of category links, lowercased: the canonical one,
+/// which works on every wiki, and those of the other wikis we ingest.
+const CATEGORY_NAMESPACES: [&str; 4] = ["category", "kategorie", "catégorie", "categoría"];
+
+/// The category a link as returned by [`find_links`] puts its article
+/// in, e.g. `Living people` for `Category:Living_people`. Links to a
+/// category page, written with a leading colon, do not categorize.
+pub fn category_name(link: &str) -> Option<CompactString> {
+    let (namespace, name) = link.split_once(':')?;
+    if !CATEGORY_NAMESPACES.contains(&namespace.trim().to_lowercase().as_str()) {
+        return None;
+    }
+    let name = name.replace('_', " ");
+    let mut chars = name.trim().chars();
+    let first = chars.next()?;
+    Some(format_compact!(
+        "{}{}",
+        first.to_uppercase(),
+        chars.as_str()
+    ))
+}
+
+/// Start of an infobox up to its first parameter, capturing its type.
+/// Also matches the `Ficha de` infoboxes of eswiki.
+static INFOBOX_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?i)\{\{\s*(?:infobox|ficha de)[ _]+([^|}<\n]+)").unwrap());
+
+/// Type of the first infobox of an article, e.g. `settlement` for
+/// `{{Infobox settlement`, lowercased with underscores as spaces.
+pub fn find_infobox(wikitext: &str) -> Option<CompactString> {
+    let name = INFOBOX_REGEX
+        .captures(wikitext)?
+        .get(1)?
+        .as_str()
+        .replace('_', " ");
+    let name = collapse_whitespace(&name);
+    (!name.is_empty()).then(|| name.to_lowercase().into())
+}
+
+/// Named parameters of the first infobox of an article, sorted by
+/// name, with comments removed and whitespace collapsed. Parameters
+/// left empty are omitted, and of repeated ones the last wins, as in
+/// MediaWiki. Like [`find_links`], does not handle escapes.
+pub fn find_infobox_fields(wikitext: &str) -> Vec<(CompactString, CompactString)> {
+    static COMMENT_REGEX: LazyLock<Regex> =
+        LazyLock::new(|| Regex::new(r"(?s)<!--.*?-->").unwrap());
+    let Some(start) = INFOBOX_REGEX.find(wikitext) else {
+        return Vec::new();
+    };
+    let body = &wikitext[start.end()..];
+
+    // split on the pipes of the infobox itself, not those of nested
+    // templates and links
+    let bytes = body.as_bytes();
+    let mut params = Vec::new();
+    let mut param_start = None;
+    let mut depth = 0usize;
+    let mut i = 0;
+    while i < bytes.len() {
+        match &bytes[i..] {
+            [b'{', b'{', ..] | [b'[', b'[', ..] => {
+                depth += 1;
+                i += 2;
+                continue;
+            }
+            [b'}', b'}', ..] | [b']', b']', ..] if depth > 0 => {
+                depth -= 1;
+                i += 2;
+                continue;
+            }
+            [b'}', b'}', ..] => break,
+            [b'|', ..] if depth == 0 => {
+                if let Some(param_start) = param_start {
+                    params.push(&body[param_start..i]);
+                }
+                param_start = Some(i + 1);
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    if let Some(param_start) = param_start {
+        params.push(&body[param_start..i]);
+    }
+
+    let mut fields = BTreeMap::new();
+    for param in params {
+        let Some((name, value)) = param.split_once('=') else {
+            continue;
+        };
+        let name = name.trim();
+        let value = collapse_whitespace(&COMMENT_REGEX.replace_all(value, ""));
+        if !name.is_empty() && !value.is_empty() {
+            fields.insert(CompactString::from(name), CompactString::from(value));
+        }
+    }
+    fields.into_iter().collect()
+}
+
+/// Target of a redirect page, e.g. `Macy's` for `#REDIRECT [[Macy's]]`,
+/// including the redirect keywords of the other wikis we ingest.
+pub fn redirect_target(wikitext: &str) -> Option<CompactString> {
+    static REGEX: LazyLock<Regex> = LazyLock::new(|| {
+        Regex::new(
+            r"(?i)^\s*#(?:redirect|weiterleitung|redirection|redirección)\s*:?\s*\[\[([^\]|]+)",
+        )
+        .unwrap()
+    });
+    let target = REGEX.captures(wikitext)?.get(1)?.as_str().trim();
+    (!target.is_empty()).then(|| target.into())
+}
+
+static URL_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"(?i)\bhttps?://[^\s\[\]<>|{}"]+"#).unwrap());
+
+/// Sources cited by an article: its `<ref>` tags and the external
+/// links outside of them. Reuses of a named ref, `<ref name="a" />`,
+/// cite nothing new. Like [`find_links`], does not handle escapes.
+pub fn find_references(wikitext: &str) -> Vec<Reference> {
+    static OPEN_REGEX: LazyLock<Regex> =
+        LazyLock::new(|| Regex::new(r"(?i)<ref(?:\s[^>]*)?>").unwrap());
+    static CLOSE_REGEX: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?i)</ref\s*>").unwrap());
+    let mut references = Vec::new();
+    let mut external_links = |text: &str| {
+        for url in URL_REGEX.find_iter(text) {
+            references.push(Reference::ExternalLink(trim_url(url.as_str())));
+        }
+    };
+    let mut refs = Vec::new();
+    let mut pos = 0;
+    while let Some(open) = OPEN_REGEX.find_at(wikitext, pos) {
+        if open.as_str().ends_with("/>") {
+            external_links(&wikitext[pos..open.end()]);
+            pos = open.end();
+            continue;
+        }
+        let Some(close) = CLOSE_REGEX.find_at(wikitext, open.end()) else {
+            break;
+        };
+        external_links(&wikitext[pos..open.start()]);
+        refs.extend(ref_identity(&wikitext[open.end()..close.start()]).map(Reference::Ref));
+        pos = close.end();
+    }
+    external_links(&wikitext[pos..]);
+    references.extend(refs);
+    references.sort_unstable();
+    references.dedup();
+    references
+}
+
+/// Identifies a citation by the first URL in it, or else by its cite
+/// template and the most specific of its `doi`, `isbn` and `title`.
+/// Citations with neither are identified by their text.
+fn ref_identity(content: &str) -> Option<CompactString> {
+    static CITE_REGEX: LazyLock<Regex> =
+        LazyLock::new(|| Regex::new(r"(?i)\{\{\s*(cite[ _][^|}]+|citation)").unwrap());
+    static PARAM_REGEX: LazyLock<Regex> =
+        LazyLock::new(|| Regex::new(r"(?i)\|\s*(doi|isbn|title)\s*=\s*([^|}]+)").unwrap());
+    if let Some(url) = URL_REGEX.find(content) {
+        return Some(trim_url(url.as_str()));
+    }
+    if let Some(cite) = CITE_REGEX.captures(content) {
+        let template = collapse_whitespace(&cite[1].replace('_', " ")).to_lowercase();
+        let param = PARAM_REGEX
+            .captures_iter(content)
+            .map(|param| (param[1].to_lowercase(), collapse_whitespace(&param[2])))
+            .filter(|(_, value)| !value.is_empty())
+            .min_by_key(|(name, _)| ["doi", "isbn", "title"].iter().position(|n| n == name));
+        return Some(match param {
+            Some((name, value)) => format_compact!("{template}|{name}={value}"),
+            None => template.into(),
+        });
+    }
+    let text = collapse_whitespace(content);
+    (!text.is_empty()).then(|| text.chars().take(200).collect())
+}
+
+/// Drops punctuation following a URL in running text.
+fn trim_url(url: &str) -> CompactString {
+    url.trim_end_matches(['.', ',', ';', ':', '!', '?', ')', '\''])
+        .into()
+}
+
+fn collapse_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -208,14 +416,105 @@ mod tests {
         assert_eq!(links, expected);
     }
 
+    #[test]
+    fn test_category_name() {
+        let text = "[[Category:Department stores|Macy's]] [[category: living_people]] \
+                    [[:Category:Retail]] [[Kategorie:Warenhaus]] [[New York City]]";
+        let categories: Vec<CompactString> = find_links(text)
+            .iter()
+            .filter_map(|link| category_name(link))
+            .collect();
+        assert_eq!(
+            categories,
+            ["Department stores", "Warenhaus", "Living people"]
+        );
+    }
+
+    #[test]
+    fn test_find_infobox() {
+        let text = "{{Short description|Department store}}\n{{Infobox_Company\n| name = Macy's\n}}\n\
+                    {{Infobox person}}";
+        assert_eq!(find_infobox(text).as_deref(), Some("company"));
+        assert_eq!(
+            find_infobox("{{ infobox  settlement | name = Berlin}}").as_deref(),
+            Some("settlement")
+        );
+        assert_eq!(
+            find_infobox("{{Ficha de localidad de España}}").as_deref(),
+            Some("localidad de españa")
+        );
+        assert_eq!(find_infobox("{{Infobox\n| name = Macy's}}"), None);
+        assert_eq!(find_infobox("[[Infobox settlement]]"), None);
+    }
+
+    #[test]
+    fn test_find_infobox_fields() {
+        let text = "'''Berlin''' {{Infobox settlement\n\
+                    | name = Berlin <!-- capital | city -->\n\
+                    | leader_name = [[Kai Wegner|Wegner]]\n\
+                    | population_total = {{formatnum:3,878,100}}\n\
+                    | image_skyline =\n\
+                    | Stadtstaat\n\
+                    | name = Berlin, Germany\n\
+                    }} {{Infobox other | name = other}}";
+        assert_eq!(
+            find_infobox_fields(text),
+            [
+                ("leader_name".into(), "[[Kai Wegner|Wegner]]".into()),
+                ("name".into(), "Berlin, Germany".into()),
+                ("population_total".into(), "{{formatnum:3,878,100}}".into()),
+            ]
+        );
+        assert!(find_infobox_fields("{{Short description|Berlin}}").is_empty());
+    }
+
+    #[test]
+    fn test_find_references() {
+        let text = "Macy's moved to Herald Square in 1902.<ref name=\"move\">{{cite web \
+                    |url=https://www.nytimes.com/1902/macys.html |title=Macy's moves}}</ref> \
+                    It holds a parade.<ref>{{Cite book|title=The Parade|isbn=978-0-06-000000-0}}</ref>\
+                    <ref name=\"move\" /> Its flagship is large.<REF>Store guide, p. 3</REF>\n\
+                    == External links ==\n* [https://www.macys.com/ Official website].";
+        assert_eq!(
+            find_references(text),
+            [
+                Reference::Ref("Store guide, p. 3".into()),
+                Reference::Ref("cite book|isbn=978-0-06-000000-0".into()),
+                Reference::Ref("https://www.nytimes.com/1902/macys.html".into()),
+                Reference::ExternalLink("https://www.macys.com/".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_redirect_target() {
+        assert_eq!(
+            redirect_target("#REDIRECT [[Macy's|Macy’s]]\n{{R from move}}").as_deref(),
+            Some("Macy's")
+        );
+        assert_eq!(
+            redirect_target("#WEITERLEITUNG [[Berlin]]").as_deref(),
+            Some("Berlin")
+        );
+        assert_eq!(
+            redirect_target("Macy's is a store. #REDIRECT [[Detroit]]"),
+            None
+        );
+    }
+
     #[test]
     fn test_xml_data() {
         let data = include_str!("../../test_xml_data.xml");
         let mut articles = Vec::new();
-        parse(Reader::from_str(data), |a| {
-            articles.push(a);
-            Ok(())
-        })
+        parse(
+            Reader::from_str(data),
+            |_| false,
+            |a, texts| {
+                assert!(texts.is_empty());
+                articles.push(a);
+                Ok(())
+            },
+        )
         .unwrap();
     }
 }
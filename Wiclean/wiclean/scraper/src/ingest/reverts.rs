@@ -0,0 +1,39 @@
+//! Reverts found by comparing text hashes, as MediaWiki's manual
+//! revert detection does.
+
+use crate::ingest::temp_db::TempArticleRevision;
+
+/// How many earlier revisions an identical text counts as a revert
+/// of.
+const REVERT_WINDOW: usize = 15;
+
+/// The revision within the revert window whose text revision `i`
+/// restored. Revisions identical to their parent (null edits) are no
+/// reverts.
+pub fn find_reverted(revisions: &[&TempArticleRevision], i: usize) -> Option<u64> {
+    let hash = revisions[i].text_hash;
+    if hash == 0 || i == 0 || revisions[i - 1].text_hash == hash {
+        return None;
+    }
+    revisions[i.saturating_sub(REVERT_WINDOW)..i - 1]
+        .iter()
+        .rev()
+        .find(|revision| revision.text_hash == hash)
+        .map(|revision| revision.id)
+}
+
+/// The first revision within the revert window after revision `last`
+/// that restores the text of revision `restored`, undoing everything
+/// in between. Revisions must be sorted oldest first.
+pub fn find_restore(
+    revisions: &[TempArticleRevision],
+    restored: usize,
+    last: usize,
+) -> Option<usize> {
+    let hash = revisions[restored].text_hash;
+    if hash == 0 {
+        return None;
+    }
+    (last + 1..revisions.len().min(last + 1 + REVERT_WINDOW))
+        .find(|&i| revisions[i].text_hash == hash)
+}
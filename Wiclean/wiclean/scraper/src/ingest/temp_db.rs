@@ -1,86 +1,684 @@
+use crate::dataset::DEFAULT_WIKI;
+use crate::ingest::parser;
 use anyhow::Context;
 use bincode::Options;
 use compact_str::CompactString;
 use flume::{Receiver, Sender};
+use foldhash::HashSet;
 use jiff::Timestamp;
-use redb::{Database, ReadableTable, TableDefinition};
+use redb::{Database, ReadableTable, ReadableTableMetadata, TableDefinition, TableError};
 use serde::{Deserialize, Serialize};
-use std::{cell::RefCell, fs, mem, sync::Arc};
+use std::collections::BinaryHeap;
+use std::io::Read;
+use std::ops::Bound;
+use std::path::Path;
+use std::sync::Mutex;
+use std::{cell::RefCell, cmp::Reverse, fs, sync::Arc};
 
-pub const ARTICLES_TABLE: TableDefinition<&[u8], &[u8]> = TableDefinition::new("articles");
+/// Articles keyed by `wiki:title`, see [`TempDb::key`].
+pub const ARTICLES_TABLE: TableDefinition<&[u8], &[u8]> = TableDefinition::new("wiki_articles");
+/// Articles keyed by bare enwiki titles, before format version 2.
+const LEGACY_ARTICLES_TABLE: TableDefinition<&[u8], &[u8]> = TableDefinition::new("articles");
 const METADATA_TABLE: TableDefinition<&str, u64> = TableDefinition::new("metadata");
 const DOWNLOADED_URLS_TABLE: TableDefinition<&str, ()> = TableDefinition::new("downloaded_urls");
+/// Revision wikitext of archived articles by wiki and revision ID.
+/// Values are a `TEXT_*` tag byte followed by the zstd-compressed text.
+const REVISION_TEXTS_TABLE: TableDefinition<(&str, u64), &[u8]> =
+    TableDefinition::new("wiki_revision_texts");
+const BLOBS_TABLE: TableDefinition<&str, &[u8]> = TableDefinition::new("blobs");
+/// Keys of articles changed by incremental ingestion since the last
+/// postprocess run.
+const DIRTY_TABLE: TableDefinition<&[u8], ()> = TableDefinition::new("dirty_articles");
+const TEXT_DICTIONARY_KEY: &str = "revision_text_dictionary";
+/// Blob holding the last article key re-encoded by an unfinished
+/// migration.
+const MIGRATION_PROGRESS_KEY: &str = "migration_progress";
+/// Entries moved or re-encoded per transaction while migrating.
+const MIGRATION_CHUNK: usize = 4096;
+/// Keys compared by a case-insensitive title lookup before giving up.
+const CASE_INSENSITIVE_SCAN_LIMIT: usize = 10_000;
+
+const TEXT_PLAIN: u8 = 0;
+const TEXT_DICTIONARY: u8 = 1;
+/// Texts needed before a dictionary is trained. Until then, texts are
+/// compressed without one.
+const DICTIONARY_MIN_SAMPLES: usize = 256;
+const DICTIONARY_SIZE: usize = 112 * 1024;
+const DICTIONARY_SAMPLE_BYTES: usize = 64 * 1024;
+
+/// Version of the layout of the database. Version 1 predates the
+/// `format_version` metadata entry; it keyed articles by bare enwiki
+/// titles and kept only the links of revisions. It is upgraded by
+/// [`TempDb::migrate`].
+const FORMAT_VERSION: u64 = 2;
 
 /// Temporary database written to during ingestion, which
 /// does not contain resolved IDs and links.
+///
+/// Articles of several wikis live side by side. A handle reads and
+/// writes the articles of one wiki, enwiki unless switched with
+/// [`TempDb::for_wiki`].
 #[derive(Clone)]
 pub struct TempDb {
     db: Arc<Database>,
+    wiki: CompactString,
+    /// Whether articles were migrated from format version 1 and lack
+    /// revision IDs.
+    ids_missing: bool,
+    text_dictionary: Arc<Mutex<Option<Arc<[u8]>>>>,
     _dropper: Arc<Dropper>,
 }
 
 impl TempDb {
     pub fn open() -> anyhow::Result<(Self, Receiver<()>)> {
         fs::create_dir_all("data").ok();
-        let db = Arc::new(
-            Database::builder()
-                .set_cache_size(1024 * 1024 * 1024)
-                .set_repair_callback(|s| {
-                    tracing::warn!("repair progress: {:.2}", s.progress());
-                })
-                .create("data/temp-db")?,
-        );
+        Self::open_at("data/temp-db")
+    }
+
+    /// Opens or creates a database at `path` instead of data/temp-db.
+    /// Databases of an older format version must be migrated first, see
+    /// [`TempDb::migrate_at`].
+    pub fn open_at(path: impl AsRef<Path>) -> anyhow::Result<(Self, Receiver<()>)> {
+        let db = Arc::new(Self::builder().create(path)?);
+        let ids_missing = Self::check_format_version(&db)?;
+        let text_dictionary = Self::read_blob(&db, TEXT_DICTIONARY_KEY)?.map(Arc::from);
         let (on_shutdown, on_shutown_rx) = flume::bounded(1);
         Ok((
             Self {
                 db,
+                wiki: DEFAULT_WIKI.into(),
+                ids_missing,
+                text_dictionary: Arc::new(Mutex::new(text_dictionary)),
                 _dropper: Arc::new(Dropper(on_shutdown)),
             },
             on_shutown_rx,
         ))
     }
 
+    fn builder() -> redb::Builder {
+        let mut builder = Database::builder();
+        builder
+            .set_cache_size(1024 * 1024 * 1024)
+            .set_repair_callback(|s| {
+                tracing::warn!("repair progress: {:.2}", s.progress());
+            });
+        builder
+    }
+
+    /// Reads the format version, stamping new databases with the
+    /// current one.
+    fn format_version(db: &Database) -> anyhow::Result<u64> {
+        let tx = db.begin_write()?;
+        let version = tx
+            .open_table(METADATA_TABLE)?
+            .get("format_version")?
+            .map(|val| val.value());
+        let version = match version {
+            Some(version) => version,
+            None if tx.open_table(LEGACY_ARTICLES_TABLE)?.len()? > 0 => 1,
+            None => {
+                tx.delete_table(LEGACY_ARTICLES_TABLE)?;
+                tx.open_table(METADATA_TABLE)?
+                    .insert("format_version", FORMAT_VERSION)?;
+                FORMAT_VERSION
+            }
+        };
+        tx.commit()?;
+        anyhow::ensure!(
+            version <= FORMAT_VERSION,
+            "data/temp-db has format version {version}, newer than this build supports"
+        );
+        Ok(version)
+    }
+
+    /// Ensures the database has the current format version. Returns
+    /// whether revision IDs are missing.
+    fn check_format_version(db: &Database) -> anyhow::Result<bool> {
+        let version = Self::format_version(db)?;
+        anyhow::ensure!(
+            version == FORMAT_VERSION,
+            "data/temp-db has format version {version}, run the migrate subcommand to upgrade it to {FORMAT_VERSION}"
+        );
+        let ids_missing = db
+            .begin_read()?
+            .open_table(METADATA_TABLE)?
+            .get("revision_ids_missing")?
+            .is_some();
+        if ids_missing {
+            tracing::warn!(
+                "data/temp-db was ingested before format version 2; revision IDs and text hashes are unavailable"
+            );
+        }
+        Ok(ids_missing)
+    }
+
+    /// Migrates data/temp-db to the current format version.
+    pub fn migrate() -> anyhow::Result<()> {
+        Self::migrate_at("data/temp-db")
+    }
+
+    /// Migrates the database at `path` to the current format version.
+    /// Version 1 only held enwiki, so its articles move to
+    /// wiki-qualified keys before they are re-encoded. Work is committed
+    /// in chunks, so an interrupted migration continues where it stopped
+    /// when run again.
+    pub fn migrate_at(path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let db = Self::builder().open(path)?;
+        let version = Self::format_version(&db)?;
+        if version == FORMAT_VERSION {
+            tracing::info!("data/temp-db already has format version {FORMAT_VERSION}");
+            return Ok(());
+        }
+        tracing::info!("migrating data/temp-db from format version {version} to {FORMAT_VERSION}");
+        {
+            // format version 1 is only implied by the legacy table, which
+            // empties as articles move
+            let tx = db.begin_write()?;
+            let mut metadata = tx.open_table(METADATA_TABLE)?;
+            metadata.insert("format_version", version)?;
+            if version == 1 {
+                metadata.insert("revision_ids_missing", 1)?;
+            }
+            drop(metadata);
+            tx.commit()?;
+        }
+        Self::migrate_wiki_keys(&db)?;
+        Self::reencode_articles(&db, version)?;
+
+        let tx = db.begin_write()?;
+        tx.open_table(METADATA_TABLE)?
+            .insert("format_version", FORMAT_VERSION)?;
+        tx.open_table(BLOBS_TABLE)?.remove(MIGRATION_PROGRESS_KEY)?;
+        tx.commit()?;
+        tracing::warn!(
+            "revision data added since format version {version} is missing from existing revisions, re-ingest to fill it in"
+        );
+        Ok(())
+    }
+
+    /// Moves articles to wiki-qualified keys, a chunk per transaction.
+    /// Dropping the emptied legacy table marks this step done.
+    fn migrate_wiki_keys(db: &Database) -> anyhow::Result<()> {
+        match db.begin_read()?.open_table(LEGACY_ARTICLES_TABLE) {
+            Ok(_) => {}
+            Err(TableError::TableDoesNotExist(_)) => return Ok(()),
+            Err(e) => return Err(e.into()),
+        }
+        loop {
+            let tx = db.begin_write()?;
+            let mut legacy = tx.open_table(LEGACY_ARTICLES_TABLE)?;
+            let chunk = legacy
+                .iter()?
+                .take(MIGRATION_CHUNK)
+                .map(|entry| {
+                    let (title, data) = entry?;
+                    Ok((title.value().to_vec(), data.value().to_vec()))
+                })
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            if chunk.is_empty() {
+                break;
+            }
+            let mut articles = tx.open_table(ARTICLES_TABLE)?;
+            for (title, data) in chunk {
+                legacy.remove(title.as_slice())?;
+                let key = Self::key_of(DEFAULT_WIKI, &String::from_utf8_lossy(&title));
+                articles.insert(key.as_bytes(), data.as_slice())?;
+            }
+            drop((legacy, articles));
+            tx.commit()?;
+        }
+        let tx = db.begin_write()?;
+        tx.delete_table(LEGACY_ARTICLES_TABLE)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Re-encodes every article from the encoding of `version`, a chunk
+    /// per transaction. The last key re-encoded is kept in the blobs
+    /// table to resume from.
+    fn reencode_articles(db: &Database, version: u64) -> anyhow::Result<()> {
+        let mut buf = Vec::new();
+        let mut last_key = Self::read_blob(db, MIGRATION_PROGRESS_KEY)?;
+        let mut reencoded = 0;
+        loop {
+            let tx = db.begin_write()?;
+            let mut articles = tx.open_table(ARTICLES_TABLE)?;
+            let lower = match &last_key {
+                Some(key) => Bound::Excluded(key.as_slice()),
+                None => Bound::Unbounded,
+            };
+            let chunk = articles
+                .range::<&[u8]>((lower, Bound::Unbounded))?
+                .take(MIGRATION_CHUNK)
+                .map(|entry| {
+                    let (key, data) = entry?;
+                    Ok((key.value().to_vec(), data.value().to_vec()))
+                })
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            let Some((key, _)) = chunk.last() else {
+                break;
+            };
+            last_key = Some(key.clone());
+            reencoded += chunk.len();
+            for (key, data) in chunk {
+                let article = decode_legacy_article(version, &data)?;
+                articles.insert(
+                    key.as_slice(),
+                    encode_article(&article, &mut buf)?.as_slice(),
+                )?;
+            }
+            drop(articles);
+            tx.open_table(BLOBS_TABLE)?
+                .insert(MIGRATION_PROGRESS_KEY, last_key.as_deref().unwrap())?;
+            tx.commit()?;
+            tracing::info!("re-encoded {reencoded} articles");
+        }
+        Ok(())
+    }
+
+    /// A handle on the articles of `wiki`, by database name such as
+    /// `dewiki`, sharing this database.
+    pub fn for_wiki(&self, wiki: &str) -> Self {
+        Self {
+            wiki: wiki.into(),
+            ..self.clone()
+        }
+    }
+
+    /// Key of an article of this handle's wiki.
+    fn key(&self, title: &str) -> CompactString {
+        Self::key_of(&self.wiki, title)
+    }
+
+    /// Keys are `wiki:title`. Database names never contain a colon, so
+    /// the first colon separates the two.
+    fn key_of(wiki: &str, title: &str) -> CompactString {
+        compact_str::format_compact!("{wiki}:{title}")
+    }
+
+    /// Splits a key into wiki and title.
+    pub fn split_key(key: &[u8]) -> (CompactString, CompactString) {
+        let key = String::from_utf8_lossy(key);
+        let (wiki, title) = key.split_once(':').unwrap_or((DEFAULT_WIKI, &key));
+        (wiki.into(), title.into())
+    }
+
+    /// Range of the keys of a wiki's articles.
+    pub fn key_range(wiki: &str) -> (Vec<u8>, Vec<u8>) {
+        // ';' follows ':' in ASCII
+        (
+            format!("{wiki}:").into_bytes(),
+            format!("{wiki};").into_bytes(),
+        )
+    }
+
+    /// The wikis with articles in the database.
+    pub fn wikis(&self) -> anyhow::Result<Vec<CompactString>> {
+        let tx = self.db.begin_read()?;
+        let articles_table = match tx.open_table(ARTICLES_TABLE) {
+            Ok(table) => table,
+            Err(TableError::TableDoesNotExist(_)) => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        // skip from one wiki's keys to the next instead of scanning all
+        let mut wikis = Vec::new();
+        let mut from = Vec::new();
+        while let Some(entry) = articles_table.range(from.as_slice()..)?.next() {
+            let (key, _) = entry?;
+            let (wiki, _) = Self::split_key(key.value());
+            from = Self::key_range(&wiki).1;
+            wikis.push(wiki);
+        }
+        Ok(wikis)
+    }
+
     pub fn insert_article_batch(
         &self,
         articles: impl IntoIterator<Item = TempArticle>,
     ) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            !self.ids_missing,
+            "cannot ingest into data/temp-db without revision IDs, move it away to start afresh"
+        );
         let mut kv_pairs = Vec::new();
         let mut uncompressed_buf = Vec::new();
-        let mut compressed_buf = Vec::new();
         for article in articles {
-            bincode::options().serialize_into(&mut uncompressed_buf, &article)?;
+            let data = encode_article(&article, &mut uncompressed_buf)?;
+            kv_pairs.push((self.key(&article.title), data));
+        }
 
-            thread_local! {
-                static COMPRESSOR: RefCell<zstd::bulk::Compressor<'static>> = RefCell::new(zstd::bulk::Compressor::new(3).unwrap());
-            }
-            compressed_buf.reserve(uncompressed_buf.len() * 2);
-            COMPRESSOR.with(|cell| {
-                cell.borrow_mut()
-                    .compress_to_buffer(&uncompressed_buf, &mut compressed_buf)
-                    .unwrap();
-            });
+        let mut tx = self.db.begin_write()?;
+        tx.set_durability(redb::Durability::Eventual);
 
-            kv_pairs.push((article.title.clone(), mem::take(&mut compressed_buf)));
+        let mut articles_table = tx.open_table(ARTICLES_TABLE)?;
 
-            uncompressed_buf.clear();
-            compressed_buf.clear();
+        for (key, data) in kv_pairs {
+            articles_table
+                .insert(key.as_bytes(), data.as_slice())
+                .with_context(|| format!("failed to insert article {}", key))?;
         }
+        drop(articles_table);
+        tx.commit()?;
+        Ok(())
+    }
 
+    /// Merges revisions from an incremental dump into the stored
+    /// articles, matching revisions by ID, and marks the articles that
+    /// gained revisions dirty. Returns the number of new revisions.
+    pub fn merge_article_batch(
+        &self,
+        articles: impl IntoIterator<Item = TempArticle>,
+    ) -> anyhow::Result<usize> {
+        anyhow::ensure!(
+            !self.ids_missing,
+            "cannot merge into data/temp-db without revision IDs, move it away to start afresh"
+        );
         let mut tx = self.db.begin_write()?;
         tx.set_durability(redb::Durability::Eventual);
-
         let mut articles_table = tx.open_table(ARTICLES_TABLE)?;
+        let mut dirty_table = tx.open_table(DIRTY_TABLE)?;
 
-        for (title, data) in kv_pairs {
+        let mut uncompressed_buf = Vec::new();
+        let mut merged = 0;
+        for article in articles {
+            let key = self.key(&article.title);
+            let existing = articles_table
+                .get(key.as_bytes())?
+                .map(|data| self.decode_article(data.value()))
+                .transpose()
+                .with_context(|| format!("failed to decode article {}", article.title))?;
+            let article = match existing {
+                Some(mut existing) => {
+                    let added = existing.merge(article);
+                    if added == 0 {
+                        continue;
+                    }
+                    merged += added;
+                    existing
+                }
+                None => {
+                    merged += article.revisions.len();
+                    article
+                }
+            };
+            let data = encode_article(&article, &mut uncompressed_buf)?;
             articles_table
-                .insert(title.as_bytes(), data.as_slice())
-                .with_context(|| format!("failed to insert article {}", title))?;
+                .insert(key.as_bytes(), data.as_slice())
+                .with_context(|| format!("failed to insert article {key}"))?;
+            dirty_table.insert(key.as_bytes(), ())?;
         }
         drop(articles_table);
+        drop(dirty_table);
+        tx.commit()?;
+        Ok(merged)
+    }
+
+    pub fn dirty_count(&self) -> anyhow::Result<u64> {
+        let tx = self.db.begin_read()?;
+        match tx.open_table(DIRTY_TABLE) {
+            Ok(table) => Ok(table.len()?),
+            Err(TableError::TableDoesNotExist(_)) => Ok(0),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Wikis and titles of the articles changed since they were last
+    /// postprocessed, of all wikis.
+    pub fn dirty_titles(&self) -> anyhow::Result<Vec<(CompactString, CompactString)>> {
+        let tx = self.db.begin_read()?;
+        let table = match tx.open_table(DIRTY_TABLE) {
+            Ok(table) => table,
+            Err(TableError::TableDoesNotExist(_)) => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut titles = Vec::new();
+        for entry in table.iter()? {
+            let (key, _) = entry?;
+            titles.push(Self::split_key(key.value()));
+        }
+        Ok(titles)
+    }
+
+    /// Unmarks articles as dirty by wiki and title, or all of them if
+    /// `titles` is `None`.
+    pub fn clear_dirty(
+        &self,
+        titles: Option<&[(CompactString, CompactString)]>,
+    ) -> anyhow::Result<()> {
+        let tx = self.db.begin_write()?;
+        match titles {
+            Some(titles) => {
+                let mut table = tx.open_table(DIRTY_TABLE)?;
+                for (wiki, title) in titles {
+                    table.remove(Self::key_of(wiki, title).as_bytes())?;
+                }
+            }
+            None => {
+                tx.delete_table(DIRTY_TABLE)?;
+            }
+        }
         tx.commit()?;
         Ok(())
     }
 
+    /// Looks up an article by title. Titles that do not match exactly
+    /// are retried with a capitalized first letter, as Wikipedia
+    /// capitalizes titles, and then case-insensitively among the first
+    /// [`CASE_INSENSITIVE_SCAN_LIMIT`] titles sharing the first letter.
+    pub fn get_article(&self, title: &str) -> anyhow::Result<Option<TempArticle>> {
+        let tx = self.db.begin_read()?;
+        let articles_table = match tx.open_table(ARTICLES_TABLE) {
+            Ok(table) => table,
+            Err(TableError::TableDoesNotExist(_)) => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+        let title = title.trim();
+        let mut chars = title.chars();
+        let Some(first) = chars.next() else {
+            return Ok(None);
+        };
+        let capitalized = format!("{}{}", first.to_uppercase(), chars.as_str());
+        for candidate in [title, capitalized.as_str()] {
+            if let Some(data) = articles_table.get(self.key(candidate).as_bytes())? {
+                return self.decode_article(data.value()).map(Some);
+            }
+        }
+
+        // only keys starting with either case of the first letter can
+        // match, but on a large wiki that is still a big share of it
+        let lowercase = title.to_lowercase();
+        let mut prefixes = vec![
+            self.key(&first.to_uppercase().to_string()),
+            self.key(&first.to_lowercase().to_string()),
+        ];
+        prefixes.dedup();
+        let mut budget = CASE_INSENSITIVE_SCAN_LIMIT;
+        for prefix in prefixes {
+            for entry in articles_table.range(prefix.as_bytes()..)? {
+                let (key, data) = entry?;
+                if !key.value().starts_with(prefix.as_bytes()) {
+                    break;
+                }
+                if budget == 0 {
+                    tracing::debug!("gave up matching {title:?} case-insensitively");
+                    return Ok(None);
+                }
+                budget -= 1;
+                if Self::split_key(key.value()).1.to_lowercase() == lowercase {
+                    return self.decode_article(data.value()).map(Some);
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Titles starting with `prefix`, either as typed or with a
+    /// capitalized first letter, in key order.
+    pub fn search_titles(&self, prefix: &str, limit: usize) -> anyhow::Result<Vec<CompactString>> {
+        let tx = self.db.begin_read()?;
+        let articles_table = match tx.open_table(ARTICLES_TABLE) {
+            Ok(table) => table,
+            Err(TableError::TableDoesNotExist(_)) => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut chars = prefix.chars();
+        let mut prefixes = vec![self.key(prefix)];
+        if let Some(first) = chars.next() {
+            prefixes.push(self.key(&format!("{}{}", first.to_uppercase(), chars.as_str())));
+        }
+        prefixes.dedup();
+
+        let mut titles = Vec::new();
+        for prefix in prefixes {
+            for entry in articles_table.range(prefix.as_bytes()..)? {
+                let (key, _) = entry?;
+                if !key.value().starts_with(prefix.as_bytes()) || titles.len() >= limit {
+                    break;
+                }
+                titles.push(Self::split_key(key.value()).1);
+            }
+        }
+        titles.sort_unstable();
+        Ok(titles)
+    }
+
+    fn read_blob(db: &Database, key: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        let tx = db.begin_read()?;
+        let table = match tx.open_table(BLOBS_TABLE) {
+            Ok(table) => table,
+            Err(TableError::TableDoesNotExist(_)) => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(table.get(key)?.map(|value| value.value().to_vec()))
+    }
+
+    /// Stores the wikitext of revisions of this wiki, keyed by revision
+    /// ID.
+    pub fn insert_revision_texts(
+        &self,
+        texts: impl IntoIterator<Item = (u64, CompactString)>,
+    ) -> anyhow::Result<()> {
+        let texts: Vec<(u64, CompactString)> = texts.into_iter().collect();
+        if texts.is_empty() {
+            return Ok(());
+        }
+        let dictionary = self.text_dictionary_for(&texts)?;
+        let mut compressor = match &dictionary {
+            Some(dictionary) => zstd::bulk::Compressor::with_dictionary(3, dictionary)?,
+            None => zstd::bulk::Compressor::new(3)?,
+        };
+        let tag = if dictionary.is_some() {
+            TEXT_DICTIONARY
+        } else {
+            TEXT_PLAIN
+        };
+
+        let mut values = Vec::with_capacity(texts.len());
+        for (id, text) in &texts {
+            let mut value = vec![tag];
+            value.extend(compressor.compress(text.as_bytes())?);
+            values.push((*id, value));
+        }
+
+        let mut tx = self.db.begin_write()?;
+        tx.set_durability(redb::Durability::Eventual);
+        let mut table = tx.open_table(REVISION_TEXTS_TABLE)?;
+        for (id, value) in &values {
+            table.insert((self.wiki.as_str(), *id), value.as_slice())?;
+        }
+        drop(table);
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// The text dictionary, trained on `samples` if there is none yet
+    /// and they are enough.
+    fn text_dictionary_for(
+        &self,
+        samples: &[(u64, CompactString)],
+    ) -> anyhow::Result<Option<Arc<[u8]>>> {
+        let mut dictionary = self.text_dictionary.lock().unwrap();
+        if dictionary.is_none() && samples.len() >= DICTIONARY_MIN_SAMPLES {
+            let samples: Vec<&[u8]> = samples
+                .iter()
+                .map(|(_, text)| &text.as_bytes()[..text.len().min(DICTIONARY_SAMPLE_BYTES)])
+                .collect();
+            match zstd::dict::from_samples(&samples, DICTIONARY_SIZE) {
+                Ok(trained) => {
+                    let tx = self.db.begin_write()?;
+                    tx.open_table(BLOBS_TABLE)?
+                        .insert(TEXT_DICTIONARY_KEY, trained.as_slice())?;
+                    tx.commit()?;
+                    tracing::info!("trained a {} byte revision text dictionary", trained.len());
+                    *dictionary = Some(trained.into());
+                }
+                Err(e) => tracing::warn!("failed to train revision text dictionary: {e}"),
+            }
+        }
+        Ok(dictionary.clone())
+    }
+
+    /// The wikitext of an archived revision.
+    pub fn revision_text(&self, id: u64) -> anyhow::Result<Option<String>> {
+        let tx = self.db.begin_read()?;
+        let table = match tx.open_table(REVISION_TEXTS_TABLE) {
+            Ok(table) => table,
+            Err(TableError::TableDoesNotExist(_)) => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let Some(value) = table.get((self.wiki.as_str(), id))? else {
+            return Ok(None);
+        };
+        let value = value.value();
+        let (&tag, compressed) = value.split_first().context("empty revision text")?;
+        let mut text = String::new();
+        match tag {
+            TEXT_PLAIN => {
+                zstd::stream::Decoder::new(compressed)?.read_to_string(&mut text)?;
+            }
+            TEXT_DICTIONARY => {
+                let dictionary = self
+                    .text_dictionary
+                    .lock()
+                    .unwrap()
+                    .clone()
+                    .context("revision text dictionary is missing")?;
+                zstd::stream::Decoder::with_dictionary(compressed, &dictionary)?
+                    .read_to_string(&mut text)?;
+            }
+            _ => anyhow::bail!("unknown revision text tag {tag}"),
+        }
+        Ok(Some(text))
+    }
+
+    /// Titles of the `n` articles with the most revisions. Decodes
+    /// every article, so this takes a while on a full ingest.
+    pub fn most_revised_titles(&self, n: usize) -> anyhow::Result<Vec<CompactString>> {
+        let tx = self.db.begin_read()?;
+        let articles_table = match tx.open_table(ARTICLES_TABLE) {
+            Ok(table) => table,
+            Err(TableError::TableDoesNotExist(_)) => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let (start, end) = Self::key_range(&self.wiki);
+        let mut top = BinaryHeap::new();
+        for entry in articles_table.range(start.as_slice()..end.as_slice())? {
+            let (_, data) = entry?;
+            let article = self.decode_article(data.value())?;
+            top.push(Reverse((article.revisions.len(), article.title)));
+            if top.len() > n {
+                top.pop();
+            }
+        }
+        Ok(top
+            .into_sorted_vec()
+            .into_iter()
+            .map(|Reverse((_, title))| title)
+            .collect())
+    }
+
     pub fn mark_url_downloaded(&self, url: &str, bytes_read: u64) -> anyhow::Result<()> {
         let tx = self.db.begin_write()?;
         tx.open_table(DOWNLOADED_URLS_TABLE)?.insert(url, ())?;
@@ -130,11 +728,39 @@ impl TempDb {
         &self.db
     }
 
+    /// Decodes a value of `ARTICLES_TABLE`.
+    pub fn decode_article(&self, data: &[u8]) -> anyhow::Result<TempArticle> {
+        self.deserialize_article(&zstd::decode_all(data)?)
+    }
+
+    /// Deserializes an already decompressed value of `ARTICLES_TABLE`.
+    pub fn deserialize_article(&self, bytes: &[u8]) -> anyhow::Result<TempArticle> {
+        Ok(bincode::options().deserialize(bytes)?)
+    }
+
     pub fn close(self) -> anyhow::Result<()> {
         Ok(())
     }
 }
 
+/// Serializes and compresses an article into a value of
+/// `ARTICLES_TABLE`, using `buf` as scratch space.
+fn encode_article(article: &TempArticle, buf: &mut Vec<u8>) -> anyhow::Result<Vec<u8>> {
+    buf.clear();
+    bincode::options().serialize_into(&mut *buf, article)?;
+
+    thread_local! {
+        static COMPRESSOR: RefCell<zstd::bulk::Compressor<'static>> = RefCell::new(zstd::bulk::Compressor::new(3).unwrap());
+    }
+    let mut compressed = Vec::with_capacity(buf.len() * 2);
+    COMPRESSOR.with(|cell| {
+        cell.borrow_mut()
+            .compress_to_buffer(buf.as_slice(), &mut compressed)
+            .unwrap();
+    });
+    Ok(compressed)
+}
+
 #[derive(Clone)]
 struct Dropper(Sender<()>);
 
@@ -150,11 +776,301 @@ pub struct TempArticle {
     pub revisions: Vec<TempArticleRevision>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+impl TempArticle {
+    /// Adds the revisions of `other` not already present, by ID, and
+    /// keeps revisions in time order. Returns the number added.
+    pub fn merge(&mut self, other: TempArticle) -> usize {
+        let known: HashSet<u64> = self.revisions.iter().map(|revision| revision.id).collect();
+        let before = self.revisions.len();
+        self.revisions.extend(
+            other
+                .revisions
+                .into_iter()
+                .filter(|revision| !known.contains(&revision.id)),
+        );
+        let added = self.revisions.len() - before;
+        if added > 0 {
+            self.revisions.sort_by_key(|revision| revision.timestamp);
+        }
+        added
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
 pub struct TempArticleRevision {
+    /// Revision ID on the wiki, 0 if ingested before format version 2.
+    pub id: u64,
     pub user_id: i64,
     pub timestamp: Timestamp,
     /// Links stored as article titles. Later resolved
     /// to IDs after all articles are ingested.
     pub links: Vec<CompactString>,
+    /// Truncated BLAKE3 hash of the wikitext, to detect reverts.
+    /// 0 if ingested before format version 2.
+    pub text_hash: u64,
+    /// Type of the first infobox, e.g. `settlement` for
+    /// `{{Infobox settlement`. `None` without an infobox or if ingested
+    /// before format version 2.
+    pub infobox: Option<CompactString>,
+    /// Named parameters of the first infobox, sorted by name. Empty if
+    /// ingested before format version 2.
+    pub infobox_fields: Vec<(CompactString, CompactString)>,
+    /// Citations and external links, sorted. Empty if ingested before
+    /// format version 2.
+    pub references: Vec<Reference>,
+    /// Length of the wikitext in bytes. `None` if ingested before
+    /// format version 2.
+    pub text_len: Option<u64>,
+    /// Target of the revision if it is a redirect. `None` also if
+    /// ingested before format version 2.
+    pub redirect: Option<CompactString>,
+}
+
+impl TempArticleRevision {
+    /// A revision with everything we keep extracted from its wikitext.
+    pub fn from_text(id: u64, user_id: i64, timestamp: Timestamp, text: &str) -> Self {
+        Self {
+            id,
+            user_id,
+            timestamp,
+            links: parser::find_links(text),
+            text_hash: Self::hash_text(text),
+            infobox: parser::find_infobox(text),
+            infobox_fields: parser::find_infobox_fields(text),
+            references: parser::find_references(text),
+            text_len: Some(text.len() as u64),
+            redirect: parser::redirect_target(text),
+        }
+    }
+
+    pub fn hash_text(text: &str) -> u64 {
+        u64::from_be_bytes(
+            blake3::hash(text.as_bytes()).as_bytes()[..8]
+                .try_into()
+                .unwrap(),
+        )
+    }
+}
+
+#[cfg(test)]
+impl TempArticleRevision {
+    /// A revision linking to `links`, with nothing else extracted.
+    pub fn for_test(user_id: i64, timestamp: &str, links: &[&str]) -> Self {
+        Self {
+            user_id,
+            timestamp: timestamp.parse().unwrap(),
+            links: links.iter().map(|&link| link.into()).collect(),
+            ..Default::default()
+        }
+    }
+}
+
+/// A source cited by a revision.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum Reference {
+    /// A `<ref>` tag, identified by the URL it cites or else by its
+    /// `{{cite ...}}` template.
+    Ref(CompactString),
+    /// An external link outside of `<ref>` tags.
+    ExternalLink(CompactString),
+}
+
+impl Reference {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Reference::Ref(_) => "ref",
+            Reference::ExternalLink(_) => "external_link",
+        }
+    }
+
+    pub fn identity(&self) -> &str {
+        match self {
+            Reference::Ref(identity) | Reference::ExternalLink(identity) => identity,
+        }
+    }
+}
+
+/// Encoding of format version 1.
+#[derive(Serialize, Deserialize)]
+struct TempArticleV1 {
+    title: CompactString,
+    revisions: Vec<TempArticleRevisionV1>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct TempArticleRevisionV1 {
+    user_id: i64,
+    timestamp: Timestamp,
+    links: Vec<CompactString>,
+}
+
+/// Decodes a value of `ARTICLES_TABLE` written by format `version`.
+fn decode_legacy_article(version: u64, data: &[u8]) -> anyhow::Result<TempArticle> {
+    let bytes = zstd::decode_all(data)?;
+    Ok(match version {
+        1 => bincode::options()
+            .deserialize::<TempArticleV1>(&bytes)?
+            .into(),
+        _ => bincode::options().deserialize(&bytes)?,
+    })
+}
+
+impl From<TempArticleV1> for TempArticle {
+    fn from(article: TempArticleV1) -> Self {
+        Self {
+            title: article.title,
+            revisions: article
+                .revisions
+                .into_iter()
+                .map(|revision| TempArticleRevision {
+                    id: 0,
+                    user_id: revision.user_id,
+                    timestamp: revision.timestamp,
+                    links: revision.links,
+                    text_hash: 0,
+                    ..Default::default()
+                })
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merge() {
+        let revision = |id, timestamp| TempArticleRevision {
+            id,
+            text_hash: id,
+            ..TempArticleRevision::for_test(1, timestamp, &[])
+        };
+        let mut article = TempArticle {
+            title: "Macy's".into(),
+            revisions: vec![
+                revision(1, "2024-01-01T00:00:00Z"),
+                revision(3, "2024-01-03T00:00:00Z"),
+            ],
+        };
+        let added = article.merge(TempArticle {
+            title: "Macy's".into(),
+            revisions: vec![
+                revision(3, "2024-01-03T00:00:00Z"),
+                revision(2, "2024-01-02T00:00:00Z"),
+                revision(4, "2024-01-04T00:00:00Z"),
+            ],
+        });
+        assert_eq!(added, 2);
+        assert_eq!(
+            article
+                .revisions
+                .iter()
+                .map(|revision| revision.id)
+                .collect::<Vec<_>>(),
+            [1, 2, 3, 4]
+        );
+    }
+
+    #[test]
+    fn test_wiki_keys() {
+        let path = std::env::temp_dir().join(format!("temp-db-test-{}.redb", std::process::id()));
+        let (temp_db, _shutdown) = TempDb::open_at(&path).unwrap();
+        let article = |title: &str, id| TempArticle {
+            title: title.into(),
+            revisions: vec![TempArticleRevision {
+                id,
+                ..TempArticleRevision::for_test(1, "2024-01-01T00:00:00Z", &[])
+            }],
+        };
+        let dewiki = temp_db.for_wiki("dewiki");
+        temp_db.merge_article_batch([article("Berlin", 1)]).unwrap();
+        dewiki
+            .merge_article_batch([article("Berlin", 2), article("Bremen", 3)])
+            .unwrap();
+        dewiki
+            .insert_revision_texts([(2, CompactString::from("Berlin ist"))])
+            .unwrap();
+
+        assert_eq!(temp_db.wikis().unwrap(), ["dewiki", "enwiki"]);
+        assert_eq!(
+            temp_db.get_article("Berlin").unwrap().unwrap().revisions[0].id,
+            1
+        );
+        assert_eq!(
+            dewiki.get_article("berlin").unwrap().unwrap().revisions[0].id,
+            2
+        );
+        assert_eq!(
+            dewiki.get_article("BREMEN").unwrap().unwrap().revisions[0].id,
+            3
+        );
+        assert!(temp_db.get_article("Bremen").unwrap().is_none());
+        assert_eq!(dewiki.search_titles("b", 10).unwrap(), ["Berlin", "Bremen"]);
+        assert_eq!(
+            dewiki.revision_text(2).unwrap().as_deref(),
+            Some("Berlin ist")
+        );
+        assert!(temp_db.revision_text(2).unwrap().is_none());
+        assert_eq!(
+            temp_db.dirty_titles().unwrap(),
+            [
+                ("dewiki".into(), "Berlin".into()),
+                ("dewiki".into(), "Bremen".into()),
+                ("enwiki".into(), "Berlin".into()),
+            ]
+        );
+        drop((temp_db, dewiki));
+        std::fs::remove_file(path).ok();
+    }
+
+    #[test]
+    fn test_migrate_v1() {
+        let path =
+            std::env::temp_dir().join(format!("temp-db-test-v1-{}.redb", std::process::id()));
+        let article = TempArticleV1 {
+            title: "Macy's".into(),
+            revisions: vec![TempArticleRevisionV1 {
+                user_id: 1,
+                timestamp: "2024-01-01T00:00:00Z".parse().unwrap(),
+                links: vec!["Herald Square".into()],
+            }],
+        };
+        {
+            let db = Database::create(&path).unwrap();
+            let tx = db.begin_write().unwrap();
+            let data = zstd::encode_all(
+                bincode::options().serialize(&article).unwrap().as_slice(),
+                3,
+            )
+            .unwrap();
+            tx.open_table(LEGACY_ARTICLES_TABLE)
+                .unwrap()
+                .insert("Macy's".as_bytes(), data.as_slice())
+                .unwrap();
+            tx.commit().unwrap();
+        }
+
+        assert!(TempDb::open_at(&path).is_err());
+        TempDb::migrate_at(&path).unwrap();
+        // already migrated, so nothing is moved twice
+        TempDb::migrate_at(&path).unwrap();
+        let (temp_db, _shutdown) = TempDb::open_at(&path).unwrap();
+        assert!(temp_db.ids_missing);
+        let article = temp_db.get_article("Macy's").unwrap().unwrap();
+        assert_eq!(article.revisions[0].links, ["Herald Square"]);
+        assert_eq!(article.revisions[0].id, 0);
+        assert_eq!(article.revisions[0].infobox, None);
+        assert!(
+            temp_db
+                .for_wiki("dewiki")
+                .get_article("Macy's")
+                .unwrap()
+                .is_none()
+        );
+        let tx = temp_db.db().begin_read().unwrap();
+        assert!(tx.open_table(LEGACY_ARTICLES_TABLE).is_err());
+        drop((tx, temp_db));
+        std::fs::remove_file(path).ok();
+    }
 }
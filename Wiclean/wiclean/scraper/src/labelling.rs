@@ -0,0 +1,307 @@
+//! Interactive labelling of flagged article-weeks.
+//!
+//! Candidates are walked in anomaly score order, skipping those the
+//! labeller already labelled, so a session can be interrupted and
+//! resumed at any time. Every answer is appended to the labels file
+//! right away.
+
+use crate::anomalies::{self, ScoredWeek};
+use crate::dataset;
+use crate::diff;
+use crate::history::{self, RevisionChange};
+use crate::ingest::temp_db::{TempArticle, TempDb};
+use crate::labels::{self, Label, LabelKey};
+use crate::report;
+use crate::week::IsoWeek;
+use crate::wiki_api;
+use foldhash::HashSet;
+use std::io::{self, BufRead, Write};
+use std::path::PathBuf;
+
+/// Links shown per direction in a candidate summary.
+const SHOWN_LINKS: usize = 10;
+
+/// Unchanged characters shown around each change in the text diff.
+const DIFF_CONTEXT: usize = 40;
+
+/// Characters of the text diff shown before it is cut off.
+const SHOWN_DIFF: usize = 2000;
+
+#[derive(Debug, clap::Args)]
+pub struct LabelArgs {
+    /// Name recorded with each label, to tell labellers apart.
+    #[clap(long)]
+    labeller: String,
+    /// Anomalies written by `anomalies`.
+    #[clap(long, default_value = "data/anomalies.parquet")]
+    anomalies: PathBuf,
+    /// Labels file to append to, readable by `evaluate`.
+    #[clap(long, default_value = "data/labels.tsv")]
+    labels: PathBuf,
+    /// Only offer article-weeks that other labellers have labelled,
+    /// to build up overlap for agreement stats.
+    #[clap(long)]
+    overlap: bool,
+    /// Print agreement between labellers and exit.
+    #[clap(long)]
+    agreement: bool,
+    /// Wiki to label article-weeks of, by database name.
+    #[clap(long, default_value = dataset::DEFAULT_WIKI)]
+    wiki: String,
+    /// Base URL of the wiki, for diff links. Defaults to the Wikipedia
+    /// of `--wiki`.
+    #[clap(long)]
+    wiki_url: Option<String>,
+}
+
+enum Answer {
+    Label(bool),
+    Skip,
+    Quit,
+}
+
+pub fn label(temp_db: &TempDb, args: &LabelArgs) -> anyhow::Result<()> {
+    let existing = if args.labels.exists() {
+        labels::read_labels(&args.labels)?
+    } else {
+        Vec::new()
+    };
+    let existing: Vec<Label> = existing
+        .into_iter()
+        .filter(|label| label.wiki == args.wiki)
+        .collect();
+    if args.agreement {
+        print_agreement(&existing);
+        return Ok(());
+    }
+
+    let mine: HashSet<LabelKey> = existing
+        .iter()
+        .filter(|label| label.labeller.as_deref() == Some(args.labeller.as_str()))
+        .map(Label::key)
+        .collect();
+    let others: HashSet<LabelKey> = existing
+        .iter()
+        .filter(|label| label.labeller.as_deref() != Some(args.labeller.as_str()))
+        .map(Label::key)
+        .collect();
+    let candidates: Vec<ScoredWeek> = anomalies::read_anomalies(&args.anomalies)?
+        .into_iter()
+        .filter(|row| row.anomaly && row.wiki == args.wiki)
+        .filter(|row| {
+            let Some(title) = &row.title else {
+                return false;
+            };
+            let key = (
+                row.wiki.clone(),
+                labels::normalize_label_title(title),
+                row.week,
+            );
+            !mine.contains(&key) && (!args.overlap || others.contains(&key))
+        })
+        .collect();
+    println!(
+        "{} candidates left for {}, {} labelled before",
+        candidates.len(),
+        args.labeller,
+        mine.len()
+    );
+
+    let temp_db = temp_db.for_wiki(&args.wiki);
+    let wiki_url = match &args.wiki_url {
+        Some(url) => url.clone(),
+        None => wiki_api::site_url(&args.wiki)?,
+    };
+    let mut input = io::stdin().lock();
+    let mut labelled = 0;
+    for (i, row) in candidates.iter().enumerate() {
+        let title = row.lookup_title().unwrap_or_default();
+        let Some(article) = temp_db.get_article(title)? else {
+            tracing::warn!("article {title:?} is not in data/temp-db");
+            continue;
+        };
+        println!();
+        println!(
+            "[{}/{}] {} {} (score {:.3})",
+            i + 1,
+            candidates.len(),
+            article.title,
+            row.week,
+            row.score
+        );
+        print_summary(&temp_db, &article, row.week, &wiki_url)?;
+
+        let valid = match ask_label(&mut input)? {
+            Answer::Label(valid) => valid,
+            Answer::Skip => continue,
+            Answer::Quit => break,
+        };
+        let note = prompt(&mut input, "note (optional): ")?.unwrap_or_default();
+        labels::append_tsv(
+            &args.labels,
+            &Label {
+                wiki: args.wiki.as_str().into(),
+                title: article.title.clone(),
+                week: row.week,
+                valid,
+                labeller: Some(args.labeller.as_str().into()),
+                note: note.trim().to_owned(),
+            },
+        )?;
+        labelled += 1;
+    }
+
+    println!();
+    println!(
+        "labelled {labelled} article-weeks into {}",
+        args.labels.display()
+    );
+    let labels: Vec<Label> = labels::read_labels(&args.labels)?
+        .into_iter()
+        .filter(|label| label.wiki == args.wiki)
+        .collect();
+    print_agreement(&labels);
+    Ok(())
+}
+
+fn ask_label(input: &mut impl BufRead) -> anyhow::Result<Answer> {
+    loop {
+        let Some(answer) = prompt(input, "[v]alid, [i]nvalid, [s]kip or [q]uit? ")? else {
+            return Ok(Answer::Quit);
+        };
+        match answer.trim() {
+            "v" | "valid" => return Ok(Answer::Label(true)),
+            "i" | "invalid" => return Ok(Answer::Label(false)),
+            "s" | "skip" => return Ok(Answer::Skip),
+            "q" | "quit" => return Ok(Answer::Quit),
+            _ => {}
+        }
+    }
+}
+
+/// Reads one line, or `None` at the end of input.
+fn prompt(input: &mut impl BufRead, text: &str) -> anyhow::Result<Option<String>> {
+    print!("{text}");
+    io::stdout().flush()?;
+    let mut line = String::new();
+    if input.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    Ok(Some(line))
+}
+
+fn print_summary(
+    temp_db: &TempDb,
+    article: &TempArticle,
+    week: IsoWeek,
+    wiki_url: &str,
+) -> anyhow::Result<()> {
+    let changes = history::revision_changes(article);
+    let week_changes: Vec<&RevisionChange> = changes
+        .iter()
+        .filter(|change| IsoWeek::of(change.timestamp) == week)
+        .collect();
+    let editors = report::editor_stats(&week_changes);
+    let reverts = week_changes
+        .iter()
+        .filter(|change| change.reverts_to.is_some())
+        .count();
+    println!(
+        "  {} revisions by {} editors, {} reverts",
+        week_changes.len(),
+        editors.len(),
+        reverts
+    );
+    for (user_id, stats) in editors.iter().take(5) {
+        println!(
+            "  user {user_id}: {} revisions, +{} -{} links, {} reverts",
+            stats.revisions, stats.added, stats.removed, stats.reverts
+        );
+    }
+
+    for (symbol, links) in [
+        (
+            "+",
+            week_changes
+                .iter()
+                .flat_map(|change| &change.added)
+                .collect::<Vec<_>>(),
+        ),
+        (
+            "-",
+            week_changes
+                .iter()
+                .flat_map(|change| &change.removed)
+                .collect(),
+        ),
+    ] {
+        if links.is_empty() {
+            continue;
+        }
+        let shown: Vec<&str> = links
+            .iter()
+            .take(SHOWN_LINKS)
+            .map(|link| link.as_str())
+            .collect();
+        let more = links.len().saturating_sub(SHOWN_LINKS);
+        if more > 0 {
+            println!("  {symbol} {} (+{more} more)", shown.join(", "));
+        } else {
+            println!("  {symbol} {}", shown.join(", "));
+        }
+    }
+
+    // the revision touching the most links is usually the one to look at
+    if let Some(change) = week_changes
+        .iter()
+        .filter(|change| change.id != 0)
+        .max_by_key(|change| change.added.len() + change.removed.len())
+    {
+        println!(
+            "  largest change: {wiki_url}/w/index.php?diff=prev&oldid={}",
+            change.id
+        );
+        let diffs = diff::text_diffs(temp_db, article, [change.id])?;
+        if let Some(words) = diffs.first().and_then(|diff| diff.words.as_deref()) {
+            let rendered = diff::render_words(words, DIFF_CONTEXT);
+            let shown: String = rendered.chars().take(SHOWN_DIFF).collect();
+            println!();
+            println!("{}", indent(&shown));
+            if shown.len() < rendered.len() {
+                println!("  … diff cut off, see the link above");
+            }
+        }
+    }
+    Ok(())
+}
+
+fn indent(text: &str) -> String {
+    text.lines()
+        .map(|line| format!("  {line}"))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn print_agreement(labels: &[Label]) {
+    let agreements = labels::pairwise_agreement(labels);
+    if agreements.is_empty() {
+        println!("no article-weeks labelled by more than one labeller");
+        return;
+    }
+    println!(
+        "{:<24}{:>8}{:>10}{:>8}",
+        "labellers", "shared", "agreement", "kappa"
+    );
+    for agreement in agreements {
+        let (a, b) = &agreement.labellers;
+        println!(
+            "{:<24}{:>8}{:>10.3}{:>8}",
+            format!("{a} / {b}"),
+            agreement.shared,
+            agreement.agreed as f64 / agreement.shared as f64,
+            agreement
+                .kappa
+                .map_or_else(|| "-".to_owned(), |kappa| format!("{kappa:.3}"))
+        );
+    }
+}
@@ -0,0 +1,285 @@
+//! Hand-labelled article-weeks.
+//!
+//! Labels are read either from the free-text `examples.txt` format
+//!
+//! ```text
+//! 1. Detroit, 2024-45: valid; vandalism attempt that deleted most of the article
+//! ```
+//!
+//! or from a TSV file with the columns `title`, `week`, `label`,
+//! `labeller`, `note` and `wiki`, where `label` is `valid` or
+//! `invalid`. Labels without a wiki are of enwiki.
+
+use crate::dataset::DEFAULT_WIKI;
+use crate::postprocess_to_parquet::normalize_title;
+use crate::week::IsoWeek;
+use anyhow::{Context, bail};
+use compact_str::CompactString;
+use foldhash::HashMap;
+use regex::Regex;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::LazyLock;
+
+pub const TSV_HEADER: &str = "title\tweek\tlabel\tlabeller\tnote\twiki";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Label {
+    pub wiki: CompactString,
+    pub title: CompactString,
+    pub week: IsoWeek,
+    /// Whether the article-week really is an anomaly.
+    pub valid: bool,
+    pub labeller: Option<CompactString>,
+    pub note: String,
+}
+
+/// Wiki, normalized title and week of a labelled article-week.
+pub type LabelKey = (CompactString, CompactString, IsoWeek);
+
+impl Label {
+    /// Key to join the label with pipeline outputs, whose titles
+    /// are normalized.
+    pub fn key(&self) -> LabelKey {
+        (
+            self.wiki.clone(),
+            normalize_label_title(&self.title),
+            self.week,
+        )
+    }
+}
+
+/// Normalizes a title as typed by a person the way the pipeline
+/// normalizes dump titles.
+pub fn normalize_label_title(title: &str) -> CompactString {
+    normalize_title(&title.replace('’', "'").replace('_', " "))
+}
+
+pub fn read_labels(path: impl AsRef<Path>) -> anyhow::Result<Vec<Label>> {
+    let path = path.as_ref();
+    let text =
+        fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))?;
+    if path.extension().is_some_and(|ext| ext == "tsv") {
+        parse_tsv(&text)
+    } else {
+        parse_examples(&text)
+    }
+    .with_context(|| format!("failed to parse {}", path.display()))
+}
+
+/// Parses the free-text format of `examples.txt`.
+pub fn parse_examples(text: &str) -> anyhow::Result<Vec<Label>> {
+    static REGEX: LazyLock<Regex> = LazyLock::new(|| {
+        Regex::new(
+            r"^\s*\d+\s*[.:]\s*(.+),\s*(\d{4}-\d{1,2})\s*:\s*(valid|invalid)\s*(?:;\s*(.*))?$",
+        )
+        .unwrap()
+    });
+
+    let mut labels = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let captures = REGEX
+            .captures(line)
+            .with_context(|| format!("line {} is not a labelled example: {line:?}", i + 1))?;
+        labels.push(Label {
+            wiki: DEFAULT_WIKI.into(),
+            title: captures[1].trim().into(),
+            week: captures[2].parse()?,
+            valid: &captures[3] == "valid",
+            labeller: None,
+            note: captures
+                .get(4)
+                .map(|note| note.as_str().trim().to_owned())
+                .unwrap_or_default(),
+        });
+    }
+    Ok(labels)
+}
+
+pub fn parse_tsv(text: &str) -> anyhow::Result<Vec<Label>> {
+    let mut labels = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        // files written before the wiki column have a shorter header
+        if line.trim().is_empty() || (i == 0 && TSV_HEADER.starts_with(line)) {
+            continue;
+        }
+        let fields: Vec<&str> = line.split('\t').collect();
+        let [title, week, label, rest @ ..] = fields.as_slice() else {
+            bail!("line {} has too few columns", i + 1);
+        };
+        let valid = match *label {
+            "valid" => true,
+            "invalid" => false,
+            _ => bail!("line {}: unknown label {label:?}", i + 1),
+        };
+        labels.push(Label {
+            wiki: rest
+                .get(2)
+                .filter(|wiki| !wiki.is_empty())
+                .map_or(DEFAULT_WIKI, |wiki| wiki)
+                .into(),
+            title: (*title).into(),
+            week: week.parse()?,
+            valid,
+            labeller: rest
+                .first()
+                .filter(|labeller| !labeller.is_empty())
+                .map(|&labeller| labeller.into()),
+            note: rest.get(1).copied().unwrap_or_default().to_owned(),
+        });
+    }
+    Ok(labels)
+}
+
+/// Appends a label to a TSV file, writing the header first if the
+/// file is new.
+pub fn append_tsv(path: impl AsRef<Path>, label: &Label) -> anyhow::Result<()> {
+    let path = path.as_ref();
+    let is_new = fs::metadata(path).map_or(true, |metadata| metadata.len() == 0);
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    let clean = |s: &str| s.replace(['\t', '\n', '\r'], " ");
+    let mut line = String::new();
+    if is_new {
+        line.push_str(TSV_HEADER);
+        line.push('\n');
+    }
+    line.push_str(&format!(
+        "{}\t{}\t{}\t{}\t{}\t{}\n",
+        clean(&label.title),
+        label.week,
+        if label.valid { "valid" } else { "invalid" },
+        clean(label.labeller.as_deref().unwrap_or_default()),
+        clean(&label.note),
+        label.wiki
+    ));
+    file.write_all(line.as_bytes())?;
+    Ok(())
+}
+
+/// Cohen's kappa between two labellers of the same article-weeks.
+/// `None` if there are no shared labels or agreement by chance is
+/// certain.
+pub fn cohens_kappa(pairs: &[(bool, bool)]) -> Option<f64> {
+    if pairs.is_empty() {
+        return None;
+    }
+    let n = pairs.len() as f64;
+    let observed = pairs.iter().filter(|(a, b)| a == b).count() as f64 / n;
+    let a_valid = pairs.iter().filter(|(a, _)| *a).count() as f64 / n;
+    let b_valid = pairs.iter().filter(|(_, b)| *b).count() as f64 / n;
+    let chance = a_valid * b_valid + (1.0 - a_valid) * (1.0 - b_valid);
+    (chance < 1.0).then(|| (observed - chance) / (1.0 - chance))
+}
+
+/// Agreement of one pair of labellers.
+#[derive(Debug)]
+pub struct PairAgreement {
+    pub labellers: (CompactString, CompactString),
+    pub shared: usize,
+    pub agreed: usize,
+    pub kappa: Option<f64>,
+}
+
+/// Pairwise agreement between all labellers with shared
+/// article-weeks. A labeller's latest label of an article-week wins.
+pub fn pairwise_agreement(labels: &[Label]) -> Vec<PairAgreement> {
+    let mut by_labeller = HashMap::<CompactString, HashMap<LabelKey, bool>>::default();
+    for label in labels {
+        if let Some(labeller) = &label.labeller {
+            by_labeller
+                .entry(labeller.clone())
+                .or_default()
+                .insert(label.key(), label.valid);
+        }
+    }
+    let mut labellers: Vec<_> = by_labeller.keys().cloned().collect();
+    labellers.sort_unstable();
+
+    let mut agreements = Vec::new();
+    for (i, a) in labellers.iter().enumerate() {
+        for b in &labellers[i + 1..] {
+            let pairs: Vec<(bool, bool)> = by_labeller[a]
+                .iter()
+                .filter_map(|(key, &valid)| Some((valid, *by_labeller[b].get(key)?)))
+                .collect();
+            if pairs.is_empty() {
+                continue;
+            }
+            agreements.push(PairAgreement {
+                labellers: (a.clone(), b.clone()),
+                shared: pairs.len(),
+                agreed: pairs.iter().filter(|(a, b)| a == b).count(),
+                kappa: cohens_kappa(&pairs),
+            });
+        }
+    }
+    agreements
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_examples() {
+        let labels = parse_examples(include_str!("../examples.txt")).unwrap();
+        assert_eq!(labels.len(), 10);
+        assert_eq!(labels[0].title, "Detroit");
+        assert_eq!(labels[0].week.to_string(), "2024-45");
+        assert!(labels[0].valid);
+        assert_eq!(
+            labels[1].key().1,
+            "macy's thanksgiving day parade",
+            "curly apostrophes are normalized"
+        );
+        assert_eq!(labels[5].title, "PBS");
+        assert!(!labels[7].valid);
+        assert_eq!(labels[7].note, "");
+    }
+
+    #[test]
+    fn test_parse_tsv() {
+        let text = format!(
+            "{TSV_HEADER}\nAdult Swim\t2024-1\tvalid\talice\tredirect hijack\nBerlin\t2024-2\tinvalid\tbob\t\tdewiki\n"
+        );
+        let labels = parse_tsv(&text).unwrap();
+        assert_eq!(labels.len(), 2);
+        assert_eq!(labels[0].labeller.as_deref(), Some("alice"));
+        assert_eq!(labels[0].note, "redirect hijack");
+        assert_eq!(labels[0].wiki, "enwiki");
+        assert_eq!(labels[1].wiki, "dewiki");
+    }
+
+    #[test]
+    fn test_cohens_kappa() {
+        // the example from Wikipedia's article on Cohen's kappa
+        let mut pairs = Vec::new();
+        pairs.extend([(true, true)].repeat(20));
+        pairs.extend([(true, false)].repeat(5));
+        pairs.extend([(false, true)].repeat(10));
+        pairs.extend([(false, false)].repeat(15));
+        let kappa = cohens_kappa(&pairs).unwrap();
+        assert!((kappa - 0.4).abs() < 1e-9);
+        assert_eq!(cohens_kappa(&[(true, true)]), None);
+    }
+
+    #[test]
+    fn test_pairwise_agreement() {
+        let text = format!(
+            "{TSV_HEADER}
+             Berlin\t2024-2\tvalid\talice\t\tenwiki
+             Berlin\t2024-2\tinvalid\talice\t\tdewiki
+             Berlin\t2024-2\tvalid\tbob\t\tenwiki
+"
+        );
+        let agreements = pairwise_agreement(&parse_tsv(&text).unwrap());
+        assert_eq!(agreements.len(), 1);
+        // the dewiki label is of another article
+        assert_eq!(agreements[0].shared, 1);
+        assert_eq!(agreements[0].agreed, 1);
+    }
+}
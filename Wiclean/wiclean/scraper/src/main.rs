@@ -10,8 +10,27 @@ use mimalloc::MiMalloc;
 use tokio::runtime;
 use tokio_postgres::NoTls;
 
+mod anomalies;
+mod blanking;
+mod bursts;
+mod dataset;
+mod diff;
+mod evaluate;
+mod features;
+mod fetch;
+mod history;
 mod ingest;
+mod labelling;
+mod labels;
+mod partial_patterns;
+mod patterns;
 mod postprocess_to_parquet;
+mod report;
+mod serve;
+mod stream;
+mod tui;
+mod week;
+mod wiki_api;
 
 #[global_allocator]
 static ALLOC: Jemalloc = Jemalloc;
@@ -24,8 +43,38 @@ struct Cli {
 
 #[derive(Debug, Subcommand)]
 enum Command {
-    Ingest,
-    PostprocessToParquet,
+    Ingest(ingest::IngestArgs),
+    /// Merge daily adds-changes dumps into an earlier ingest.
+    IngestIncremental(ingest::IngestIncrementalArgs),
+    /// Ingest edits live from the recentchange event stream.
+    Stream(stream::StreamArgs),
+    /// Refresh the history of one article through the Action API.
+    FetchArticle(fetch::FetchArticleArgs),
+    PostprocessToParquet(postprocess_to_parquet::PostprocessArgs),
+    /// Upgrade data/temp-db to the current format version.
+    Migrate,
+    /// Compute weekly per-article link edit features.
+    Features(features::FeaturesArgs),
+    /// Mine frequent link edit patterns with FP-growth.
+    MinePatterns(patterns::MinePatternsArgs),
+    /// Find article-weeks that apply only part of a mined pattern.
+    DetectPartialPatterns(partial_patterns::DetectPartialPatternsArgs),
+    /// Score weekly features with an isolation forest.
+    Anomalies(anomalies::AnomaliesArgs),
+    /// Measure detected anomalies against labelled article-weeks.
+    Evaluate(evaluate::EvaluateArgs),
+    /// Print the link additions and removals of one article.
+    History(history::HistoryArgs),
+    /// Serve article histories over a local HTTP API.
+    Serve(serve::ServeArgs),
+    /// Browse link histories in a terminal UI.
+    Tui(tui::TuiArgs),
+    /// Write Markdown review reports for flagged article-weeks.
+    Report(report::ReportArgs),
+    /// Label flagged article-weeks interactively.
+    Label(labelling::LabelArgs),
+    /// Diff the wikitext and links of two revisions of an article.
+    Diff(diff::DiffArgs),
 }
 
 fn main() -> anyhow::Result<()> {
@@ -34,24 +83,50 @@ fn main() -> anyhow::Result<()> {
         .install_default()
         .ok();
 
-    let (temp_db, temp_db_shutdown) = TempDb::open()?;
-
     let runtime = runtime::Builder::new_multi_thread().enable_all().build()?;
     let _guard = runtime.enter();
     let _db_pool = init_db();
 
     let cli = Cli::parse();
+    // only commands using data/temp-db open it, so those reading Parquet
+    // files work without it and regardless of its format version
+    let mut temp_db_shutdown = None;
+    let mut open_temp_db = || -> anyhow::Result<TempDb> {
+        let (temp_db, shutdown) = TempDb::open()?;
+        temp_db_shutdown = Some(shutdown);
+        Ok(temp_db)
+    };
     match cli.command {
-        Command::Ingest => runtime.block_on(ingest::ingest(temp_db.clone()))?,
-        Command::PostprocessToParquet => postprocess_to_parquet::postprocess_to_parquet(&temp_db)?,
+        Command::Ingest(args) => runtime.block_on(ingest::ingest(open_temp_db()?, &args))?,
+        Command::IngestIncremental(args) => ingest::ingest_incremental(&open_temp_db()?, &args)?,
+        Command::Stream(args) => runtime.block_on(stream::stream(open_temp_db()?, &args))?,
+        Command::FetchArticle(args) => {
+            runtime.block_on(fetch::fetch_article(open_temp_db()?, &args))?
+        }
+        Command::PostprocessToParquet(args) => {
+            postprocess_to_parquet::postprocess_to_parquet(&open_temp_db()?, &args)?
+        }
+        Command::Migrate => TempDb::migrate()?,
+        Command::Features(args) => features::compute_weekly_features(&args)?,
+        Command::MinePatterns(args) => patterns::mine_patterns(&args)?,
+        Command::DetectPartialPatterns(args) => partial_patterns::detect_partial_patterns(&args)?,
+        Command::Anomalies(args) => anomalies::score_anomalies(&args)?,
+        Command::Evaluate(args) => evaluate::evaluate(&args)?,
+        Command::History(args) => history::show_history(&open_temp_db()?, &args)?,
+        Command::Serve(args) => runtime.block_on(serve::serve(open_temp_db()?, &args))?,
+        Command::Tui(args) => tui::run_tui(&open_temp_db()?, &args)?,
+        Command::Report(args) => report::write_reports(&open_temp_db()?, &args)?,
+        Command::Label(args) => labelling::label(&open_temp_db()?, &args)?,
+        Command::Diff(args) => diff::show_diff(&open_temp_db()?, &args)?,
     }
 
     drop(_guard);
 
     tracing::info!("shutting down");
-    drop(temp_db);
     runtime.shutdown_timeout(Duration::from_secs(60 * 60));
-    temp_db_shutdown.recv().unwrap();
+    if let Some(temp_db_shutdown) = temp_db_shutdown {
+        temp_db_shutdown.recv().unwrap();
+    }
     Ok(())
 }
 
@@ -0,0 +1,356 @@
+//! WiClean-style detection of missing updates.
+//!
+//! A rule mined by `mine-patterns` says that whenever the link edits
+//! of its antecedent happen, those of its consequent usually happen
+//! as well. An article-week in which the antecedent happened but part
+//! of the consequent did not, even allowing for some slack before and
+//! after, is a candidate for an incomplete edit.
+
+use crate::dataset::{self, LinkInterval, Period};
+use crate::patterns::{self, Item, Rule};
+use crate::week::IsoWeek;
+use arrow::array::{Float64Builder, Int64Builder, RecordBatch, StringBuilder};
+use arrow::datatypes::{DataType, Field, Schema, SchemaRef};
+use compact_str::CompactString;
+use foldhash::{HashMap, HashSet};
+use jiff::{SignedDuration, Timestamp};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Instant;
+
+#[derive(Debug, clap::Args)]
+pub struct DetectPartialPatternsArgs {
+    /// Association rules written by `mine-patterns`.
+    #[clap(long, default_value = "data/pattern_rules.parquet")]
+    rules: PathBuf,
+    /// Link intervals written by `postprocess-to-parquet`.
+    #[clap(long, default_value = dataset::LINKS_PATH)]
+    links: PathBuf,
+    /// Article titles written by `postprocess-to-parquet`.
+    #[clap(long, default_value = dataset::ARTICLES_PATH)]
+    articles: PathBuf,
+    /// Wiki to detect missing updates in, by database name.
+    #[clap(long, default_value = dataset::DEFAULT_WIKI)]
+    wiki: String,
+    #[clap(long, default_value = "data/partial_patterns.parquet")]
+    output: PathBuf,
+    /// Ignore rules with a lower confidence.
+    #[clap(long, default_value_t = 0.8)]
+    min_confidence: f64,
+    /// How long before the first or after the last antecedent edit
+    /// a consequent edit still counts as matching.
+    #[clap(long, default_value_t = 72)]
+    tolerance_hours: i64,
+    /// Number of top candidates to print.
+    #[clap(long, default_value_t = 20)]
+    show: usize,
+    #[clap(flatten)]
+    period: Period,
+}
+
+#[derive(Debug, Clone)]
+pub struct Candidate {
+    pub src_article: i64,
+    pub week: IsoWeek,
+    pub antecedent: Vec<Item>,
+    /// Items of the rule's consequent that did not happen.
+    pub missing: Vec<Item>,
+    pub confidence: f64,
+    pub freq: u64,
+    /// Rule confidence scaled by the fraction of the consequent
+    /// that is missing.
+    pub score: f64,
+}
+
+pub fn detect_partial_patterns(args: &DetectPartialPatternsArgs) -> anyhow::Result<()> {
+    let start = Instant::now();
+    let (from, to) = args.period.bounds()?;
+    let tolerance = SignedDuration::from_hours(args.tolerance_hours);
+
+    let rules = patterns::read_rules(&args.rules, &args.wiki, args.min_confidence)?;
+    tracing::info!(
+        "loaded {} rules for {} articles",
+        rules.values().map(Vec::len).sum::<usize>(),
+        rules.len()
+    );
+
+    let mut candidates = Vec::new();
+    for group in dataset::link_intervals(&args.links, &args.wiki)? {
+        let (src_article, intervals) = group?;
+        if let Some(rules) = rules.get(&src_article) {
+            candidates.extend(article_candidates(
+                src_article,
+                &intervals,
+                rules,
+                from,
+                to,
+                tolerance,
+            ));
+        }
+    }
+    candidates.sort_by(|a, b| {
+        b.score
+            .total_cmp(&a.score)
+            .then(b.freq.cmp(&a.freq))
+            .then(a.src_article.cmp(&b.src_article))
+            .then(a.week.cmp(&b.week))
+    });
+
+    let mut ids = HashSet::default();
+    for candidate in &candidates {
+        ids.insert(candidate.src_article);
+        ids.extend(candidate.antecedent.iter().map(|item| item.dst_article));
+        ids.extend(candidate.missing.iter().map(|item| item.dst_article));
+    }
+    let titles = dataset::read_article_titles(&args.articles, &args.wiki, |id| ids.contains(&id))?;
+
+    let mut writer = dataset::create_writer(&args.output, candidates_schema())?;
+    for chunk in candidates.chunks(16384) {
+        writer.write(&candidates_batch(&args.wiki, chunk, &titles)?)?;
+    }
+    writer.close()?;
+
+    for candidate in candidates.iter().take(args.show) {
+        println!(
+            "{:.3}  {} {}: {} without {}",
+            candidate.score,
+            title_of(&titles, candidate.src_article),
+            candidate.week,
+            describe_items(&candidate.antecedent, &titles),
+            describe_items(&candidate.missing, &titles),
+        );
+    }
+    tracing::info!(
+        "wrote {} candidates to {} in {:.2?}",
+        candidates.len(),
+        args.output.display(),
+        start.elapsed()
+    );
+    Ok(())
+}
+
+/// Checks every rule of one article against each of its weeks.
+fn article_candidates(
+    src_article: i64,
+    intervals: &[LinkInterval],
+    rules: &[Rule],
+    from: Timestamp,
+    to: Timestamp,
+    tolerance: SignedDuration,
+) -> Vec<Candidate> {
+    let weeks = dataset::weekly_events(intervals, from, to);
+
+    // consequent edits may fall into a neighbouring week, so keep
+    // all occurrences across the whole period
+    let mut occurrences = HashMap::<Item, Vec<Timestamp>>::default();
+    for events in weeks.values() {
+        for event in events {
+            occurrences
+                .entry(Item {
+                    action: event.action,
+                    src_article,
+                    dst_article: event.dst_article,
+                })
+                .or_default()
+                .push(event.timestamp);
+        }
+    }
+    let occurs_between = |item: &Item, start: Timestamp, end: Timestamp| {
+        occurrences.get(item).is_some_and(|timestamps| {
+            timestamps
+                .iter()
+                .any(|timestamp| (start..=end).contains(timestamp))
+        })
+    };
+
+    let mut candidates = Vec::new();
+    let mut in_week = HashMap::<Item, (Timestamp, Timestamp)>::default();
+    for (week, events) in &weeks {
+        in_week.clear();
+        for event in events {
+            let item = Item {
+                action: event.action,
+                src_article,
+                dst_article: event.dst_article,
+            };
+            // events are sorted by time
+            in_week
+                .entry(item)
+                .and_modify(|(_, last)| *last = event.timestamp)
+                .or_insert((event.timestamp, event.timestamp));
+        }
+
+        for rule in rules {
+            let Some(times) = rule
+                .antecedent
+                .iter()
+                .map(|item| in_week.get(item).copied())
+                .collect::<Option<Vec<_>>>()
+            else {
+                continue;
+            };
+            let start = times.iter().map(|(first, _)| *first).min().unwrap() - tolerance;
+            let end = times.iter().map(|(_, last)| *last).max().unwrap() + tolerance;
+
+            let missing: Vec<Item> = rule
+                .consequent
+                .iter()
+                .filter(|item| !occurs_between(item, start, end))
+                .copied()
+                .collect();
+            if !missing.is_empty() {
+                candidates.push(Candidate {
+                    src_article,
+                    week: *week,
+                    antecedent: rule.antecedent.clone(),
+                    score: rule.confidence * missing.len() as f64 / rule.consequent.len() as f64,
+                    missing,
+                    confidence: rule.confidence,
+                    freq: rule.freq,
+                });
+            }
+        }
+    }
+    candidates
+}
+
+fn title_of(titles: &HashMap<i64, CompactString>, id: i64) -> String {
+    match titles.get(&id) {
+        Some(title) => title.to_string(),
+        None => format!("#{id}"),
+    }
+}
+
+/// Spells out items with article titles, e.g. `+bus|-tram`.
+fn describe_items(items: &[Item], titles: &HashMap<i64, CompactString>) -> String {
+    items
+        .iter()
+        .map(|item| {
+            format!(
+                "{}{}",
+                item.action.as_str(),
+                title_of(titles, item.dst_article)
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("|")
+}
+
+fn candidates_schema() -> Schema {
+    Schema::new(vec![
+        Field::new("wiki", DataType::Utf8, false),
+        Field::new("src_article", DataType::Int64, false),
+        Field::new("title", DataType::Utf8, true),
+        Field::new("week", DataType::Utf8, false),
+        Field::new("antecedent", DataType::Utf8, false),
+        Field::new("missing", DataType::Utf8, false),
+        Field::new("antecedent_titles", DataType::Utf8, false),
+        Field::new("missing_titles", DataType::Utf8, false),
+        Field::new("confidence", DataType::Float64, false),
+        Field::new("freq", DataType::Int64, false),
+        Field::new("score", DataType::Float64, false),
+    ])
+}
+
+fn candidates_batch(
+    wiki: &str,
+    candidates: &[Candidate],
+    titles: &HashMap<i64, CompactString>,
+) -> anyhow::Result<RecordBatch> {
+    let mut wikis = StringBuilder::new();
+    let mut src_articles = Int64Builder::with_capacity(candidates.len());
+    let mut article_titles = StringBuilder::new();
+    let mut weeks = StringBuilder::new();
+    let mut antecedents = StringBuilder::new();
+    let mut missings = StringBuilder::new();
+    let mut antecedent_titles = StringBuilder::new();
+    let mut missing_titles = StringBuilder::new();
+    let mut confidences = Float64Builder::with_capacity(candidates.len());
+    let mut freqs = Int64Builder::with_capacity(candidates.len());
+    let mut scores = Float64Builder::with_capacity(candidates.len());
+    for candidate in candidates {
+        wikis.append_value(wiki);
+        src_articles.append_value(candidate.src_article);
+        article_titles.append_option(titles.get(&candidate.src_article));
+        weeks.append_value(candidate.week.to_string());
+        antecedents.append_value(patterns::format_items(&candidate.antecedent));
+        missings.append_value(patterns::format_items(&candidate.missing));
+        antecedent_titles.append_value(describe_items(&candidate.antecedent, titles));
+        missing_titles.append_value(describe_items(&candidate.missing, titles));
+        confidences.append_value(candidate.confidence);
+        freqs.append_value(candidate.freq as i64);
+        scores.append_value(candidate.score);
+    }
+    Ok(RecordBatch::try_new(
+        SchemaRef::new(candidates_schema()),
+        vec![
+            Arc::new(wikis.finish()),
+            Arc::new(src_articles.finish()),
+            Arc::new(article_titles.finish()),
+            Arc::new(weeks.finish()),
+            Arc::new(antecedents.finish()),
+            Arc::new(missings.finish()),
+            Arc::new(antecedent_titles.finish()),
+            Arc::new(missing_titles.finish()),
+            Arc::new(confidences.finish()),
+            Arc::new(freqs.finish()),
+            Arc::new(scores.finish()),
+        ],
+    )?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dataset::Action;
+
+    #[test]
+    fn test_article_candidates() {
+        let ts = |s: &str| s.parse::<Timestamp>().unwrap();
+        let item = |action, dst_article| Item {
+            action,
+            src_article: 1,
+            dst_article,
+        };
+        // adding 2 usually comes with adding 3
+        let rules = vec![Rule {
+            src_article: 1,
+            antecedent: vec![item(Action::Add, 2)],
+            consequent: vec![item(Action::Add, 3)],
+            freq: 10,
+            antecedent_freq: 10,
+            confidence: 1.0,
+        }];
+        let intervals = vec![
+            // complete: 3 is added the next week, within the tolerance
+            LinkInterval {
+                dst_article: 2,
+                created_at: ts("2024-03-10T12:00:00Z"),
+                removed_at: Some(ts("2024-06-01T00:00:00Z")),
+            },
+            LinkInterval {
+                dst_article: 3,
+                created_at: ts("2024-03-11T12:00:00Z"),
+                removed_at: None,
+            },
+            // partial: 2 is added again without 3
+            LinkInterval {
+                dst_article: 2,
+                created_at: ts("2024-08-01T00:00:00Z"),
+                removed_at: None,
+            },
+        ];
+
+        let candidates = article_candidates(
+            1,
+            &intervals,
+            &rules,
+            ts("2023-01-01T00:00:00Z"),
+            ts("2025-01-01T00:00:00Z"),
+            SignedDuration::from_hours(48),
+        );
+        assert_eq!(candidates.len(), 1);
+        assert_eq!(candidates[0].week.to_string(), "2024-31");
+        assert_eq!(candidates[0].missing, vec![item(Action::Add, 3)]);
+    }
+}
@@ -0,0 +1,662 @@
+//! Frequent link edit pattern mining, replacing the Spark FPGrowth
+//! run in `02_pattern_mining.ipynb`.
+//!
+//! As in the notebook, each (article, week) is a transaction of
+//! `+→src→dst` / `-→src→dst` items. Since every item names its
+//! source article, items of different articles never occur in the
+//! same transaction, and so no frequent itemset spans articles. We
+//! exploit that by running FP-growth separately for every article,
+//! which bounds memory by the history of the largest article
+//! instead of the whole dataset.
+
+use crate::dataset::{self, Action, LinkInterval, Period};
+use crate::week::IsoWeek;
+use anyhow::{Context, bail};
+use arrow::array::{Float64Builder, Int64Builder, RecordBatch, StringBuilder};
+use arrow::datatypes::{DataType, Field, Float64Type, Int64Type, Schema, SchemaRef};
+use foldhash::HashMap;
+use jiff::Timestamp;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Instant;
+
+const BATCH_SIZE: usize = 16384;
+
+/// Largest itemsets we mine. The notebooks only use pairs and triples.
+const MAX_PATTERN_LEN: usize = 3;
+
+pub const RULES_FILE: &str = "pattern_rules.parquet";
+
+#[derive(Debug, clap::Args)]
+pub struct MinePatternsArgs {
+    /// Link intervals written by `postprocess-to-parquet`.
+    #[clap(long, default_value = dataset::LINKS_PATH)]
+    links: PathBuf,
+    /// Only mine these articles, given as the `article_id` column of a
+    /// Parquet file such as `top10k_typed_pages.parquet`.
+    #[clap(long)]
+    articles: Option<PathBuf>,
+    /// Wiki to mine, by database name.
+    #[clap(long, default_value = dataset::DEFAULT_WIKI)]
+    wiki: String,
+    #[clap(long, default_value = "data")]
+    output_dir: PathBuf,
+    /// Minimum fraction of all transactions an itemset must occur in.
+    #[clap(long, default_value_t = 0.0001)]
+    min_support: f64,
+    /// Absolute minimum number of transactions, overriding `--min-support`.
+    #[clap(long)]
+    min_count: Option<u64>,
+    /// Minimum confidence of the association rules written out.
+    #[clap(long, default_value_t = 0.5)]
+    min_confidence: f64,
+    /// Skip transactions with fewer items.
+    #[clap(long, default_value_t = 3)]
+    min_transaction_items: usize,
+    /// Skip transactions with more items. Bounds the number of
+    /// itemsets a single transaction can contribute.
+    #[clap(long, default_value_t = 30)]
+    max_transaction_items: usize,
+    /// Number of most frequent pairs to write to `patterns_size2.parquet`.
+    #[clap(long, default_value_t = 1000)]
+    top_pairs: usize,
+    /// Number of most frequent triples to write to `patterns_size3.parquet`.
+    #[clap(long, default_value_t = 500)]
+    top_triples: usize,
+    #[clap(flatten)]
+    period: Period,
+}
+
+/// A single link edit, `{action}→{src_article}→{dst_article}`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Item {
+    pub action: Action,
+    pub src_article: i64,
+    pub dst_article: i64,
+}
+
+impl fmt::Display for Item {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}→{}→{}",
+            self.action.as_str(),
+            self.src_article,
+            self.dst_article
+        )
+    }
+}
+
+impl FromStr for Item {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.trim().split('→');
+        let (Some(action), Some(src_article), Some(dst_article), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            bail!("item {s:?} is not of the form action→src→dst");
+        };
+        let action = match action {
+            "+" => Action::Add,
+            "-" => Action::Remove,
+            _ => bail!("unknown action in item {s:?}"),
+        };
+        Ok(Self {
+            action,
+            src_article: src_article.parse().context("bad source article")?,
+            dst_article: dst_article.parse().context("bad target article")?,
+        })
+    }
+}
+
+/// Joins items the way the notebooks do (`concat_ws('|', items)`).
+pub fn format_items(items: &[Item]) -> String {
+    let mut items: Vec<String> = items.iter().map(Item::to_string).collect();
+    items.sort_unstable();
+    items.join("|")
+}
+
+pub fn parse_items(s: &str) -> anyhow::Result<Vec<Item>> {
+    s.split('|').map(Item::from_str).collect()
+}
+
+/// The distinct link edits of one article in each ISO week
+/// (the notebook's `collect_set("item")`).
+pub fn article_transactions(
+    src_article: i64,
+    intervals: &[LinkInterval],
+    from: Timestamp,
+    to: Timestamp,
+) -> Vec<(IsoWeek, Vec<Item>)> {
+    dataset::weekly_events(intervals, from, to)
+        .into_iter()
+        .map(|(week, events)| {
+            let mut items: Vec<Item> = events
+                .into_iter()
+                .map(|event| Item {
+                    action: event.action,
+                    src_article,
+                    dst_article: event.dst_article,
+                })
+                .collect();
+            items.sort_unstable();
+            items.dedup();
+            (week, items)
+        })
+        .collect()
+}
+
+pub fn mine_patterns(args: &MinePatternsArgs) -> anyhow::Result<()> {
+    let start = Instant::now();
+    let (from, to) = args.period.bounds()?;
+    let articles = args
+        .articles
+        .as_ref()
+        .map(|path| dataset::read_id_set(path, "article_id", &args.wiki))
+        .transpose()?;
+
+    let transactions_of = |src_article: i64, intervals: &[LinkInterval]| {
+        if articles
+            .as_ref()
+            .is_some_and(|articles| !articles.contains(&src_article))
+        {
+            return Vec::new();
+        }
+        let mut transactions = article_transactions(src_article, intervals, from, to);
+        transactions.retain(|(_, items)| {
+            (args.min_transaction_items..=args.max_transaction_items).contains(&items.len())
+        });
+        transactions
+    };
+
+    // first pass: count transactions to turn the relative support into a count
+    let min_count = match args.min_count {
+        Some(min_count) => min_count,
+        None => {
+            let mut num_transactions = 0u64;
+            for group in dataset::link_intervals(&args.links, &args.wiki)? {
+                let (src_article, intervals) = group?;
+                num_transactions += transactions_of(src_article, &intervals).len() as u64;
+            }
+            tracing::info!("{num_transactions} transactions");
+            ((args.min_support * num_transactions as f64).ceil() as u64).max(2)
+        }
+    };
+    tracing::info!("mining itemsets occurring in at least {min_count} transactions");
+
+    let mut top_pairs = TopPatterns::new(args.top_pairs);
+    let mut top_triples = TopPatterns::new(args.top_triples);
+    let mut rules_writer =
+        dataset::create_writer(args.output_dir.join(RULES_FILE), rules_schema())?;
+    let mut rules = Vec::with_capacity(BATCH_SIZE);
+    let mut num_rules = 0;
+
+    for group in dataset::link_intervals(&args.links, &args.wiki)? {
+        let (src_article, intervals) = group?;
+        let transactions = transactions_of(src_article, &intervals);
+        if (transactions.len() as u64) < min_count {
+            continue;
+        }
+
+        // FP-growth works on dense per-article item IDs
+        let mut item_ids = HashMap::<Item, u32>::default();
+        let mut items = Vec::new();
+        let encoded = transactions
+            .into_iter()
+            .map(|(_, transaction)| {
+                let ids = transaction
+                    .into_iter()
+                    .map(|item| {
+                        *item_ids.entry(item).or_insert_with(|| {
+                            items.push(item);
+                            items.len() as u32 - 1
+                        })
+                    })
+                    .collect();
+                (ids, 1)
+            })
+            .collect();
+
+        let itemsets = fp_growth(encoded, min_count, MAX_PATTERN_LEN);
+        for (itemset, freq) in &itemsets {
+            let decoded: Vec<Item> = itemset.iter().map(|&id| items[id as usize]).collect();
+            match itemset.len() {
+                2 => top_pairs.push(*freq, &decoded),
+                3 => top_triples.push(*freq, &decoded),
+                _ => {}
+            }
+        }
+        rules.extend(association_rules(&items, &itemsets, args.min_confidence));
+
+        if rules.len() >= BATCH_SIZE {
+            rules_writer.write(&rules_batch(&args.wiki, &rules)?)?;
+            num_rules += rules.len();
+            rules.clear();
+        }
+    }
+    if !rules.is_empty() {
+        rules_writer.write(&rules_batch(&args.wiki, &rules)?)?;
+        num_rules += rules.len();
+    }
+    rules_writer.close()?;
+
+    for (top, file) in [
+        (top_pairs, "patterns_size2.parquet"),
+        (top_triples, "patterns_size3.parquet"),
+    ] {
+        let patterns = top.into_sorted_vec();
+        let mut writer = dataset::create_writer(args.output_dir.join(file), patterns_schema())?;
+        writer.write(&patterns_batch(&args.wiki, &patterns)?)?;
+        writer.close()?;
+        tracing::info!("wrote {} patterns to {file}", patterns.len());
+    }
+
+    tracing::info!("wrote {num_rules} rules to {RULES_FILE}");
+    tracing::info!("finished in {:.2?}", start.elapsed());
+    Ok(())
+}
+
+/// Keeps the `limit` most frequent patterns seen so far.
+struct TopPatterns {
+    limit: usize,
+    heap: BinaryHeap<Reverse<(u64, String)>>,
+}
+
+impl TopPatterns {
+    fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            heap: BinaryHeap::with_capacity(limit + 1),
+        }
+    }
+
+    fn push(&mut self, freq: u64, items: &[Item]) {
+        if self.heap.len() >= self.limit
+            && self
+                .heap
+                .peek()
+                .is_none_or(|Reverse((min_freq, _))| freq <= *min_freq)
+        {
+            return;
+        }
+        self.heap.push(Reverse((freq, format_items(items))));
+        if self.heap.len() > self.limit {
+            self.heap.pop();
+        }
+    }
+
+    /// Patterns by descending frequency.
+    fn into_sorted_vec(self) -> Vec<(u64, String)> {
+        self.heap
+            .into_sorted_vec()
+            .into_iter()
+            .map(|Reverse(pattern)| pattern)
+            .collect()
+    }
+}
+
+/// An association rule `antecedent ⇒ consequent` between link edits
+/// of the same article.
+#[derive(Debug, Clone)]
+pub struct Rule {
+    pub src_article: i64,
+    pub antecedent: Vec<Item>,
+    pub consequent: Vec<Item>,
+    /// Number of transactions containing all items of the rule.
+    pub freq: u64,
+    /// Number of transactions containing the antecedent.
+    pub antecedent_freq: u64,
+    pub confidence: f64,
+}
+
+fn association_rules(
+    items: &[Item],
+    itemsets: &[(Vec<u32>, u64)],
+    min_confidence: f64,
+) -> Vec<Rule> {
+    let support: HashMap<&[u32], u64> = itemsets
+        .iter()
+        .map(|(itemset, freq)| (itemset.as_slice(), *freq))
+        .collect();
+
+    let mut rules = Vec::new();
+    let mut antecedent = Vec::new();
+    let mut consequent = Vec::new();
+    for (itemset, freq) in itemsets.iter().filter(|(itemset, _)| itemset.len() >= 2) {
+        // every non-empty proper subset is a possible antecedent
+        for mask in 1..(1u32 << itemset.len()) - 1 {
+            antecedent.clear();
+            consequent.clear();
+            for (i, &item) in itemset.iter().enumerate() {
+                if mask & (1 << i) != 0 {
+                    antecedent.push(item);
+                } else {
+                    consequent.push(item);
+                }
+            }
+            // subsets of frequent itemsets are frequent, so this always exists
+            let antecedent_freq = support[antecedent.as_slice()];
+            let confidence = *freq as f64 / antecedent_freq as f64;
+            if confidence >= min_confidence {
+                let decode = |ids: &[u32]| ids.iter().map(|&id| items[id as usize]).collect();
+                rules.push(Rule {
+                    src_article: items[itemset[0] as usize].src_article,
+                    antecedent: decode(&antecedent),
+                    consequent: decode(&consequent),
+                    freq: *freq,
+                    antecedent_freq,
+                    confidence,
+                });
+            }
+        }
+    }
+    rules
+}
+
+/// Finds all itemsets of at most `max_len` items that occur in at
+/// least `min_count` of the weighted `transactions` using FP-growth.
+/// Items within each returned itemset are sorted.
+pub fn fp_growth(
+    transactions: Vec<(Vec<u32>, u64)>,
+    min_count: u64,
+    max_len: usize,
+) -> Vec<(Vec<u32>, u64)> {
+    let mut itemsets = Vec::new();
+    FpTree::build(&transactions, min_count).mine(
+        min_count,
+        max_len,
+        &mut Vec::new(),
+        &mut itemsets,
+    );
+    for (itemset, _) in &mut itemsets {
+        itemset.sort_unstable();
+    }
+    itemsets
+}
+
+struct FpNode {
+    item: u32,
+    count: u64,
+    parent: usize,
+    children: Vec<usize>,
+}
+
+struct HeaderEntry {
+    item: u32,
+    support: u64,
+    nodes: Vec<usize>,
+}
+
+struct FpTree {
+    /// `nodes[0]` is the root.
+    nodes: Vec<FpNode>,
+    /// Frequent items by descending support.
+    header: Vec<HeaderEntry>,
+}
+
+impl FpTree {
+    fn build(transactions: &[(Vec<u32>, u64)], min_count: u64) -> Self {
+        let mut support = HashMap::<u32, u64>::default();
+        for (items, count) in transactions {
+            for &item in items {
+                *support.entry(item).or_default() += count;
+            }
+        }
+        let mut header: Vec<HeaderEntry> = support
+            .into_iter()
+            .filter(|&(_, support)| support >= min_count)
+            .map(|(item, support)| HeaderEntry {
+                item,
+                support,
+                nodes: Vec::new(),
+            })
+            .collect();
+        header.sort_unstable_by(|a, b| b.support.cmp(&a.support).then(a.item.cmp(&b.item)));
+        let rank: HashMap<u32, usize> = header
+            .iter()
+            .enumerate()
+            .map(|(rank, entry)| (entry.item, rank))
+            .collect();
+
+        let mut tree = FpTree {
+            nodes: vec![FpNode {
+                item: u32::MAX,
+                count: 0,
+                parent: 0,
+                children: Vec::new(),
+            }],
+            header,
+        };
+        let mut path = Vec::new();
+        for (items, count) in transactions {
+            path.clear();
+            path.extend(items.iter().filter_map(|item| rank.get(item).copied()));
+            path.sort_unstable();
+            path.dedup();
+
+            let mut node = 0;
+            for &rank in &path {
+                let item = tree.header[rank].item;
+                let child = tree.nodes[node]
+                    .children
+                    .iter()
+                    .copied()
+                    .find(|&child| tree.nodes[child].item == item);
+                node = match child {
+                    Some(child) => child,
+                    None => {
+                        let child = tree.nodes.len();
+                        tree.nodes.push(FpNode {
+                            item,
+                            count: 0,
+                            parent: node,
+                            children: Vec::new(),
+                        });
+                        tree.nodes[node].children.push(child);
+                        tree.header[rank].nodes.push(child);
+                        child
+                    }
+                };
+                tree.nodes[node].count += count;
+            }
+        }
+        tree
+    }
+
+    fn mine(
+        &self,
+        min_count: u64,
+        max_len: usize,
+        suffix: &mut Vec<u32>,
+        itemsets: &mut Vec<(Vec<u32>, u64)>,
+    ) {
+        for entry in self.header.iter().rev() {
+            suffix.push(entry.item);
+            itemsets.push((suffix.clone(), entry.support));
+            if suffix.len() < max_len {
+                let prefix_paths: Vec<(Vec<u32>, u64)> = entry
+                    .nodes
+                    .iter()
+                    .map(|&node| (self.prefix_path(node), self.nodes[node].count))
+                    .filter(|(path, _)| !path.is_empty())
+                    .collect();
+                let conditional = FpTree::build(&prefix_paths, min_count);
+                if !conditional.header.is_empty() {
+                    conditional.mine(min_count, max_len, suffix, itemsets);
+                }
+            }
+            suffix.pop();
+        }
+    }
+
+    /// Items on the path from the root to the parent of `node`.
+    fn prefix_path(&self, node: usize) -> Vec<u32> {
+        let mut path = Vec::new();
+        let mut node = self.nodes[node].parent;
+        while node != 0 {
+            path.push(self.nodes[node].item);
+            node = self.nodes[node].parent;
+        }
+        path
+    }
+}
+
+fn patterns_schema() -> Schema {
+    Schema::new(vec![
+        Field::new("wiki", DataType::Utf8, false),
+        Field::new("pattern", DataType::Utf8, false),
+        Field::new("freq", DataType::Int64, false),
+    ])
+}
+
+fn patterns_batch(wiki: &str, patterns: &[(u64, String)]) -> anyhow::Result<RecordBatch> {
+    let mut wikis = StringBuilder::new();
+    let mut pattern_strs = StringBuilder::new();
+    let mut freqs = Int64Builder::with_capacity(patterns.len());
+    for (freq, pattern) in patterns {
+        wikis.append_value(wiki);
+        pattern_strs.append_value(pattern);
+        freqs.append_value(*freq as i64);
+    }
+    Ok(RecordBatch::try_new(
+        SchemaRef::new(patterns_schema()),
+        vec![
+            Arc::new(wikis.finish()),
+            Arc::new(pattern_strs.finish()),
+            Arc::new(freqs.finish()),
+        ],
+    )?)
+}
+
+/// Reads the rules of `wiki` written by `mine-patterns`, grouped by
+/// source article and skipping those below `min_confidence`.
+pub fn read_rules(
+    path: impl AsRef<Path>,
+    wiki: &str,
+    min_confidence: f64,
+) -> anyhow::Result<HashMap<i64, Vec<Rule>>> {
+    let mut rules = HashMap::<i64, Vec<Rule>>::default();
+    for batch in dataset::open_reader(path)? {
+        let batch = batch?;
+        let wikis = dataset::WikiColumn::new(&batch)?;
+        let src_articles = dataset::primitive_column::<Int64Type>(&batch, "src_article")?;
+        let antecedents = dataset::string_column(&batch, "antecedent")?;
+        let consequents = dataset::string_column(&batch, "consequent")?;
+        let freqs = dataset::primitive_column::<Int64Type>(&batch, "freq")?;
+        let antecedent_freqs = dataset::primitive_column::<Int64Type>(&batch, "antecedent_freq")?;
+        let confidences = dataset::primitive_column::<Float64Type>(&batch, "confidence")?;
+        for i in 0..batch.num_rows() {
+            if wikis.value(i) != wiki || confidences.value(i) < min_confidence {
+                continue;
+            }
+            rules.entry(src_articles.value(i)).or_default().push(Rule {
+                src_article: src_articles.value(i),
+                antecedent: parse_items(antecedents.value(i))?,
+                consequent: parse_items(consequents.value(i))?,
+                freq: freqs.value(i) as u64,
+                antecedent_freq: antecedent_freqs.value(i) as u64,
+                confidence: confidences.value(i),
+            });
+        }
+    }
+    Ok(rules)
+}
+
+fn rules_schema() -> Schema {
+    Schema::new(vec![
+        Field::new("wiki", DataType::Utf8, false),
+        Field::new("src_article", DataType::Int64, false),
+        Field::new("antecedent", DataType::Utf8, false),
+        Field::new("consequent", DataType::Utf8, false),
+        Field::new("freq", DataType::Int64, false),
+        Field::new("antecedent_freq", DataType::Int64, false),
+        Field::new("confidence", DataType::Float64, false),
+    ])
+}
+
+fn rules_batch(wiki: &str, rules: &[Rule]) -> anyhow::Result<RecordBatch> {
+    let mut wikis = StringBuilder::new();
+    let mut src_articles = Int64Builder::with_capacity(rules.len());
+    let mut antecedents = StringBuilder::new();
+    let mut consequents = StringBuilder::new();
+    let mut freqs = Int64Builder::with_capacity(rules.len());
+    let mut antecedent_freqs = Int64Builder::with_capacity(rules.len());
+    let mut confidences = Float64Builder::with_capacity(rules.len());
+    for rule in rules {
+        wikis.append_value(wiki);
+        src_articles.append_value(rule.src_article);
+        antecedents.append_value(format_items(&rule.antecedent));
+        consequents.append_value(format_items(&rule.consequent));
+        freqs.append_value(rule.freq as i64);
+        antecedent_freqs.append_value(rule.antecedent_freq as i64);
+        confidences.append_value(rule.confidence);
+    }
+    Ok(RecordBatch::try_new(
+        SchemaRef::new(rules_schema()),
+        vec![
+            Arc::new(wikis.finish()),
+            Arc::new(src_articles.finish()),
+            Arc::new(antecedents.finish()),
+            Arc::new(consequents.finish()),
+            Arc::new(freqs.finish()),
+            Arc::new(antecedent_freqs.finish()),
+            Arc::new(confidences.finish()),
+        ],
+    )?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use foldhash::HashSet;
+
+    #[test]
+    fn test_fp_growth() {
+        // Han et al.'s example with items f=0, c=1, a=2, b=3, m=4, p=5
+        let transactions = vec![
+            vec![0, 2, 1, 4, 5],
+            vec![2, 3, 1, 0, 4],
+            vec![3, 0],
+            vec![1, 3, 5],
+            vec![2, 0, 1, 5, 4],
+        ];
+        let itemsets: HashSet<(Vec<u32>, u64)> =
+            fp_growth(transactions.into_iter().map(|t| (t, 1)).collect(), 3, 3)
+                .into_iter()
+                .collect();
+
+        for expected in [
+            (vec![0], 4),
+            (vec![1], 4),
+            (vec![2], 3),
+            (vec![3], 3),
+            (vec![4], 3),
+            (vec![5], 3),
+            (vec![0, 1], 3),
+            (vec![0, 2], 3),
+            (vec![1, 2], 3),
+            (vec![1, 5], 3),
+            (vec![0, 4], 3),
+            (vec![0, 1, 2], 3),
+            (vec![1, 2, 4], 3),
+        ] {
+            assert!(itemsets.contains(&expected), "missing {expected:?}");
+        }
+        // {f, c, a, m} is frequent but longer than the limit
+        assert!(itemsets.iter().all(|(itemset, _)| itemset.len() <= 3));
+        assert!(!itemsets.iter().any(|(itemset, _)| itemset == &vec![0, 3]));
+    }
+
+    #[test]
+    fn test_item_roundtrip() {
+        let item: Item = "-→12→345".parse().unwrap();
+        assert_eq!(item.action, Action::Remove);
+        assert_eq!(item.to_string(), "-→12→345");
+        assert!("+→12".parse::<Item>().is_err());
+    }
+}
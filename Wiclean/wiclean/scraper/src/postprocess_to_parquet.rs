@@ -1,82 +1,168 @@
-use crate::ingest::temp_db::{ARTICLES_TABLE, TempArticle, TempDb};
-use arrow::array::{Int64Builder, RecordBatch, StringBuilder, TimestampSecondBuilder};
-use arrow::datatypes::{DataType, Field, Schema, SchemaRef, TimeUnit};
-use bincode::Options;
+use crate::blanking::{self, FlagKind};
+use crate::bursts;
+use crate::dataset;
+use crate::ingest::parser;
+use crate::ingest::temp_db::{ARTICLES_TABLE, Reference, TempArticle, TempArticleRevision, TempDb};
+use arrow::array::{
+    BooleanBuilder, Int64Builder, RecordBatch, StringBuilder, TimestampSecondBuilder,
+};
+use arrow::datatypes::{DataType, Field, Int64Type, Schema, SchemaRef, TimeUnit};
 use compact_str::CompactString;
 use dashmap::DashMap;
 use foldhash::{HashMap, HashSet};
-use jiff::Timestamp;
+use jiff::{SignedDuration, Timestamp};
 use parquet::arrow::ArrowWriter;
-use parquet::basic::{Compression, ZstdLevel};
-use parquet::file::properties::WriterProperties;
 use pbr::ProgressBar;
 use rayon::prelude::*;
 use redb::ReadableTableMetadata;
 use std::cell::{Cell, RefCell};
-use std::fs::File;
+use std::fs::{self, File};
+use std::hash::Hash;
+use std::mem;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
 use std::thread;
 use std::time::Instant;
 use zstd::bulk::Decompressor;
 
-type ArticleIdTable = DashMap<CompactString, i64, foldhash::fast::RandomState>;
+/// Article IDs by wiki and normalized title. Each wiki has its own ID
+/// space, so rows are identified by `wiki` and `id` together.
+#[derive(Default)]
+struct ArticleIdTable {
+    ids: DashMap<(CompactString, CompactString), i64, foldhash::fast::RandomState>,
+    next_ids: DashMap<CompactString, AtomicI64, foldhash::fast::RandomState>,
+}
 
-pub fn postprocess_to_parquet(temp_db: &TempDb) -> anyhow::Result<()> {
-    let start = Instant::now();
-    let batch_size = 16384;
+impl ArticleIdTable {
+    fn get_or_assign(&self, wiki: &str, title: CompactString) -> i64 {
+        *self
+            .ids
+            .entry((wiki.into(), title))
+            .or_insert_with(|| self.next_id(wiki))
+    }
 
-    let articles_file = File::create("data/articles.parquet")?;
-    let links_file = File::create("data/links.parquet")?;
+    fn next_id(&self, wiki: &str) -> i64 {
+        // counters are created once per wiki and then only read, so
+        // workers do not contend for the shard lock
+        if let Some(next_id) = self.next_ids.get(wiki) {
+            return next_id.fetch_add(1, Ordering::Relaxed);
+        }
+        self.next_ids
+            .entry(wiki.into())
+            .or_default()
+            .fetch_add(1, Ordering::Relaxed)
+    }
 
-    let props = WriterProperties::builder()
-        .set_compression(Compression::ZSTD(ZstdLevel::try_new(3).unwrap()))
-        .build();
+    fn insert(&self, wiki: &str, title: CompactString, id: i64) {
+        self.ids.insert((wiki.into(), title), id);
+        self.next_ids
+            .entry(wiki.into())
+            .or_default()
+            .fetch_max(id + 1, Ordering::Relaxed);
+    }
 
-    let mut articles_writer = ArrowWriter::try_new(
-        articles_file,
-        SchemaRef::new(articles_schema()),
-        Some(props.clone()),
-    )?;
-    let mut links_writer = ArrowWriter::try_new(
-        links_file,
-        SchemaRef::new(links_schema()),
-        Some(props.clone()),
-    )?;
+    fn len(&self) -> usize {
+        self.ids.len()
+    }
+}
+
+#[derive(Debug, clap::Args)]
+pub struct PostprocessArgs {
+    /// Only re-encode the articles changed by `ingest-incremental`
+    /// since the last run, writing them to a delta directory. Article
+    /// IDs are kept stable by reading the ID table of earlier runs.
+    #[clap(long)]
+    incremental: bool,
+    /// Directory of the delta directories written by incremental runs.
+    #[clap(long, default_value = "data/deltas")]
+    delta_dir: PathBuf,
+    #[clap(flatten)]
+    thresholds: Thresholds,
+}
+
+/// Thresholds of the detectors run on the revisions of each article.
+#[derive(Debug, Clone, Copy, clap::Args)]
+pub struct Thresholds {
+    /// Flag revisions that delete more than this fraction of an
+    /// article's text as blanking.
+    #[clap(long, default_value_t = 0.5)]
+    blanking_fraction: f64,
+    /// Longest pause between two edits of a burst, in minutes.
+    #[clap(long, default_value_t = 10)]
+    burst_gap_minutes: i64,
+    /// Fewest consecutive edits by one user that make a burst.
+    #[clap(long, default_value_t = 3)]
+    burst_min_edits: usize,
+}
+
+impl Default for Thresholds {
+    /// The command line defaults.
+    fn default() -> Self {
+        Self {
+            blanking_fraction: 0.5,
+            burst_gap_minutes: 10,
+            burst_min_edits: 3,
+        }
+    }
+}
+
+pub fn postprocess_to_parquet(temp_db: &TempDb, args: &PostprocessArgs) -> anyhow::Result<()> {
+    if args.incremental {
+        postprocess_incremental(temp_db, &args.delta_dir, args.thresholds)
+    } else {
+        postprocess_full(temp_db, args.thresholds)
+    }
+}
+
+fn postprocess_full(temp_db: &TempDb, thresholds: Thresholds) -> anyhow::Result<()> {
+    let start = Instant::now();
+    let batch_size = 16384;
+
+    let mut writers = OutputWriters::create(Path::new("data"))?;
 
     let (data_tx, data_rx) = flume::bounded(4);
 
+    let wikis = temp_db.wikis()?;
+    tracing::info!("postprocessing {}", wikis.join(", "));
     for i in 0..32 {
         thread::spawn({
             let temp_db = temp_db.clone();
+            let wikis = wikis.clone();
             let data_tx = data_tx.clone();
             move || {
                 let tx = temp_db.db().begin_read().unwrap();
                 let articles_table = tx.open_table(ARTICLES_TABLE).unwrap();
 
-                let start = u8::MAX / 32 * i;
-                let mut iter = if i == 31 {
-                    articles_table.range((&[start][..])..).unwrap()
-                } else {
-                    let end = u8::MAX / 32 * (i + 1);
-                    articles_table.range((&[start][..])..(&[end][..])).unwrap()
-                };
+                // each thread reads the same slice of the titles of every wiki
+                for wiki in wikis {
+                    let (prefix, wiki_end) = TempDb::key_range(&wiki);
+                    let start = [prefix.as_slice(), &[u8::MAX / 32 * i]].concat();
+                    let end = if i == 31 {
+                        wiki_end
+                    } else {
+                        [prefix.as_slice(), &[u8::MAX / 32 * (i + 1)]].concat()
+                    };
+                    let mut iter = articles_table
+                        .range(start.as_slice()..end.as_slice())
+                        .unwrap();
 
-                loop {
-                    let mut done = false;
-                    let mut batch = Vec::with_capacity(batch_size);
-                    while batch.len() < batch_size {
-                        let Some(next) = iter.next() else {
-                            done = true;
-                            break;
-                        };
-                        batch.push(next.unwrap().1.value().to_vec());
-                    }
+                    loop {
+                        let mut done = false;
+                        let mut batch = Vec::with_capacity(batch_size);
+                        while batch.len() < batch_size {
+                            let Some(next) = iter.next() else {
+                                done = true;
+                                break;
+                            };
+                            batch.push((wiki.clone(), next.unwrap().1.value().to_vec()));
+                        }
 
-                    data_tx.send(batch).unwrap();
+                        data_tx.send(batch).unwrap();
 
-                    if done {
-                        break;
+                        if done {
+                            break;
+                        }
                     }
                 }
             }
@@ -85,8 +171,7 @@ pub fn postprocess_to_parquet(temp_db: &TempDb) -> anyhow::Result<()> {
 
     drop(data_tx);
 
-    let article_id_table: ArticleIdTable = DashMap::default();
-    let next_article_id = AtomicU64::new(0);
+    let article_id_table = ArticleIdTable::default();
     let num_articles = Arc::new(AtomicU64::new(0));
     let num_links = Arc::new(AtomicU64::new(0));
 
@@ -105,73 +190,24 @@ pub fn postprocess_to_parquet(temp_db: &TempDb) -> anyhow::Result<()> {
         let num_links = num_links.clone();
         move || {
             for delta_encoded in delta_encoded_batch_rx {
-                let mut article_ids = Int64Builder::with_capacity(delta_encoded.len());
-                let mut article_titles =
-                    StringBuilder::with_capacity(delta_encoded.len(), delta_encoded.len() * 32);
-
-                let mut link_src_articles = Int64Builder::with_capacity(delta_encoded.len());
-                let mut link_dst_articles = Int64Builder::with_capacity(delta_encoded.len());
-                let mut link_created_ats =
-                    TimestampSecondBuilder::with_capacity(delta_encoded.len());
-                let mut link_deleted_ats =
-                    TimestampSecondBuilder::with_capacity(delta_encoded.len());
-                let mut link_created_by_users = Int64Builder::with_capacity(delta_encoded.len());
-                let mut link_deleted_by_users = Int64Builder::with_capacity(delta_encoded.len());
-
-                for delta_encoded in delta_encoded {
-                    article_ids.append_value(delta_encoded.id);
-                    article_titles.append_value(delta_encoded.title.to_string());
-                    num_articles.fetch_add(1, Ordering::Relaxed);
-                    num_links.fetch_add(delta_encoded.links.len() as u64, Ordering::Relaxed);
-
-                    for link in delta_encoded.links {
-                        link_src_articles.append_value(delta_encoded.id);
-                        link_dst_articles.append_value(link.dst_article);
-                        link_created_ats.append_value(link.created_at.as_second());
-                        link_created_by_users.append_value(link.created_by_user);
-                        match link.removed_at {
-                            Some(r) => link_deleted_ats.append_value(r.as_second()),
-                            None => link_deleted_ats.append_null(),
-                        }
-                        match link.deleted_by_user {
-                            Some(u) => link_deleted_by_users.append_value(u),
-                            None => link_deleted_by_users.append_null(),
-                        }
-                    }
-                }
-
-                let article_batch = RecordBatch::try_new(
-                    SchemaRef::new(articles_schema()),
-                    vec![
-                        Arc::new(article_ids.finish()),
-                        Arc::new(article_titles.finish()),
-                    ],
-                )
-                .unwrap();
-                let links_batch = RecordBatch::try_new(
-                    SchemaRef::new(links_schema()),
-                    vec![
-                        Arc::new(link_src_articles.finish()),
-                        Arc::new(link_dst_articles.finish()),
-                        Arc::new(link_created_ats.finish()),
-                        Arc::new(link_created_by_users.finish()),
-                        Arc::new(link_deleted_ats.finish()),
-                        Arc::new(link_deleted_by_users.finish()),
-                    ],
-                )
-                .unwrap();
-                articles_writer.write(&article_batch).unwrap();
-                links_writer.write(&links_batch).unwrap();
+                num_articles.fetch_add(delta_encoded.len() as u64, Ordering::Relaxed);
+                num_links.fetch_add(
+                    delta_encoded
+                        .iter()
+                        .map(|article| article.links.len() as u64)
+                        .sum(),
+                    Ordering::Relaxed,
+                );
+                writers.write(&delta_encoded).unwrap();
             }
 
-            articles_writer.close().unwrap();
-            links_writer.close().unwrap();
+            writers.close().unwrap();
         }
     });
 
     for batch_data in data_rx {
         let delta_encoded = batch_data.par_iter()
-            .filter_map(|data| {
+            .filter_map(|(wiki, data)| {
                 thread_local! {
                     static DECOMPRESSOR: RefCell<Decompressor<'static>> = RefCell::new(Decompressor::new().unwrap());
                 }
@@ -184,14 +220,13 @@ pub fn postprocess_to_parquet(temp_db: &TempDb) -> anyhow::Result<()> {
                     uncompressed_data.reserve(size);
                     d.borrow_mut().decompress_to_buffer(&data, &mut uncompressed_data).unwrap();
                 });
-                let article: TempArticle = bincode::options().deserialize(&uncompressed_data).unwrap();
+                let article = temp_db.deserialize_article(&uncompressed_data).unwrap();
                 uncompressed_data.clear();
                 uncompressed_data.shrink_to(256 * 1024 * 1024);
 
-                if !article.title.is_empty() && article.title.chars().next().unwrap().is_ascii_alphanumeric() {
                 DECOMPRESS_BUF.with(move |c| c.set(uncompressed_data));
-                Some(delta_encode(article, &article_id_table, &next_article_id))
-                } else { None }
+                is_postprocessed(&article.title)
+                    .then(|| delta_encode(article, wiki, &article_id_table, thresholds))
             }).collect::<Vec<_>>();
         delta_encoded_batch_tx.send(delta_encoded).unwrap();
         pbr.add(batch_data.len() as u64);
@@ -200,6 +235,10 @@ pub fn postprocess_to_parquet(temp_db: &TempDb) -> anyhow::Result<()> {
     drop(delta_encoded_batch_tx);
     writer_thread.join().unwrap();
 
+    write_article_ids(&article_id_table)?;
+    // a full run covers every change merged so far
+    temp_db.clear_dirty(None)?;
+
     tracing::info!(
         "{}M articles, {}M links",
         num_articles.load(Ordering::Relaxed) / 1_000_000,
@@ -210,8 +249,530 @@ pub fn postprocess_to_parquet(temp_db: &TempDb) -> anyhow::Result<()> {
     Ok(())
 }
 
+/// Re-encodes the articles marked dirty by `ingest-incremental` into
+/// the same files as a full run, in a new directory under
+/// `delta_dir`. Each delta holds the full link history of its
+/// articles and supersedes their rows in earlier files.
+fn postprocess_incremental(
+    temp_db: &TempDb,
+    delta_dir: &Path,
+    thresholds: Thresholds,
+) -> anyhow::Result<()> {
+    let start = Instant::now();
+    anyhow::ensure!(
+        Path::new(dataset::ARTICLE_IDS_PATH).exists(),
+        "{} is missing, run a full postprocess-to-parquet first",
+        dataset::ARTICLE_IDS_PATH
+    );
+    let titles = temp_db.dirty_titles()?;
+    if titles.is_empty() {
+        tracing::info!("no articles changed since the last run");
+        return Ok(());
+    }
+    tracing::info!("postprocessing {} changed articles", titles.len());
+
+    let article_id_table = read_article_ids(dataset::ARTICLE_IDS_PATH)?;
+    let known_ids = article_id_table.len();
+
+    let delta_encoded = titles
+        .par_iter()
+        .map(|(wiki, title)| Ok((wiki, temp_db.for_wiki(wiki).get_article(title)?)))
+        .filter_map(|article| match article {
+            Ok((wiki, Some(article))) if is_postprocessed(&article.title) => {
+                Some(Ok((wiki, article)))
+            }
+            Ok(_) => None,
+            Err(e) => Some(Err(e)),
+        })
+        .map(|article: anyhow::Result<_>| {
+            let (wiki, article) = article?;
+            Ok(delta_encode(article, wiki, &article_id_table, thresholds))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let num_articles = delta_encoded.len();
+    let num_links: usize = delta_encoded
+        .iter()
+        .map(|article| article.links.len())
+        .sum();
+
+    let dir = delta_dir.join(Timestamp::now().strftime("%Y%m%dT%H%M%SZ").to_string());
+    fs::create_dir_all(&dir)?;
+    let mut writers = OutputWriters::create(&dir)?;
+    writers.write(&delta_encoded)?;
+    writers.close()?;
+
+    if article_id_table.len() > known_ids {
+        write_article_ids(&article_id_table)?;
+    }
+    temp_db.clear_dirty(Some(&titles))?;
+
+    tracing::info!(
+        "{num_articles} articles, {num_links} links, {} new IDs, written to {}",
+        article_id_table.len() - known_ids,
+        dir.display()
+    );
+    tracing::info!("finished in {:.2?}", start.elapsed());
+    Ok(())
+}
+
+/// Whether an article is written to Parquet; special pages and titles
+/// starting with punctuation are skipped. Titles of other wikis may
+/// start with any letter, such as `Österreich`.
+fn is_postprocessed(title: &str) -> bool {
+    title
+        .chars()
+        .next()
+        .is_some_and(|first| first.is_alphanumeric())
+}
+
+/// Writers of the Parquet files of one postprocess run.
+struct OutputWriters {
+    articles: ArrowWriter<File>,
+    links: ArrowWriter<File>,
+    categories: ArrowWriter<File>,
+    article_types: ArrowWriter<File>,
+    infobox_fields: ArrowWriter<File>,
+    references: ArrowWriter<File>,
+    revisions: ArrowWriter<File>,
+    blankings: ArrowWriter<File>,
+    bursts: ArrowWriter<File>,
+}
+
+impl OutputWriters {
+    fn create(dir: &Path) -> anyhow::Result<Self> {
+        Ok(Self {
+            articles: dataset::create_writer(dir.join("articles.parquet"), articles_schema())?,
+            links: dataset::create_writer(dir.join("links.parquet"), links_schema())?,
+            categories: dataset::create_writer(
+                dir.join("categories.parquet"),
+                string_intervals_schema("category"),
+            )?,
+            article_types: dataset::create_writer(
+                dir.join("article_types.parquet"),
+                string_intervals_schema("type"),
+            )?,
+            infobox_fields: dataset::create_writer(
+                dir.join("infobox_fields.parquet"),
+                infobox_fields_schema(),
+            )?,
+            references: dataset::create_writer(
+                dir.join("references.parquet"),
+                references_schema(),
+            )?,
+            revisions: dataset::create_writer(dir.join("revisions.parquet"), revisions_schema())?,
+            blankings: dataset::create_writer(dir.join("blankings.parquet"), blankings_schema())?,
+            bursts: dataset::create_writer(dir.join("bursts.parquet"), bursts_schema())?,
+        })
+    }
+
+    fn write(&mut self, delta_encoded: &[DeltaEncodedArticle]) -> anyhow::Result<()> {
+        self.articles.write(&articles_batch(delta_encoded)?)?;
+        self.links.write(&links_batch(delta_encoded)?)?;
+        self.categories.write(&string_intervals_batch(
+            string_intervals_schema("category"),
+            delta_encoded,
+            |article| &article.categories,
+        )?)?;
+        self.article_types.write(&string_intervals_batch(
+            string_intervals_schema("type"),
+            delta_encoded,
+            |article| &article.types,
+        )?)?;
+        self.infobox_fields
+            .write(&infobox_fields_batch(delta_encoded)?)?;
+        self.references.write(&references_batch(delta_encoded)?)?;
+        self.revisions.write(&revisions_batch(delta_encoded)?)?;
+        self.blankings.write(&blankings_batch(delta_encoded)?)?;
+        self.bursts.write(&bursts_batch(delta_encoded)?)?;
+        Ok(())
+    }
+
+    fn close(self) -> anyhow::Result<()> {
+        self.articles.close()?;
+        self.links.close()?;
+        self.categories.close()?;
+        self.article_types.close()?;
+        self.infobox_fields.close()?;
+        self.references.close()?;
+        self.revisions.close()?;
+        self.blankings.close()?;
+        self.bursts.close()?;
+        Ok(())
+    }
+}
+
+fn articles_batch(delta_encoded: &[DeltaEncodedArticle]) -> anyhow::Result<RecordBatch> {
+    let mut wikis = StringBuilder::with_capacity(delta_encoded.len(), delta_encoded.len() * 6);
+    let mut ids = Int64Builder::with_capacity(delta_encoded.len());
+    let mut titles = StringBuilder::with_capacity(delta_encoded.len(), delta_encoded.len() * 32);
+    let mut display_titles =
+        StringBuilder::with_capacity(delta_encoded.len(), delta_encoded.len() * 32);
+    for article in delta_encoded {
+        wikis.append_value(&article.wiki);
+        ids.append_value(article.id);
+        titles.append_value(&article.title);
+        display_titles.append_value(&article.display_title);
+    }
+    Ok(RecordBatch::try_new(
+        SchemaRef::new(articles_schema()),
+        vec![
+            Arc::new(wikis.finish()),
+            Arc::new(ids.finish()),
+            Arc::new(titles.finish()),
+            Arc::new(display_titles.finish()),
+        ],
+    )?)
+}
+
+fn links_batch(delta_encoded: &[DeltaEncodedArticle]) -> anyhow::Result<RecordBatch> {
+    let mut wikis = StringBuilder::with_capacity(delta_encoded.len(), delta_encoded.len() * 6);
+    let mut src_articles = Int64Builder::with_capacity(delta_encoded.len());
+    let mut dst_articles = Int64Builder::with_capacity(delta_encoded.len());
+    let mut created_ats = TimestampSecondBuilder::with_capacity(delta_encoded.len());
+    let mut deleted_ats = TimestampSecondBuilder::with_capacity(delta_encoded.len());
+    let mut created_by_users = Int64Builder::with_capacity(delta_encoded.len());
+    let mut deleted_by_users = Int64Builder::with_capacity(delta_encoded.len());
+
+    for article in delta_encoded {
+        for link in &article.links {
+            wikis.append_value(&article.wiki);
+            src_articles.append_value(article.id);
+            dst_articles.append_value(link.item);
+            created_ats.append_value(link.added_at.as_second());
+            created_by_users.append_value(link.added_by_user);
+            deleted_ats.append_option(link.removed_at.map(|r| r.as_second()));
+            deleted_by_users.append_option(link.removed_by_user);
+        }
+    }
+
+    Ok(RecordBatch::try_new(
+        SchemaRef::new(links_schema()),
+        vec![
+            Arc::new(wikis.finish()),
+            Arc::new(src_articles.finish()),
+            Arc::new(dst_articles.finish()),
+            Arc::new(created_ats.finish()),
+            Arc::new(created_by_users.finish()),
+            Arc::new(deleted_ats.finish()),
+            Arc::new(deleted_by_users.finish()),
+        ],
+    )?)
+}
+
+/// Rows of a file of named intervals, such as the categories of each
+/// article, in the layout of [`string_intervals_schema`].
+fn string_intervals_batch(
+    schema: Schema,
+    delta_encoded: &[DeltaEncodedArticle],
+    intervals: impl Fn(&DeltaEncodedArticle) -> &[Interval<CompactString>],
+) -> anyhow::Result<RecordBatch> {
+    let mut wikis = StringBuilder::new();
+    let mut articles = Int64Builder::new();
+    let mut items = StringBuilder::new();
+    let mut added_ats = TimestampSecondBuilder::new();
+    let mut added_by_users = Int64Builder::new();
+    let mut removed_ats = TimestampSecondBuilder::new();
+    let mut removed_by_users = Int64Builder::new();
+
+    for article in delta_encoded {
+        for interval in intervals(article) {
+            wikis.append_value(&article.wiki);
+            articles.append_value(article.id);
+            items.append_value(&interval.item);
+            added_ats.append_value(interval.added_at.as_second());
+            added_by_users.append_value(interval.added_by_user);
+            removed_ats.append_option(interval.removed_at.map(|r| r.as_second()));
+            removed_by_users.append_option(interval.removed_by_user);
+        }
+    }
+
+    Ok(RecordBatch::try_new(
+        SchemaRef::new(schema),
+        vec![
+            Arc::new(wikis.finish()),
+            Arc::new(articles.finish()),
+            Arc::new(items.finish()),
+            Arc::new(added_ats.finish()),
+            Arc::new(added_by_users.finish()),
+            Arc::new(removed_ats.finish()),
+            Arc::new(removed_by_users.finish()),
+        ],
+    )?)
+}
+
+fn infobox_fields_batch(delta_encoded: &[DeltaEncodedArticle]) -> anyhow::Result<RecordBatch> {
+    let mut wikis = StringBuilder::new();
+    let mut articles = Int64Builder::new();
+    let mut fields = StringBuilder::new();
+    let mut values = StringBuilder::new();
+    let mut set_ats = TimestampSecondBuilder::new();
+    let mut set_by_users = Int64Builder::new();
+    let mut replaced_ats = TimestampSecondBuilder::new();
+    let mut replaced_by_users = Int64Builder::new();
+    let mut restoreds = BooleanBuilder::new();
+
+    let mut seen = HashSet::default();
+    for article in delta_encoded {
+        // intervals are in the order their values were set
+        seen.clear();
+        for interval in &article.infobox_fields {
+            let (field, value) = &interval.item;
+            wikis.append_value(&article.wiki);
+            articles.append_value(article.id);
+            fields.append_value(field);
+            values.append_value(value);
+            set_ats.append_value(interval.added_at.as_second());
+            set_by_users.append_value(interval.added_by_user);
+            replaced_ats.append_option(interval.removed_at.map(|r| r.as_second()));
+            replaced_by_users.append_option(interval.removed_by_user);
+            restoreds.append_value(!seen.insert(&interval.item));
+        }
+    }
+
+    Ok(RecordBatch::try_new(
+        SchemaRef::new(infobox_fields_schema()),
+        vec![
+            Arc::new(wikis.finish()),
+            Arc::new(articles.finish()),
+            Arc::new(fields.finish()),
+            Arc::new(values.finish()),
+            Arc::new(set_ats.finish()),
+            Arc::new(set_by_users.finish()),
+            Arc::new(replaced_ats.finish()),
+            Arc::new(replaced_by_users.finish()),
+            Arc::new(restoreds.finish()),
+        ],
+    )?)
+}
+
+fn references_batch(delta_encoded: &[DeltaEncodedArticle]) -> anyhow::Result<RecordBatch> {
+    let mut wikis = StringBuilder::new();
+    let mut articles = Int64Builder::new();
+    let mut kinds = StringBuilder::new();
+    let mut references = StringBuilder::new();
+    let mut added_ats = TimestampSecondBuilder::new();
+    let mut added_by_users = Int64Builder::new();
+    let mut removed_ats = TimestampSecondBuilder::new();
+    let mut removed_by_users = Int64Builder::new();
+
+    for article in delta_encoded {
+        for reference in &article.references {
+            wikis.append_value(&article.wiki);
+            articles.append_value(article.id);
+            kinds.append_value(reference.item.kind());
+            references.append_value(reference.item.identity());
+            added_ats.append_value(reference.added_at.as_second());
+            added_by_users.append_value(reference.added_by_user);
+            removed_ats.append_option(reference.removed_at.map(|r| r.as_second()));
+            removed_by_users.append_option(reference.removed_by_user);
+        }
+    }
+
+    Ok(RecordBatch::try_new(
+        SchemaRef::new(references_schema()),
+        vec![
+            Arc::new(wikis.finish()),
+            Arc::new(articles.finish()),
+            Arc::new(kinds.finish()),
+            Arc::new(references.finish()),
+            Arc::new(added_ats.finish()),
+            Arc::new(added_by_users.finish()),
+            Arc::new(removed_ats.finish()),
+            Arc::new(removed_by_users.finish()),
+        ],
+    )?)
+}
+
+fn revisions_batch(delta_encoded: &[DeltaEncodedArticle]) -> anyhow::Result<RecordBatch> {
+    let mut wikis = StringBuilder::new();
+    let mut articles = Int64Builder::new();
+    let mut revision_ids = Int64Builder::new();
+    let mut timestamps = TimestampSecondBuilder::new();
+    let mut user_ids = Int64Builder::new();
+    let mut bytes_addeds = Int64Builder::new();
+    let mut citations_addeds = Int64Builder::new();
+    let mut unsourced_additions = Int64Builder::new();
+
+    for article in delta_encoded {
+        for revision in &article.revisions {
+            wikis.append_value(&article.wiki);
+            articles.append_value(article.id);
+            revision_ids.append_value(revision.id as i64);
+            timestamps.append_value(revision.timestamp.as_second());
+            user_ids.append_value(revision.user_id);
+            bytes_addeds.append_option(revision.bytes_added);
+            citations_addeds.append_option(revision.citations_added);
+            unsourced_additions.append_option(revision.unsourced_addition());
+        }
+    }
+
+    Ok(RecordBatch::try_new(
+        SchemaRef::new(revisions_schema()),
+        vec![
+            Arc::new(wikis.finish()),
+            Arc::new(articles.finish()),
+            Arc::new(revision_ids.finish()),
+            Arc::new(timestamps.finish()),
+            Arc::new(user_ids.finish()),
+            Arc::new(bytes_addeds.finish()),
+            Arc::new(citations_addeds.finish()),
+            Arc::new(unsourced_additions.finish()),
+        ],
+    )?)
+}
+
+fn blankings_batch(delta_encoded: &[DeltaEncodedArticle]) -> anyhow::Result<RecordBatch> {
+    let mut wikis = StringBuilder::new();
+    let mut articles = Int64Builder::new();
+    let mut revision_ids = Int64Builder::new();
+    let mut timestamps = TimestampSecondBuilder::new();
+    let mut user_ids = Int64Builder::new();
+    let mut kinds = StringBuilder::new();
+    let mut bytes_befores = Int64Builder::new();
+    let mut bytes_afters = Int64Builder::new();
+    let mut redirect_targets = StringBuilder::new();
+    let mut reverted_ats = TimestampSecondBuilder::new();
+    let mut reverted_by_users = Int64Builder::new();
+
+    for article in delta_encoded {
+        for blanking in &article.blankings {
+            wikis.append_value(&article.wiki);
+            articles.append_value(article.id);
+            revision_ids.append_value(blanking.revision_id as i64);
+            timestamps.append_value(blanking.timestamp.as_second());
+            user_ids.append_value(blanking.user_id);
+            kinds.append_value(blanking.kind.as_str());
+            bytes_befores.append_value(blanking.bytes_before as i64);
+            bytes_afters.append_value(blanking.bytes_after as i64);
+            redirect_targets.append_option(blanking.redirect_target.as_deref());
+            reverted_ats.append_option(blanking.reverted_at.map(|r| r.as_second()));
+            reverted_by_users.append_option(blanking.reverted_by_user);
+        }
+    }
+
+    Ok(RecordBatch::try_new(
+        SchemaRef::new(blankings_schema()),
+        vec![
+            Arc::new(wikis.finish()),
+            Arc::new(articles.finish()),
+            Arc::new(revision_ids.finish()),
+            Arc::new(timestamps.finish()),
+            Arc::new(user_ids.finish()),
+            Arc::new(kinds.finish()),
+            Arc::new(bytes_befores.finish()),
+            Arc::new(bytes_afters.finish()),
+            Arc::new(redirect_targets.finish()),
+            Arc::new(reverted_ats.finish()),
+            Arc::new(reverted_by_users.finish()),
+        ],
+    )?)
+}
+
+fn bursts_batch(delta_encoded: &[DeltaEncodedArticle]) -> anyhow::Result<RecordBatch> {
+    let mut wikis = StringBuilder::new();
+    let mut articles = Int64Builder::new();
+    let mut user_ids = Int64Builder::new();
+    let mut first_revision_ids = Int64Builder::new();
+    let mut last_revision_ids = Int64Builder::new();
+    let mut started_ats = TimestampSecondBuilder::new();
+    let mut ended_ats = TimestampSecondBuilder::new();
+    let mut edits = Int64Builder::new();
+    let mut link_churns = Int64Builder::new();
+    let mut reverted_ats = TimestampSecondBuilder::new();
+    let mut reverted_by_users = Int64Builder::new();
+
+    for article in delta_encoded {
+        for burst in &article.bursts {
+            wikis.append_value(&article.wiki);
+            articles.append_value(article.id);
+            user_ids.append_value(burst.user_id);
+            first_revision_ids.append_value(burst.first_revision_id as i64);
+            last_revision_ids.append_value(burst.last_revision_id as i64);
+            started_ats.append_value(burst.started_at.as_second());
+            ended_ats.append_value(burst.ended_at.as_second());
+            edits.append_value(burst.edits as i64);
+            link_churns.append_value(burst.link_churn as i64);
+            reverted_ats.append_option(burst.reverted_at.map(|r| r.as_second()));
+            reverted_by_users.append_option(burst.reverted_by_user);
+        }
+    }
+
+    Ok(RecordBatch::try_new(
+        SchemaRef::new(bursts_schema()),
+        vec![
+            Arc::new(wikis.finish()),
+            Arc::new(articles.finish()),
+            Arc::new(user_ids.finish()),
+            Arc::new(first_revision_ids.finish()),
+            Arc::new(last_revision_ids.finish()),
+            Arc::new(started_ats.finish()),
+            Arc::new(ended_ats.finish()),
+            Arc::new(edits.finish()),
+            Arc::new(link_churns.finish()),
+            Arc::new(reverted_ats.finish()),
+            Arc::new(reverted_by_users.finish()),
+        ],
+    )?)
+}
+
+/// Writes every assigned ID, including those of link targets without
+/// an article of their own, so later incremental runs can reuse them.
+fn write_article_ids(id_table: &ArticleIdTable) -> anyhow::Result<()> {
+    let mut writer = dataset::create_writer(dataset::ARTICLE_IDS_PATH, article_ids_schema())?;
+    let entries: Vec<((CompactString, CompactString), i64)> = id_table
+        .ids
+        .iter()
+        .map(|entry| (entry.key().clone(), *entry.value()))
+        .collect();
+    for chunk in entries.chunks(1 << 20) {
+        let mut wikis = StringBuilder::with_capacity(chunk.len(), chunk.len() * 6);
+        let mut ids = Int64Builder::with_capacity(chunk.len());
+        let mut titles = StringBuilder::with_capacity(chunk.len(), chunk.len() * 32);
+        for ((wiki, title), id) in chunk {
+            wikis.append_value(wiki);
+            ids.append_value(*id);
+            titles.append_value(title);
+        }
+        let batch = RecordBatch::try_new(
+            SchemaRef::new(article_ids_schema()),
+            vec![
+                Arc::new(wikis.finish()),
+                Arc::new(ids.finish()),
+                Arc::new(titles.finish()),
+            ],
+        )?;
+        writer.write(&batch)?;
+    }
+    writer.close()?;
+    Ok(())
+}
+
+fn read_article_ids(path: &str) -> anyhow::Result<ArticleIdTable> {
+    let id_table = ArticleIdTable::default();
+    for batch in dataset::open_reader(path)? {
+        let batch = batch?;
+        let wikis = dataset::WikiColumn::new(&batch)?;
+        let ids = dataset::primitive_column::<Int64Type>(&batch, "id")?;
+        let titles = dataset::string_column(&batch, "title")?;
+        for i in 0..batch.num_rows() {
+            id_table.insert(wikis.value(i), titles.value(i).into(), ids.value(i));
+        }
+    }
+    Ok(id_table)
+}
+
 fn articles_schema() -> Schema {
     Schema::new(vec![
+        Field::new("wiki", DataType::Utf8, false),
+        Field::new("id", DataType::Int64, false),
+        Field::new("title", DataType::Utf8, false),
+        Field::new("display_title", DataType::Utf8, false),
+    ])
+}
+
+fn article_ids_schema() -> Schema {
+    Schema::new(vec![
+        Field::new("wiki", DataType::Utf8, false),
         Field::new("id", DataType::Int64, false),
         Field::new("title", DataType::Utf8, false),
     ])
@@ -219,6 +780,7 @@ fn articles_schema() -> Schema {
 
 fn links_schema() -> Schema {
     Schema::new(vec![
+        Field::new("wiki", DataType::Utf8, false),
         Field::new("src_article", DataType::Int64, false),
         Field::new("dst_article", DataType::Int64, false),
         Field::new(
@@ -236,101 +798,694 @@ fn links_schema() -> Schema {
     ])
 }
 
+/// Schema of the intervals during which an article had a named item,
+/// e.g. a `category` or an infobox `type`. A null `removed_at` means
+/// the article still has it.
+fn string_intervals_schema(item: &str) -> Schema {
+    Schema::new(vec![
+        Field::new("wiki", DataType::Utf8, false),
+        Field::new("article", DataType::Int64, false),
+        Field::new(item, DataType::Utf8, false),
+        Field::new(
+            "added_at",
+            DataType::Timestamp(TimeUnit::Second, None),
+            false,
+        ),
+        Field::new("added_by_user", DataType::Int64, false),
+        Field::new(
+            "removed_at",
+            DataType::Timestamp(TimeUnit::Second, None),
+            true,
+        ),
+        Field::new("removed_by_user", DataType::Int64, true),
+    ])
+}
+
+/// Schema of the values each infobox field held. `restored` marks
+/// values the field held before, so fields flipping back and forth
+/// have several restored rows.
+fn infobox_fields_schema() -> Schema {
+    Schema::new(vec![
+        Field::new("wiki", DataType::Utf8, false),
+        Field::new("article", DataType::Int64, false),
+        Field::new("field", DataType::Utf8, false),
+        Field::new("value", DataType::Utf8, false),
+        Field::new("set_at", DataType::Timestamp(TimeUnit::Second, None), false),
+        Field::new("set_by_user", DataType::Int64, false),
+        Field::new(
+            "replaced_at",
+            DataType::Timestamp(TimeUnit::Second, None),
+            true,
+        ),
+        Field::new("replaced_by_user", DataType::Int64, true),
+        Field::new("restored", DataType::Boolean, false),
+    ])
+}
+
+/// Schema of the intervals during which an article cited a source.
+/// `kind` is `ref` for `<ref>` tags, identified by URL or cite
+/// template, and `external_link` for other external links.
+fn references_schema() -> Schema {
+    Schema::new(vec![
+        Field::new("wiki", DataType::Utf8, false),
+        Field::new("article", DataType::Int64, false),
+        Field::new("kind", DataType::Utf8, false),
+        Field::new("reference", DataType::Utf8, false),
+        Field::new(
+            "added_at",
+            DataType::Timestamp(TimeUnit::Second, None),
+            false,
+        ),
+        Field::new("added_by_user", DataType::Int64, false),
+        Field::new(
+            "removed_at",
+            DataType::Timestamp(TimeUnit::Second, None),
+            true,
+        ),
+        Field::new("removed_by_user", DataType::Int64, true),
+    ])
+}
+
+/// Schema of the revisions of each article, with what they changed
+/// relative to their parent. The metrics are null for the first
+/// revision we have of an article and for revisions ingested before
+/// text lengths were kept.
+fn revisions_schema() -> Schema {
+    Schema::new(vec![
+        Field::new("wiki", DataType::Utf8, false),
+        Field::new("article", DataType::Int64, false),
+        Field::new("revision_id", DataType::Int64, false),
+        Field::new(
+            "timestamp",
+            DataType::Timestamp(TimeUnit::Second, None),
+            false,
+        ),
+        Field::new("user_id", DataType::Int64, false),
+        Field::new("bytes_added", DataType::Int64, true),
+        Field::new("citations_added", DataType::Int64, true),
+        Field::new("unsourced_addition", DataType::Int64, true),
+    ])
+}
+
+/// Schema of the revisions flagged by [`blanking::detect`]. `kind` is
+/// `blanking` or `redirect`, and `reverted_at` is null for flagged
+/// revisions that were not reverted.
+fn blankings_schema() -> Schema {
+    Schema::new(vec![
+        Field::new("wiki", DataType::Utf8, false),
+        Field::new("article", DataType::Int64, false),
+        Field::new("revision_id", DataType::Int64, false),
+        Field::new(
+            "timestamp",
+            DataType::Timestamp(TimeUnit::Second, None),
+            false,
+        ),
+        Field::new("user_id", DataType::Int64, false),
+        Field::new("kind", DataType::Utf8, false),
+        Field::new("bytes_before", DataType::Int64, false),
+        Field::new("bytes_after", DataType::Int64, false),
+        Field::new("redirect_target", DataType::Utf8, true),
+        Field::new(
+            "reverted_at",
+            DataType::Timestamp(TimeUnit::Second, None),
+            true,
+        ),
+        Field::new("reverted_by_user", DataType::Int64, true),
+    ])
+}
+
+/// Schema of the edit bursts found by [`bursts::detect`].
+/// `reverted_at` is null for bursts that were not reverted.
+fn bursts_schema() -> Schema {
+    Schema::new(vec![
+        Field::new("wiki", DataType::Utf8, false),
+        Field::new("article", DataType::Int64, false),
+        Field::new("user_id", DataType::Int64, false),
+        Field::new("first_revision_id", DataType::Int64, false),
+        Field::new("last_revision_id", DataType::Int64, false),
+        Field::new(
+            "started_at",
+            DataType::Timestamp(TimeUnit::Second, None),
+            false,
+        ),
+        Field::new(
+            "ended_at",
+            DataType::Timestamp(TimeUnit::Second, None),
+            false,
+        ),
+        Field::new("edits", DataType::Int64, false),
+        Field::new("link_churn", DataType::Int64, false),
+        Field::new(
+            "reverted_at",
+            DataType::Timestamp(TimeUnit::Second, None),
+            true,
+        ),
+        Field::new("reverted_by_user", DataType::Int64, true),
+    ])
+}
+
 struct DeltaEncodedArticle {
+    wiki: CompactString,
+    /// Normalized title, the key of the article ID.
     title: CompactString,
+    /// Title as written, which the temp DB is keyed by.
+    display_title: CompactString,
     id: i64,
-    links: Vec<DeltaEncodedLink>,
+    /// Links by target article ID.
+    links: Vec<Interval<i64>>,
+    /// Category memberships by category name.
+    categories: Vec<Interval<CompactString>>,
+    /// Infobox types, at most one at a time.
+    types: Vec<Interval<CompactString>>,
+    /// Values of infobox fields by field name and value.
+    infobox_fields: Vec<Interval<(CompactString, CompactString)>>,
+    /// Citations and external links.
+    references: Vec<Interval<Reference>>,
+    /// Revisions, oldest first.
+    revisions: Vec<RevisionMetrics>,
+    blankings: Vec<Blanking>,
+    bursts: Vec<Burst>,
+}
+
+/// A run of quick consecutive revisions by one user.
+struct Burst {
+    user_id: i64,
+    first_revision_id: u64,
+    last_revision_id: u64,
+    started_at: Timestamp,
+    ended_at: Timestamp,
+    edits: usize,
+    link_churn: usize,
+    reverted_at: Option<Timestamp>,
+    reverted_by_user: Option<i64>,
+}
+
+impl Burst {
+    fn of(
+        revisions: &[TempArticleRevision],
+        max_gap: SignedDuration,
+        min_edits: usize,
+    ) -> Vec<Self> {
+        bursts::detect(revisions, max_gap, min_edits)
+            .into_iter()
+            .map(|run| {
+                let (first, last) = (&revisions[run.first], &revisions[run.last]);
+                let revert = run.reverted_by.map(|i| &revisions[i]);
+                Burst {
+                    user_id: first.user_id,
+                    first_revision_id: first.id,
+                    last_revision_id: last.id,
+                    started_at: first.timestamp,
+                    ended_at: last.timestamp,
+                    edits: run.edits(),
+                    link_churn: run.link_churn,
+                    reverted_at: revert.map(|revert| revert.timestamp),
+                    reverted_by_user: revert.map(|revert| revert.user_id),
+                }
+            })
+            .collect()
+    }
+}
+
+/// A revision flagged as blanking or redirect hijacking.
+struct Blanking {
+    revision_id: u64,
+    timestamp: Timestamp,
+    user_id: i64,
+    kind: FlagKind,
+    bytes_before: u64,
+    bytes_after: u64,
+    redirect_target: Option<CompactString>,
+    reverted_at: Option<Timestamp>,
+    reverted_by_user: Option<i64>,
 }
 
-struct DeltaEncodedLink {
-    dst_article: i64,
-    created_by_user: i64,
-    deleted_by_user: Option<i64>,
-    created_at: Timestamp,
+impl Blanking {
+    fn of(revisions: &[TempArticleRevision], blanking_fraction: f64) -> Vec<Self> {
+        blanking::detect(revisions, blanking_fraction)
+            .into_iter()
+            .map(|flag| {
+                let revision = &revisions[flag.revision];
+                let revert = flag.reverted_by.map(|i| &revisions[i]);
+                Blanking {
+                    revision_id: revision.id,
+                    timestamp: revision.timestamp,
+                    user_id: revision.user_id,
+                    kind: flag.kind,
+                    bytes_before: revisions[flag.revision - 1].text_len.unwrap_or(0),
+                    bytes_after: revision.text_len.unwrap_or(0),
+                    redirect_target: revision.redirect.clone(),
+                    reverted_at: revert.map(|revert| revert.timestamp),
+                    reverted_by_user: revert.map(|revert| revert.user_id),
+                }
+            })
+            .collect()
+    }
+}
+
+/// What a revision changed relative to its parent, the revision
+/// before it.
+struct RevisionMetrics {
+    id: u64,
+    timestamp: Timestamp,
+    user_id: i64,
+    /// Change in text length, negative if text was removed.
+    bytes_added: Option<i64>,
+    /// `<ref>` citations not in the parent.
+    citations_added: Option<i64>,
+}
+
+impl RevisionMetrics {
+    fn of(revisions: &[TempArticleRevision]) -> Vec<Self> {
+        let parents = std::iter::once(None).chain(revisions.iter().map(Some));
+        parents
+            .zip(revisions)
+            .map(|(parent, revision)| {
+                let parent_len = parent.and_then(|parent| parent.text_len);
+                let (bytes_added, citations_added) = match (parent, parent_len, revision.text_len) {
+                    (Some(parent), Some(parent_len), Some(len)) => {
+                        let citations_added = revision
+                            .references
+                            .iter()
+                            .filter(|reference| {
+                                matches!(reference, Reference::Ref(_))
+                                    && parent.references.binary_search(reference).is_err()
+                            })
+                            .count();
+                        (
+                            Some(len as i64 - parent_len as i64),
+                            Some(citations_added as i64),
+                        )
+                    }
+                    _ => (None, None),
+                };
+                RevisionMetrics {
+                    id: revision.id,
+                    timestamp: revision.timestamp,
+                    user_id: revision.user_id,
+                    bytes_added,
+                    citations_added,
+                }
+            })
+            .collect()
+    }
+
+    /// Bytes added per citation added, so that large insertions
+    /// without any citation score highest. Removals score 0.
+    fn unsourced_addition(&self) -> Option<i64> {
+        Some(self.bytes_added?.max(0) / (1 + self.citations_added?))
+    }
+}
+
+/// A stretch of revisions in which an item, such as a link, was
+/// present in an article.
+struct Interval<T> {
+    item: T,
+    added_at: Timestamp,
+    added_by_user: i64,
     removed_at: Option<Timestamp>,
+    removed_by_user: Option<i64>,
 }
 
-#[derive(Default)]
-struct CachedStructures {
-    link_indexes: HashMap<i64, usize>,
-    current_links: HashSet<i64>,
-    this_revision_links: HashSet<i64>,
+/// Turns the items present in each revision of an article into
+/// intervals. Revisions must be pushed oldest first.
+struct IntervalTracker<T> {
+    intervals: Vec<Interval<T>>,
+    /// Index into `intervals` of each item of the latest revision.
+    current: HashMap<T, usize>,
+    this_revision: HashSet<T>,
+}
+
+impl<T> Default for IntervalTracker<T> {
+    fn default() -> Self {
+        Self {
+            intervals: Vec::new(),
+            current: HashMap::default(),
+            this_revision: HashSet::default(),
+        }
+    }
+}
+
+impl<T: Hash + Eq + Clone> IntervalTracker<T> {
+    fn push_revision(
+        &mut self,
+        timestamp: Timestamp,
+        user_id: i64,
+        items: impl IntoIterator<Item = T>,
+    ) {
+        for item in items {
+            if !self.current.contains_key(&item) {
+                self.intervals.push(Interval {
+                    item: item.clone(),
+                    added_at: timestamp,
+                    added_by_user: user_id,
+                    removed_at: None,
+                    removed_by_user: None,
+                });
+                self.current.insert(item.clone(), self.intervals.len() - 1);
+            }
+            self.this_revision.insert(item);
+        }
+
+        let intervals = &mut self.intervals;
+        let this_revision = &self.this_revision;
+        self.current.retain(|item, &mut i| {
+            if this_revision.contains(item) {
+                true
+            } else {
+                intervals[i].removed_at = Some(timestamp);
+                intervals[i].removed_by_user = Some(user_id);
+                false
+            }
+        });
+        self.this_revision.clear();
+    }
+
+    /// Takes the intervals of the article, keeping the allocations
+    /// for the next one.
+    fn finish(&mut self) -> Vec<Interval<T>> {
+        self.current.clear();
+        self.current.shrink_to(16384);
+        self.this_revision.clear();
+        self.this_revision.shrink_to(4096);
+        mem::take(&mut self.intervals)
+    }
 }
 
 fn delta_encode(
     mut article: TempArticle,
+    wiki: &str,
     id_table: &ArticleIdTable,
-    next_id: &AtomicU64,
+    thresholds: Thresholds,
 ) -> DeltaEncodedArticle {
     thread_local! {
-        static CACHED_STRUCTURES: Cell<CachedStructures> = Cell::new(CachedStructures::default());
+        static LINK_TRACKER: RefCell<IntervalTracker<i64>> = RefCell::default();
+        static CATEGORY_TRACKER: RefCell<IntervalTracker<CompactString>> = RefCell::default();
+        static TYPE_TRACKER: RefCell<IntervalTracker<CompactString>> = RefCell::default();
+        static FIELD_TRACKER: RefCell<IntervalTracker<(CompactString, CompactString)>> =
+            RefCell::default();
+        static REFERENCE_TRACKER: RefCell<IntervalTracker<Reference>> = RefCell::default();
     }
 
-    let CachedStructures {
-        mut link_indexes,
-        mut current_links,
-        mut this_revision_links,
-    } = CACHED_STRUCTURES.with(|c| c.take());
-
     let title = normalize_title(&article.title);
-    let id = *id_table
-        .entry(title.clone())
-        .or_insert_with(|| next_id.fetch_add(1, Ordering::Relaxed) as i64);
-
-    let mut links = Vec::<DeltaEncodedLink>::new();
+    let id = id_table.get_or_assign(wiki, title.clone());
 
     article.revisions.sort_unstable_by_key(|rev| rev.timestamp);
-    for revision in article.revisions {
-        for link in &revision.links {
-            let dst_id = *id_table
-                .entry(normalize_title(link))
-                .or_insert_with(|| next_id.fetch_add(1, Ordering::Relaxed) as i64);
-            if current_links.insert(dst_id) {
-                links.push(DeltaEncodedLink {
-                    dst_article: dst_id,
-                    created_at: revision.timestamp,
-                    removed_at: None,
-                    created_by_user: revision.user_id,
-                    deleted_by_user: None,
-                });
-                link_indexes.insert(dst_id, links.len() - 1);
+    let (links, categories) = LINK_TRACKER.with_borrow_mut(|links| {
+        CATEGORY_TRACKER.with_borrow_mut(|categories| {
+            for revision in &article.revisions {
+                // category links put the article in a category rather
+                // than linking to another article
+                let mut revision_categories = Vec::new();
+                let mut revision_links = Vec::with_capacity(revision.links.len());
+                for link in &revision.links {
+                    match parser::category_name(link) {
+                        Some(category) => revision_categories.push(category),
+                        None => {
+                            revision_links.push(id_table.get_or_assign(wiki, normalize_title(link)))
+                        }
+                    }
+                }
+                links.push_revision(revision.timestamp, revision.user_id, revision_links);
+                categories.push_revision(revision.timestamp, revision.user_id, revision_categories);
             }
-            this_revision_links.insert(dst_id);
-        }
+            (links.finish(), categories.finish())
+        })
+    });
 
-        current_links.retain(|&dst_id| {
-            if !this_revision_links.contains(&dst_id) {
-                links[link_indexes[&dst_id]].removed_at = Some(revision.timestamp);
-                links[link_indexes[&dst_id]].deleted_by_user = Some(revision.user_id);
-                false
-            } else {
-                true
+    let (types, infobox_fields) = TYPE_TRACKER.with_borrow_mut(|types| {
+        FIELD_TRACKER.with_borrow_mut(|fields| {
+            for revision in &article.revisions {
+                types.push_revision(
+                    revision.timestamp,
+                    revision.user_id,
+                    revision.infobox.clone(),
+                );
+                fields.push_revision(
+                    revision.timestamp,
+                    revision.user_id,
+                    revision.infobox_fields.iter().cloned(),
+                );
             }
-        });
-        this_revision_links.clear();
-    }
-
-    link_indexes.clear();
-    link_indexes.shrink_to(16384);
-    current_links.clear();
-    current_links.shrink_to(4096);
-    this_revision_links.clear();
-    this_revision_links.shrink_to(4096);
-    CACHED_STRUCTURES.with(move |c| {
-        c.set(CachedStructures {
-            link_indexes,
-            current_links,
-            this_revision_links,
+            (types.finish(), fields.finish())
         })
     });
 
+    let references = REFERENCE_TRACKER.with_borrow_mut(|references| {
+        for revision in &article.revisions {
+            references.push_revision(
+                revision.timestamp,
+                revision.user_id,
+                revision.references.iter().cloned(),
+            );
+        }
+        references.finish()
+    });
+
+    let revisions = RevisionMetrics::of(&article.revisions);
+    let blankings = Blanking::of(&article.revisions, thresholds.blanking_fraction);
+    let bursts = Burst::of(
+        &article.revisions,
+        SignedDuration::from_mins(thresholds.burst_gap_minutes),
+        thresholds.burst_min_edits,
+    );
+
     if links.len() > 1_000_000 {
         dbg!(&article.title);
     }
 
-    DeltaEncodedArticle { title, id, links }
+    DeltaEncodedArticle {
+        wiki: wiki.into(),
+        title,
+        display_title: article.title,
+        id,
+        links,
+        categories,
+        types,
+        infobox_fields,
+        references,
+        revisions,
+        blankings,
+        bursts,
+    }
 }
 
-fn normalize_title(title: &str) -> CompactString {
+pub fn normalize_title(title: &str) -> CompactString {
     CompactString::from_str_to_lowercase(title.trim())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow::array::{Array, AsArray};
+
+    #[test]
+    fn test_stable_ids() {
+        let id_table = ArticleIdTable::default();
+        id_table.insert("enwiki", "macy's".into(), 4);
+        id_table.insert("enwiki", "new york city".into(), 9);
+        id_table.insert("dewiki", "new york city".into(), 2);
+        let article = || TempArticle {
+            title: "Macy's".into(),
+            revisions: vec![TempArticleRevision {
+                id: 1,
+                ..TempArticleRevision::for_test(
+                    1,
+                    "2024-01-01T00:00:00Z",
+                    &["New York City", "Herald Square"],
+                )
+            }],
+        };
+        let encoded = delta_encode(article(), "enwiki", &id_table, Thresholds::default());
+        assert_eq!(encoded.id, 4);
+        let mut dst: Vec<i64> = encoded.links.iter().map(|link| link.item).collect();
+        dst.sort_unstable();
+        assert_eq!(dst, [9, 10]);
+        assert_eq!(id_table.get_or_assign("enwiki", "herald square".into()), 10);
+
+        // the same title in another wiki gets an ID of that wiki's space
+        let encoded = delta_encode(article(), "dewiki", &id_table, Thresholds::default());
+        assert_eq!(encoded.id, 3);
+        let mut dst: Vec<i64> = encoded.links.iter().map(|link| link.item).collect();
+        dst.sort_unstable();
+        assert_eq!(dst, [2, 4]);
+
+        // dewiki titles starting with a non-ASCII letter are articles too
+        let article = TempArticle {
+            title: "Österreich".into(),
+            ..article()
+        };
+        assert!(is_postprocessed(&article.title));
+        assert!(!is_postprocessed("!Kung"));
+        let encoded = delta_encode(article, "dewiki", &id_table, Thresholds::default());
+        assert_eq!(encoded.id, 5);
+        // the temp DB is keyed by the title as written
+        assert_eq!(encoded.title, "österreich");
+        assert_eq!(encoded.display_title, "Österreich");
+    }
+
+    #[test]
+    fn test_category_intervals() {
+        let id_table = ArticleIdTable::default();
+        let revision = TempArticleRevision::for_test;
+        let article = TempArticle {
+            title: "Macy's".into(),
+            revisions: vec![
+                revision(2, "2024-01-02T00:00:00Z", &["Herald Square"]),
+                revision(
+                    1,
+                    "2024-01-01T00:00:00Z",
+                    &["Category:Department stores", "Herald Square"],
+                ),
+                revision(
+                    1,
+                    "2024-01-03T00:00:00Z",
+                    &["Category:Department_stores", "Herald Square"],
+                ),
+            ],
+        };
+        let encoded = delta_encode(article, "enwiki", &id_table, Thresholds::default());
+        assert_eq!(encoded.links.len(), 1);
+        let categories: Vec<_> = encoded
+            .categories
+            .iter()
+            .map(|category| {
+                (
+                    category.item.as_str(),
+                    category.added_by_user,
+                    category.removed_by_user,
+                )
+            })
+            .collect();
+        assert_eq!(
+            categories,
+            [
+                ("Department stores", 1, Some(2)),
+                ("Department stores", 1, None)
+            ]
+        );
+        assert!(
+            id_table
+                .ids
+                .iter()
+                .all(|entry| !entry.key().1.starts_with("category:"))
+        );
+    }
+
+    #[test]
+    fn test_article_types() {
+        let id_table = ArticleIdTable::default();
+        let revision = |user_id: i64, infobox: Option<&str>| TempArticleRevision {
+            id: user_id as u64,
+            user_id,
+            timestamp: Timestamp::from_second(user_id).unwrap(),
+            infobox: infobox.map(CompactString::from),
+            ..Default::default()
+        };
+        let article = TempArticle {
+            title: "Macy's".into(),
+            revisions: vec![
+                revision(1, Some("company")),
+                revision(2, None),
+                revision(3, Some("company")),
+                revision(4, Some("department store")),
+            ],
+        };
+        let encoded = delta_encode(article, "enwiki", &id_table, Thresholds::default());
+        let types: Vec<_> = encoded
+            .types
+            .iter()
+            .map(|ty| (ty.item.as_str(), ty.added_by_user, ty.removed_by_user))
+            .collect();
+        assert_eq!(
+            types,
+            [
+                ("company", 1, Some(2)),
+                ("company", 3, Some(4)),
+                ("department store", 4, None)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_infobox_fields() {
+        let id_table = ArticleIdTable::default();
+        let revision = |user_id: i64, winner: &str| TempArticleRevision {
+            id: user_id as u64,
+            user_id,
+            timestamp: Timestamp::from_second(user_id).unwrap(),
+            infobox: Some("election".into()),
+            infobox_fields: vec![
+                ("election_date".into(), "14 August 1949".into()),
+                ("winner".into(), winner.into()),
+            ],
+            ..Default::default()
+        };
+        let article = TempArticle {
+            title: "1949 West German federal election".into(),
+            revisions: vec![revision(1, "CDU"), revision(2, "SPD"), revision(3, "CDU")],
+        };
+        let encoded = delta_encode(article, "enwiki", &id_table, Thresholds::default());
+        let batch = infobox_fields_batch(&[encoded]).unwrap();
+        let fields = dataset::string_column(&batch, "field").unwrap();
+        let values = dataset::string_column(&batch, "value").unwrap();
+        let restored = batch.column_by_name("restored").unwrap().as_boolean();
+        let rows: Vec<_> = (0..batch.num_rows())
+            .map(|i| (fields.value(i), values.value(i), restored.value(i)))
+            .collect();
+        assert_eq!(
+            rows,
+            [
+                ("election_date", "14 August 1949", false),
+                ("winner", "CDU", false),
+                ("winner", "SPD", false),
+                ("winner", "CDU", true),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_references() {
+        let id_table = ArticleIdTable::default();
+        let revision = |user_id: i64, references: Vec<Reference>| TempArticleRevision {
+            id: user_id as u64,
+            user_id,
+            timestamp: Timestamp::from_second(user_id).unwrap(),
+            references,
+            ..Default::default()
+        };
+        let source = Reference::Ref("https://www.nytimes.com/1902/macys.html".into());
+        let website = Reference::ExternalLink("https://www.macys.com/".into());
+        let article = TempArticle {
+            title: "Macy's".into(),
+            revisions: vec![
+                revision(1, vec![source.clone(), website.clone()]),
+                revision(2, vec![website]),
+            ],
+        };
+        let encoded = delta_encode(article, "enwiki", &id_table, Thresholds::default());
+        let batch = references_batch(&[encoded]).unwrap();
+        let kinds = dataset::string_column(&batch, "kind").unwrap();
+        let references = dataset::string_column(&batch, "reference").unwrap();
+        let removed_bys = batch
+            .column_by_name("removed_by_user")
+            .unwrap()
+            .as_primitive::<Int64Type>();
+        let rows: Vec<_> = (0..batch.num_rows())
+            .map(|i| {
+                (
+                    kinds.value(i),
+                    references.value(i),
+                    removed_bys.is_valid(i).then(|| removed_bys.value(i)),
+                )
+            })
+            .collect();
+        assert_eq!(
+            rows,
+            [
+                ("ref", source.identity(), Some(2)),
+                ("external_link", "https://www.macys.com/", None),
+            ]
+        );
+    }
+}
@@ -0,0 +1,424 @@
+//! Markdown review reports for flagged article-weeks, collecting
+//! what reviewers used to piece together from several notebook cells.
+
+use crate::anomalies::{self, ScoredWeek};
+use crate::dataset;
+use crate::diff::{self, RevisionDiff};
+use crate::history::{self, RevisionChange};
+use crate::ingest::temp_db::{TempArticle, TempDb};
+use crate::week::IsoWeek;
+use crate::wiki_api;
+use arrow::datatypes::{Float64Type, Int64Type};
+use compact_str::CompactString;
+use foldhash::{HashMap, HashSet};
+use percent_encoding::{NON_ALPHANUMERIC, utf8_percent_encode};
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Links listed per section before the rest is summarized.
+const MAX_LISTED: usize = 100;
+
+/// Text diffs included per report, largest link change first.
+const MAX_DIFFS: usize = 20;
+
+/// Unchanged characters shown around each change in text diffs.
+const DIFF_CONTEXT: usize = 80;
+
+#[derive(Debug, clap::Args)]
+pub struct ReportArgs {
+    /// Anomalies written by `anomalies`.
+    #[clap(long, default_value = "data/anomalies.parquet")]
+    anomalies: PathBuf,
+    /// Candidates written by `detect-partial-patterns`, included as
+    /// pattern violations if the file exists.
+    #[clap(long, default_value = "data/partial_patterns.parquet")]
+    partial_patterns: PathBuf,
+    #[clap(long, default_value = "data/reports")]
+    output_dir: PathBuf,
+    /// Number of most anomalous article-weeks to report on.
+    #[clap(long, default_value_t = 50)]
+    top: usize,
+    /// Wiki to report on, by database name.
+    #[clap(long, default_value = dataset::DEFAULT_WIKI)]
+    wiki: String,
+    /// Base URL of the wiki, for history and diff links. Defaults to
+    /// the Wikipedia of `--wiki`.
+    #[clap(long)]
+    wiki_url: Option<String>,
+}
+
+/// A rule of `mine-patterns` the article-week applied only in part.
+#[derive(Debug, Clone)]
+pub struct Violation {
+    pub antecedent: String,
+    pub missing: String,
+    pub confidence: f64,
+    pub score: f64,
+}
+
+pub fn write_reports(temp_db: &TempDb, args: &ReportArgs) -> anyhow::Result<()> {
+    let flagged: Vec<ScoredWeek> = anomalies::read_anomalies(&args.anomalies)?
+        .into_iter()
+        .filter(|row| row.anomaly && row.lookup_title().is_some() && row.wiki == args.wiki)
+        .take(args.top)
+        .collect();
+    let violations = if args.partial_patterns.exists() {
+        let keys = flagged
+            .iter()
+            .map(|row| (row.src_article, row.week))
+            .collect();
+        read_violations(&args.partial_patterns, &args.wiki, &keys)?
+    } else {
+        HashMap::default()
+    };
+    let temp_db = temp_db.for_wiki(&args.wiki);
+    let wiki_url = match &args.wiki_url {
+        Some(url) => url.clone(),
+        None => wiki_api::site_url(&args.wiki)?,
+    };
+
+    fs::create_dir_all(&args.output_dir)?;
+    let mut index = String::from(
+        "# Flagged article-weeks\n\n| # | Article | Week | Score |\n|---|---|---|---|\n",
+    );
+    let mut written = 0;
+    for (i, row) in flagged.iter().enumerate() {
+        let title = row.lookup_title().unwrap_or_default();
+        let Some(article) = temp_db.get_article(title)? else {
+            tracing::warn!("article {title:?} is not in data/temp-db");
+            continue;
+        };
+        let violations = violations
+            .get(&(row.src_article, row.week))
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        let diffs = week_diffs(&temp_db, &article, row.week)?;
+        let report = render_report(&article, row, i + 1, violations, &diffs, &wiki_url);
+
+        let file_name = format!("{:03}-{}-{}.md", i + 1, slug(&article.title), row.week);
+        fs::write(args.output_dir.join(&file_name), report)?;
+        writeln!(
+            index,
+            "| {} | [{}]({file_name}) | {} | {:.3} |",
+            i + 1,
+            escape_cell(&article.title),
+            row.week,
+            row.score
+        )?;
+        written += 1;
+    }
+    fs::write(args.output_dir.join("index.md"), index)?;
+    tracing::info!("wrote {written} reports to {}", args.output_dir.display());
+    Ok(())
+}
+
+fn read_violations(
+    path: &Path,
+    wiki: &str,
+    keys: &HashSet<(i64, IsoWeek)>,
+) -> anyhow::Result<HashMap<(i64, IsoWeek), Vec<Violation>>> {
+    let mut violations = HashMap::<_, Vec<Violation>>::default();
+    for batch in dataset::open_reader(path)? {
+        let batch = batch?;
+        let wikis = dataset::WikiColumn::new(&batch)?;
+        let src_articles = dataset::primitive_column::<Int64Type>(&batch, "src_article")?;
+        let weeks = dataset::string_column(&batch, "week")?;
+        let antecedents = dataset::string_column(&batch, "antecedent_titles")?;
+        let missings = dataset::string_column(&batch, "missing_titles")?;
+        let confidences = dataset::primitive_column::<Float64Type>(&batch, "confidence")?;
+        let scores = dataset::primitive_column::<Float64Type>(&batch, "score")?;
+        for i in 0..batch.num_rows() {
+            if wikis.value(i) != wiki {
+                continue;
+            }
+            let key = (src_articles.value(i), weeks.value(i).parse()?);
+            if keys.contains(&key) {
+                violations.entry(key).or_default().push(Violation {
+                    antecedent: antecedents.value(i).to_owned(),
+                    missing: missings.value(i).to_owned(),
+                    confidence: confidences.value(i),
+                    score: scores.value(i),
+                });
+            }
+        }
+    }
+    Ok(violations)
+}
+
+/// Text diffs of the revisions of `week` that touched the most links,
+/// if the article's revision text is archived.
+fn week_diffs(
+    temp_db: &TempDb,
+    article: &TempArticle,
+    week: IsoWeek,
+) -> anyhow::Result<Vec<RevisionDiff>> {
+    let mut changes: Vec<RevisionChange> = history::revision_changes(article)
+        .into_iter()
+        .filter(|change| IsoWeek::of(change.timestamp) == week)
+        .collect();
+    changes.sort_by_key(|change| std::cmp::Reverse(change.added.len() + change.removed.len()));
+    let mut diffs = diff::text_diffs(
+        temp_db,
+        article,
+        changes.iter().take(MAX_DIFFS).map(|change| change.id),
+    )?;
+    let order: HashMap<u64, usize> = changes
+        .iter()
+        .enumerate()
+        .map(|(i, change)| (change.id, i))
+        .collect();
+    diffs.sort_by_key(|diff| order[&diff.to]);
+    Ok(diffs)
+}
+
+/// Renders the report of one flagged article-week.
+pub fn render_report(
+    article: &TempArticle,
+    row: &ScoredWeek,
+    rank: usize,
+    violations: &[Violation],
+    diffs: &[RevisionDiff],
+    wiki_url: &str,
+) -> String {
+    let changes = history::revision_changes(article);
+    let reverted = reverted_revisions(&changes);
+    let week_changes: Vec<&RevisionChange> = changes
+        .iter()
+        .filter(|change| IsoWeek::of(change.timestamp) == row.week)
+        .collect();
+    let page = utf8_percent_encode(&article.title.replace(' ', "_"), NON_ALPHANUMERIC).to_string();
+
+    let mut md = String::new();
+    writeln!(md, "# {} — week {}\n", article.title, row.week).unwrap();
+    writeln!(
+        md,
+        "Anomaly score {:.3} (rank {rank}), {} to {}. [Article]({wiki_url}/wiki/{page}) · [History]({wiki_url}/w/index.php?title={page}&action=history&offset={})\n",
+        row.score,
+        row.week.start().strftime("%Y-%m-%d"),
+        row.week.end().strftime("%Y-%m-%d"),
+        row.week.end().strftime("%Y%m%d%H%M%S"),
+    )
+    .unwrap();
+
+    let added: usize = week_changes.iter().map(|change| change.added.len()).sum();
+    let removed: usize = week_changes.iter().map(|change| change.removed.len()).sum();
+    let reverts = week_changes
+        .iter()
+        .filter(|change| change.reverts_to.is_some())
+        .count();
+    let editors = editor_stats(&week_changes);
+    writeln!(
+        md,
+        "{} revisions by {} editors, {added} links added, {removed} links removed, {reverts} reverts.\n",
+        week_changes.len(),
+        editors.len()
+    )
+    .unwrap();
+
+    md.push_str("## Editors\n\n| User | Revisions | Links added | Links removed | Reverts |\n|---|---|---|---|---|\n");
+    for (user_id, stats) in &editors {
+        writeln!(
+            md,
+            "| {user_id} | {} | {} | {} | {} |",
+            stats.revisions, stats.added, stats.removed, stats.reverts
+        )
+        .unwrap();
+    }
+
+    md.push_str("\n## Revisions\n\n| Time (UTC) | User | Added | Removed | Revert | Diff |\n|---|---|---|---|---|---|\n");
+    for change in &week_changes {
+        let revert = match change.reverts_to {
+            Some(id) => format!("↩ restores {id}"),
+            None if reverted.contains(&change.id) => "reverted".to_owned(),
+            None => String::new(),
+        };
+        let diff = if change.id == 0 {
+            "–".to_owned()
+        } else {
+            format!(
+                "[diff]({wiki_url}/w/index.php?diff=prev&oldid={})",
+                change.id
+            )
+        };
+        writeln!(
+            md,
+            "| {} | {} | {} | {} | {revert} | {diff} |",
+            change.timestamp.strftime("%Y-%m-%d %H:%M:%S"),
+            change.user_id,
+            change.added.len(),
+            change.removed.len(),
+        )
+        .unwrap();
+    }
+
+    for (heading, links) in [
+        (
+            "Links added",
+            week_changes
+                .iter()
+                .flat_map(|change| change.added.iter().map(move |link| (*change, link)))
+                .collect::<Vec<_>>(),
+        ),
+        (
+            "Links removed",
+            week_changes
+                .iter()
+                .flat_map(|change| change.removed.iter().map(move |link| (*change, link)))
+                .collect(),
+        ),
+    ] {
+        writeln!(md, "\n## {heading}\n").unwrap();
+        if links.is_empty() {
+            md.push_str("None.\n");
+        }
+        for (change, link) in links.iter().take(MAX_LISTED) {
+            writeln!(
+                md,
+                "- [[{link}]] by {} at {}",
+                change.user_id,
+                change.timestamp.strftime("%Y-%m-%d %H:%M")
+            )
+            .unwrap();
+        }
+        if links.len() > MAX_LISTED {
+            writeln!(md, "- … and {} more", links.len() - MAX_LISTED).unwrap();
+        }
+    }
+
+    if !diffs.is_empty() {
+        md.push_str("\n## Text changes\n");
+    }
+    for diff in diffs {
+        let Some(change) = week_changes.iter().find(|change| change.id == diff.to) else {
+            continue;
+        };
+        writeln!(
+            md,
+            "\n### {} by {} ([diff]({wiki_url}/w/index.php?diff={}&oldid={}))\n",
+            change.timestamp.strftime("%Y-%m-%d %H:%M:%S"),
+            change.user_id,
+            diff.to,
+            diff.from
+        )
+        .unwrap();
+        let words = diff.words.as_deref().unwrap_or_default();
+        writeln!(md, "```\n{}\n```", diff::render_words(words, DIFF_CONTEXT)).unwrap();
+    }
+
+    if !violations.is_empty() {
+        md.push_str("\n## Pattern violations\n\n| Applied | Missing | Confidence | Score |\n|---|---|---|---|\n");
+        for violation in violations {
+            writeln!(
+                md,
+                "| {} | {} | {:.2} | {:.3} |",
+                escape_cell(&violation.antecedent),
+                escape_cell(&violation.missing),
+                violation.confidence,
+                violation.score
+            )
+            .unwrap();
+        }
+    }
+    md
+}
+
+#[derive(Debug, Default)]
+pub struct EditorStats {
+    pub revisions: usize,
+    pub added: usize,
+    pub removed: usize,
+    pub reverts: usize,
+}
+
+/// Per-user counts, busiest editor first.
+pub fn editor_stats(changes: &[&RevisionChange]) -> Vec<(i64, EditorStats)> {
+    let mut stats = HashMap::<i64, EditorStats>::default();
+    for change in changes {
+        let stats = stats.entry(change.user_id).or_default();
+        stats.revisions += 1;
+        stats.added += change.added.len();
+        stats.removed += change.removed.len();
+        stats.reverts += usize::from(change.reverts_to.is_some());
+    }
+    let mut stats: Vec<_> = stats.into_iter().collect();
+    stats.sort_by_key(|(user_id, stats)| (std::cmp::Reverse(stats.revisions), *user_id));
+    stats
+}
+
+/// IDs of the revisions undone by a later revert.
+fn reverted_revisions(changes: &[RevisionChange]) -> HashSet<u64> {
+    let mut reverted = HashSet::default();
+    for (i, change) in changes.iter().enumerate() {
+        let Some(target) = change.reverts_to else {
+            continue;
+        };
+        let undone = changes[..i]
+            .iter()
+            .rev()
+            .take_while(|earlier| earlier.id != target)
+            .map(|earlier| earlier.id);
+        reverted.extend(undone);
+    }
+    reverted
+}
+
+fn slug(title: &str) -> CompactString {
+    title
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
+fn escape_cell(s: &str) -> String {
+    s.replace('|', "\\|")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ingest::temp_db::TempArticleRevision;
+
+    #[test]
+    fn test_render_report() {
+        let revision = |id, timestamp, user_id, text, links| TempArticleRevision {
+            id,
+            text_hash: TempArticleRevision::hash_text(text),
+            ..TempArticleRevision::for_test(user_id, timestamp, links)
+        };
+        let article = TempArticle {
+            title: "PBS".into(),
+            revisions: vec![
+                revision(1, "2023-12-01T00:00:00Z", 1, "a", &["Television"]),
+                revision(2, "2024-01-15T00:00:00Z", 2, "", &[]),
+                revision(3, "2024-01-15T01:00:00Z", 1, "a", &["Television"]),
+            ],
+        };
+        let row = ScoredWeek {
+            wiki: "enwiki".into(),
+            src_article: 7,
+            title: Some("pbs".into()),
+            display_title: Some("PBS".into()),
+            week: "2024-3".parse().unwrap(),
+            score: -0.2,
+            anomaly: true,
+        };
+        let diffs = [RevisionDiff {
+            from: 1,
+            to: 2,
+            links_added: Vec::new(),
+            links_removed: vec!["Television".into()],
+            words: Some(diff::word_diff("a", "")),
+        }];
+        let report = render_report(&article, &row, 1, &[], &diffs, "https://en.wikipedia.org");
+        assert!(
+            report.contains("2 revisions by 2 editors, 1 links added, 1 links removed, 1 reverts.")
+        );
+        assert!(report.contains(
+            "| reverted | [diff](https://en.wikipedia.org/w/index.php?diff=prev&oldid=2) |"
+        ));
+        assert!(report.contains("↩ restores 1"));
+        assert!(report.contains("/w/index.php?diff=2&oldid=1))\n\n```\n[-a-]\n```"));
+        assert!(!report.contains("Pattern violations"));
+    }
+}
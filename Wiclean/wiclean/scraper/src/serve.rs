@@ -0,0 +1,427 @@
+//! Local HTTP API for browsing article histories.
+//!
+//! Timelines, links and revisions are read from `TempDb` on every
+//! request. Backlinks need the reverse direction, so they are served
+//! from an index of `links.parquet` built at startup. HTML pages for
+//! reviewing flagged article-weeks live in [`view`].
+
+use crate::dataset;
+use crate::diff::{self, RevisionDiff};
+use crate::history::{self, RevisionChange, TimelineEvent};
+use crate::ingest::temp_db::{TempArticle, TempDb};
+use crate::postprocess_to_parquet::normalize_title;
+use crate::week::IsoWeek;
+use anyhow::Context;
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use compact_str::CompactString;
+use foldhash::HashMap;
+use jiff::Timestamp;
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Instant;
+
+mod view;
+
+const DEFAULT_LIMIT: usize = 100;
+const MAX_LIMIT: usize = 1000;
+
+#[derive(Debug, clap::Args)]
+pub struct ServeArgs {
+    #[clap(long, default_value = "127.0.0.1:3000")]
+    addr: SocketAddr,
+    /// Wiki to serve, by database name.
+    #[clap(long, default_value = dataset::DEFAULT_WIKI)]
+    wiki: String,
+    /// Link intervals written by `postprocess-to-parquet`, used for
+    /// backlinks.
+    #[clap(long, default_value = dataset::LINKS_PATH)]
+    links: PathBuf,
+    /// Article titles written by `postprocess-to-parquet`.
+    #[clap(long, default_value = dataset::ARTICLES_PATH)]
+    articles: PathBuf,
+    /// Anomalies written by `anomalies`, listed on the index page if
+    /// the file exists.
+    #[clap(long, default_value = "data/anomalies.parquet")]
+    anomalies: PathBuf,
+    /// Skip building the backlink index, which holds all of
+    /// `links.parquet` in memory.
+    #[clap(long)]
+    no_backlinks: bool,
+}
+
+#[derive(Clone)]
+struct AppState {
+    temp_db: TempDb,
+    backlinks: Option<Arc<BacklinkIndex>>,
+    flagged: Arc<Vec<view::Flagged>>,
+}
+
+pub async fn serve(temp_db: TempDb, args: &ServeArgs) -> anyhow::Result<()> {
+    let backlinks = if args.no_backlinks {
+        None
+    } else {
+        let links = args.links.clone();
+        let articles = args.articles.clone();
+        let wiki = args.wiki.clone();
+        let index =
+            tokio::task::spawn_blocking(move || BacklinkIndex::build(&links, &articles, &wiki))
+                .await??;
+        Some(Arc::new(index))
+    };
+
+    let flagged = if args.anomalies.exists() {
+        view::read_flagged(&args.anomalies, &args.wiki)?
+    } else {
+        Vec::new()
+    };
+
+    let app = router(AppState {
+        temp_db: temp_db.for_wiki(&args.wiki),
+        backlinks,
+        flagged: Arc::new(flagged),
+    });
+    let listener = tokio::net::TcpListener::bind(args.addr)
+        .await
+        .with_context(|| format!("failed to bind {}", args.addr))?;
+    tracing::info!("listening on http://{}", args.addr);
+    axum::serve(listener, app)
+        .with_graceful_shutdown(async {
+            tokio::signal::ctrl_c().await.ok();
+        })
+        .await?;
+    Ok(())
+}
+
+fn router(state: AppState) -> Router {
+    Router::new()
+        .route("/", get(view::index))
+        .route("/view", get(view::form))
+        .route("/view/{title}", get(view::timeline))
+        .route("/articles/{title}/timeline", get(timeline))
+        .route("/articles/{title}/links", get(links))
+        .route("/articles/{title}/backlinks", get(backlinks))
+        .route("/articles/{title}/revisions", get(revisions))
+        .route("/articles/{title}/diff", get(revision_diff))
+        .route("/revisions/{id}/text", get(revision_text))
+        .with_state(state)
+}
+
+#[derive(Debug, Deserialize)]
+struct PageQuery {
+    #[serde(default)]
+    offset: usize,
+    limit: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+struct Page<T> {
+    total: usize,
+    offset: usize,
+    limit: usize,
+    items: Vec<T>,
+}
+
+impl PageQuery {
+    fn apply<T>(&self, items: Vec<T>) -> Page<T> {
+        let limit = self.limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT);
+        Page {
+            total: items.len(),
+            offset: self.offset,
+            limit,
+            items: items.into_iter().skip(self.offset).take(limit).collect(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct RangeQuery {
+    from: Option<Timestamp>,
+    to: Option<Timestamp>,
+}
+
+#[derive(Debug, Deserialize)]
+struct AtQuery {
+    /// Defaults to now.
+    at: Option<Timestamp>,
+}
+
+#[derive(Debug, Deserialize)]
+struct WeekQuery {
+    week: String,
+}
+
+struct ApiError(StatusCode, String);
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let body = serde_json::json!({ "error": self.1 });
+        (self.0, Json(body)).into_response()
+    }
+}
+
+impl<E: Into<anyhow::Error>> From<E> for ApiError {
+    fn from(e: E) -> Self {
+        let e = e.into();
+        tracing::error!("request failed: {e:#}");
+        Self(StatusCode::INTERNAL_SERVER_ERROR, format!("{e:#}"))
+    }
+}
+
+type ApiResult<T> = Result<Json<T>, ApiError>;
+
+/// Reads an article from `TempDb` without blocking the runtime.
+async fn load_article(temp_db: &TempDb, title: String) -> Result<TempArticle, ApiError> {
+    let temp_db = temp_db.clone();
+    let article = tokio::task::spawn_blocking(move || temp_db.get_article(&title)).await??;
+    article.ok_or_else(|| ApiError(StatusCode::NOT_FOUND, "no such article".to_owned()))
+}
+
+async fn timeline(
+    State(state): State<AppState>,
+    Path(title): Path<String>,
+    Query(range): Query<RangeQuery>,
+    Query(page): Query<PageQuery>,
+) -> ApiResult<Page<TimelineEvent>> {
+    let article = load_article(&state.temp_db, title).await?;
+    let mut events = history::link_timeline(&article);
+    events.retain(|event| {
+        range.from.is_none_or(|from| event.timestamp >= from)
+            && range.to.is_none_or(|to| event.timestamp < to)
+    });
+    Ok(Json(page.apply(events)))
+}
+
+#[derive(Debug, Serialize)]
+struct Link {
+    target: CompactString,
+    added_at: Timestamp,
+    added_by_user: i64,
+}
+
+async fn links(
+    State(state): State<AppState>,
+    Path(title): Path<String>,
+    Query(at): Query<AtQuery>,
+    Query(page): Query<PageQuery>,
+) -> ApiResult<Page<Link>> {
+    let article = load_article(&state.temp_db, title).await?;
+    let at = at.at.unwrap_or_else(Timestamp::now);
+    Ok(Json(page.apply(links_at(&article, at))))
+}
+
+/// Links of the article as of its last revision at or before `at`.
+fn links_at(article: &TempArticle, at: Timestamp) -> Vec<Link> {
+    let mut current = HashMap::<CompactString, Link>::default();
+    for event in history::link_timeline(article) {
+        if event.timestamp > at {
+            break;
+        }
+        let key = normalize_title(&event.target);
+        match event.action {
+            dataset::Action::Add => {
+                current.insert(
+                    key,
+                    Link {
+                        target: event.target,
+                        added_at: event.timestamp,
+                        added_by_user: event.user_id,
+                    },
+                );
+            }
+            dataset::Action::Remove => {
+                current.remove(&key);
+            }
+        }
+    }
+    let mut links: Vec<Link> = current.into_values().collect();
+    links.sort_by(|a, b| a.target.cmp(&b.target));
+    links
+}
+
+#[derive(Debug, Serialize)]
+struct Backlink {
+    source: CompactString,
+    added_at: Timestamp,
+}
+
+async fn backlinks(
+    State(state): State<AppState>,
+    Path(title): Path<String>,
+    Query(at): Query<AtQuery>,
+    Query(page): Query<PageQuery>,
+) -> ApiResult<Page<Backlink>> {
+    let Some(index) = &state.backlinks else {
+        return Err(ApiError(
+            StatusCode::NOT_IMPLEMENTED,
+            "backlink index was disabled with --no-backlinks".to_owned(),
+        ));
+    };
+    let at = at.at.unwrap_or_else(Timestamp::now);
+    let Some(backlinks) = index.backlinks_at(&title, at) else {
+        return Err(ApiError(
+            StatusCode::NOT_FOUND,
+            "no such article".to_owned(),
+        ));
+    };
+    Ok(Json(page.apply(backlinks)))
+}
+
+async fn revisions(
+    State(state): State<AppState>,
+    Path(title): Path<String>,
+    Query(week): Query<WeekQuery>,
+    Query(page): Query<PageQuery>,
+) -> ApiResult<Page<RevisionChange>> {
+    let week: IsoWeek = week
+        .week
+        .parse()
+        .map_err(|e| ApiError(StatusCode::BAD_REQUEST, format!("{e:#}")))?;
+    let article = load_article(&state.temp_db, title).await?;
+    let mut changes = history::revision_changes(&article);
+    changes.retain(|change| IsoWeek::of(change.timestamp) == week);
+    Ok(Json(page.apply(changes)))
+}
+
+/// All link intervals of `links.parquet`, sorted by target article.
+struct BacklinkIndex {
+    ids: HashMap<CompactString, i64>,
+    titles: HashMap<i64, CompactString>,
+    intervals: Vec<BacklinkInterval>,
+}
+
+/// Timestamps are kept as seconds to keep the index small.
+struct BacklinkInterval {
+    dst_article: i64,
+    src_article: i64,
+    created_at: i64,
+    removed_at: Option<i64>,
+}
+
+impl BacklinkIndex {
+    fn build(
+        links: &std::path::Path,
+        articles: &std::path::Path,
+        wiki: &str,
+    ) -> anyhow::Result<Self> {
+        let start = Instant::now();
+        let titles = dataset::read_article_titles(articles, wiki, |_| true)?;
+        let ids = titles
+            .iter()
+            .map(|(&id, title)| (title.clone(), id))
+            .collect();
+
+        let mut intervals = Vec::new();
+        for group in dataset::link_intervals(links, wiki)? {
+            let (src_article, links) = group?;
+            intervals.extend(links.into_iter().map(|link| BacklinkInterval {
+                dst_article: link.dst_article,
+                src_article,
+                created_at: link.created_at.as_second(),
+                removed_at: link.removed_at.map(|removed_at| removed_at.as_second()),
+            }));
+        }
+        intervals.sort_unstable_by_key(|interval| (interval.dst_article, interval.created_at));
+        tracing::info!(
+            "indexed {} link intervals in {:.2?}",
+            intervals.len(),
+            start.elapsed()
+        );
+        Ok(Self {
+            ids,
+            titles,
+            intervals,
+        })
+    }
+
+    /// Articles linking to `title` at `at`, or `None` if no article
+    /// has that title.
+    fn backlinks_at(&self, title: &str, at: Timestamp) -> Option<Vec<Backlink>> {
+        let id = *self.ids.get(&normalize_title(title))?;
+        let at = at.as_second();
+        let start = self.intervals.partition_point(|i| i.dst_article < id);
+        let end = self.intervals.partition_point(|i| i.dst_article <= id);
+        let mut backlinks: Vec<Backlink> = self.intervals[start..end]
+            .iter()
+            .filter(|i| i.created_at <= at && i.removed_at.is_none_or(|removed_at| at < removed_at))
+            .map(|i| Backlink {
+                source: self
+                    .titles
+                    .get(&i.src_article)
+                    .cloned()
+                    .unwrap_or_else(|| format!("#{}", i.src_article).into()),
+                added_at: Timestamp::from_second(i.created_at).unwrap_or_default(),
+            })
+            .collect();
+        backlinks.sort_by(|a, b| a.source.cmp(&b.source));
+        Some(backlinks)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct DiffQuery {
+    from: u64,
+    to: u64,
+}
+
+async fn revision_diff(
+    State(state): State<AppState>,
+    Path(title): Path<String>,
+    Query(query): Query<DiffQuery>,
+) -> ApiResult<RevisionDiff> {
+    let article = load_article(&state.temp_db, title).await?;
+    let temp_db = state.temp_db.clone();
+    let diff = tokio::task::spawn_blocking(move || {
+        diff::diff_revisions(&temp_db, &article, query.from, query.to)
+    })
+    .await?
+    .map_err(|e| ApiError(StatusCode::NOT_FOUND, format!("{e:#}")))?;
+    Ok(Json(diff))
+}
+
+/// Wikitext of an archived revision, see `ingest --archive-titles`.
+async fn revision_text(
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Result<String, ApiError> {
+    let temp_db = state.temp_db.clone();
+    let text = tokio::task::spawn_blocking(move || temp_db.revision_text(id)).await??;
+    text.ok_or_else(|| {
+        ApiError(
+            StatusCode::NOT_FOUND,
+            "revision text is not archived".to_owned(),
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ingest::temp_db::TempArticleRevision;
+
+    #[test]
+    fn test_links_at() {
+        let revision = |timestamp, links| TempArticleRevision::for_test(1, timestamp, links);
+        let article = TempArticle {
+            title: "Detroit".into(),
+            revisions: vec![
+                revision("2024-11-04T00:00:00Z", &["Michigan", "Ford"]),
+                revision("2024-11-05T00:00:00Z", &["Michigan"]),
+            ],
+        };
+        let targets = |at: &str| -> Vec<CompactString> {
+            links_at(&article, at.parse().unwrap())
+                .into_iter()
+                .map(|link| link.target)
+                .collect()
+        };
+        assert!(targets("2024-11-03T00:00:00Z").is_empty());
+        assert_eq!(targets("2024-11-04T12:00:00Z"), ["Ford", "Michigan"]);
+        assert_eq!(targets("2024-11-05T00:00:00Z"), ["Michigan"]);
+    }
+}
@@ -0,0 +1,472 @@
+//! Server-rendered HTML pages for reviewing flagged article-weeks.
+//!
+//! The timeline is an inline SVG swimlane chart with one lane per link
+//! target. Tooltips use SVG `<title>` elements, so the pages work
+//! without any JavaScript.
+
+use super::{ApiError, AppState, load_article};
+use crate::anomalies;
+use crate::dataset::Action;
+use crate::history::{self, TimelineEvent};
+use crate::postprocess_to_parquet::normalize_title;
+use crate::week::IsoWeek;
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::response::Html;
+use compact_str::CompactString;
+use foldhash::HashMap;
+use jiff::tz::TimeZone;
+use jiff::{SignedDuration, Timestamp};
+use percent_encoding::{NON_ALPHANUMERIC, utf8_percent_encode};
+use serde::Deserialize;
+use std::fmt::Write;
+
+const MAX_LANES: usize = 300;
+const MAX_FLAGGED: usize = 1000;
+
+const WIDTH: f64 = 1200.0;
+const LABEL_WIDTH: f64 = 280.0;
+const LANE_HEIGHT: f64 = 16.0;
+const AXIS_HEIGHT: f64 = 24.0;
+
+/// An article-week from `anomalies.parquet`, listed on the index page.
+pub(super) struct Flagged {
+    /// Title as written, which `/view` looks the article up by.
+    title: CompactString,
+    week: IsoWeek,
+    score: f64,
+}
+
+pub(super) fn read_flagged(path: &std::path::Path, wiki: &str) -> anyhow::Result<Vec<Flagged>> {
+    Ok(anomalies::read_anomalies(path)?
+        .into_iter()
+        .filter(|row| row.anomaly && row.wiki == wiki)
+        .filter_map(|row| {
+            Some(Flagged {
+                title: row.lookup_title()?.into(),
+                week: row.week,
+                score: row.score,
+            })
+        })
+        .take(MAX_FLAGGED)
+        .collect())
+}
+
+pub(super) async fn index(State(state): State<AppState>) -> Html<String> {
+    let mut body = String::new();
+    body.push_str("<h1>Flagged article-weeks</h1>\n");
+    body.push_str(
+        "<form action=\"/view\" method=\"get\">\
+         <input name=\"title\" placeholder=\"Article title\"> \
+         <input name=\"week\" placeholder=\"YYYY-W\" size=\"8\"> \
+         <button>Show</button></form>\n",
+    );
+    if state.flagged.is_empty() {
+        body.push_str("<p>No anomalies loaded.</p>\n");
+    } else {
+        body.push_str("<table>\n<tr><th>Article</th><th>Week</th><th>Score</th></tr>\n");
+        for flagged in state.flagged.iter() {
+            writeln!(
+                body,
+                "<tr><td><a href=\"{}\">{}</a></td><td>{}</td><td>{:.3}</td></tr>",
+                view_url(&flagged.title, Some(&flagged.week.to_string())),
+                escape(&flagged.title),
+                flagged.week,
+                flagged.score
+            )
+            .unwrap();
+        }
+        body.push_str("</table>\n");
+    }
+    Html(page("Flagged article-weeks", &body))
+}
+
+#[derive(Debug, Deserialize)]
+pub(super) struct FormQuery {
+    title: String,
+    week: Option<String>,
+}
+
+/// Target of the search form, which cannot put the title in the path.
+pub(super) async fn form(Query(query): Query<FormQuery>) -> axum::response::Redirect {
+    let week = query.week.as_deref().filter(|week| !week.trim().is_empty());
+    axum::response::Redirect::to(&view_url(&query.title, week))
+}
+
+#[derive(Debug, Deserialize)]
+pub(super) struct TimelineQuery {
+    /// Week to highlight and center the chart on.
+    week: Option<String>,
+    /// Weeks of context shown around the highlighted week.
+    #[serde(default = "default_context_weeks")]
+    context: i64,
+}
+
+fn default_context_weeks() -> i64 {
+    4
+}
+
+pub(super) async fn timeline(
+    State(state): State<AppState>,
+    Path(title): Path<String>,
+    Query(query): Query<TimelineQuery>,
+) -> Result<Html<String>, ApiError> {
+    let week = query
+        .week
+        .as_deref()
+        .map(str::parse::<IsoWeek>)
+        .transpose()
+        .map_err(|e| ApiError(StatusCode::BAD_REQUEST, format!("{e:#}")))?;
+    let article = load_article(&state.temp_db, title).await?;
+    let events = history::link_timeline(&article);
+
+    let (from, to) = match week {
+        Some(week) => {
+            let context = SignedDuration::from_hours(query.context.clamp(0, 104) * 7 * 24);
+            (week.start() - context, week.end() + context)
+        }
+        None => match (events.first(), events.last()) {
+            (Some(first), Some(last)) => {
+                let day = SignedDuration::from_hours(24);
+                (first.timestamp - day, last.timestamp + day)
+            }
+            _ => {
+                let now = Timestamp::now();
+                (now - SignedDuration::from_hours(24), now)
+            }
+        },
+    };
+
+    let mut body = String::new();
+    writeln!(
+        body,
+        "<p><a href=\"/\">Flagged article-weeks</a> · <a href=\"/articles/{}/timeline?limit=1000\">JSON</a></p>",
+        utf8_percent_encode(&article.title, NON_ALPHANUMERIC)
+    )
+    .unwrap();
+    match week {
+        Some(week) => writeln!(
+            body,
+            "<h1>{} <small>{week}</small></h1>",
+            escape(&article.title)
+        ),
+        None => writeln!(body, "<h1>{}</h1>", escape(&article.title)),
+    }
+    .unwrap();
+    writeln!(
+        body,
+        "<p>{} revisions, {} link events, showing {} to {}.</p>",
+        article.revisions.len(),
+        events.len(),
+        format_date(from),
+        format_date(to)
+    )
+    .unwrap();
+    body.push_str(&swimlanes(&events, from, to, week));
+    Ok(Html(page(&article.title, &body)))
+}
+
+/// A period during which the article linked to a target.
+struct Span {
+    added_at: Timestamp,
+    added_by: i64,
+    removed: Option<(Timestamp, i64)>,
+}
+
+struct Lane {
+    target: CompactString,
+    spans: Vec<Span>,
+}
+
+fn lanes(events: &[TimelineEvent]) -> Vec<Lane> {
+    let mut lanes = Vec::<Lane>::new();
+    let mut index = HashMap::<CompactString, usize>::default();
+    for event in events {
+        let key = normalize_title(&event.target);
+        let i = *index.entry(key).or_insert_with(|| {
+            lanes.push(Lane {
+                target: event.target.clone(),
+                spans: Vec::new(),
+            });
+            lanes.len() - 1
+        });
+        match event.action {
+            Action::Add => lanes[i].spans.push(Span {
+                added_at: event.timestamp,
+                added_by: event.user_id,
+                removed: None,
+            }),
+            Action::Remove => {
+                if let Some(span) = lanes[i].spans.last_mut() {
+                    span.removed = Some((event.timestamp, event.user_id));
+                }
+            }
+        }
+    }
+    lanes
+}
+
+/// Renders the links that changed within `[from, to)` as an SVG
+/// chart, one lane per target and one color per editor.
+fn swimlanes(
+    events: &[TimelineEvent],
+    from: Timestamp,
+    to: Timestamp,
+    week: Option<IsoWeek>,
+) -> String {
+    let in_range = |timestamp: Timestamp| (from..to).contains(&timestamp);
+    let mut lanes: Vec<Lane> = lanes(events)
+        .into_iter()
+        .filter(|lane| {
+            lane.spans.iter().any(|span| {
+                in_range(span.added_at)
+                    || span
+                        .removed
+                        .is_some_and(|(removed_at, _)| in_range(removed_at))
+            })
+        })
+        .collect();
+    if lanes.is_empty() {
+        return "<p>No link changes in this period.</p>\n".to_owned();
+    }
+    // lanes whose first change in range comes first go on top
+    let first_change = |lane: &Lane| {
+        lane.spans
+            .iter()
+            .flat_map(|span| [Some(span.added_at), span.removed.map(|(at, _)| at)])
+            .flatten()
+            .filter(|&timestamp| in_range(timestamp))
+            .min()
+    };
+    lanes.sort_by_key(|lane| first_change(lane));
+    let truncated = lanes.len().saturating_sub(MAX_LANES);
+    lanes.truncate(MAX_LANES);
+
+    let plot_width = WIDTH - LABEL_WIDTH - 10.0;
+    let span_seconds = to.duration_since(from).as_secs_f64().max(1.0);
+    let x = |timestamp: Timestamp| {
+        let timestamp = timestamp.clamp(from, to);
+        LABEL_WIDTH + timestamp.duration_since(from).as_secs_f64() / span_seconds * plot_width
+    };
+    let height = AXIS_HEIGHT + lanes.len() as f64 * LANE_HEIGHT + 4.0;
+
+    let mut svg = String::new();
+    writeln!(
+        svg,
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{WIDTH}\" height=\"{height}\" font-size=\"11\" font-family=\"sans-serif\">"
+    )
+    .unwrap();
+
+    if let Some(week) = week {
+        writeln!(
+            svg,
+            "<rect x=\"{:.1}\" y=\"0\" width=\"{:.1}\" height=\"{height}\" fill=\"#fde68a\" opacity=\"0.6\"><title>week {week}</title></rect>",
+            x(week.start()),
+            x(week.end()) - x(week.start())
+        )
+        .unwrap();
+    }
+
+    for tick in ticks(from, to) {
+        writeln!(
+            svg,
+            "<line x1=\"{0:.1}\" x2=\"{0:.1}\" y1=\"{AXIS_HEIGHT}\" y2=\"{height}\" stroke=\"#e5e7eb\"/>\
+             <text x=\"{0:.1}\" y=\"14\" text-anchor=\"middle\" fill=\"#6b7280\">{1}</text>",
+            x(tick),
+            format_date(tick)
+        )
+        .unwrap();
+    }
+
+    for (i, lane) in lanes.iter().enumerate() {
+        let y = AXIS_HEIGHT + i as f64 * LANE_HEIGHT;
+        let middle = y + LANE_HEIGHT / 2.0;
+        writeln!(
+            svg,
+            "<text x=\"{:.1}\" y=\"{:.1}\" text-anchor=\"end\">{}</text>",
+            LABEL_WIDTH - 6.0,
+            middle + 4.0,
+            escape(&truncate(&lane.target, 40))
+        )
+        .unwrap();
+        for span in &lane.spans {
+            let end = span.removed.map_or(to, |(removed_at, _)| removed_at);
+            if end < from || span.added_at >= to {
+                continue;
+            }
+            writeln!(
+                svg,
+                "<rect x=\"{:.1}\" y=\"{:.1}\" width=\"{:.1}\" height=\"6\" fill=\"#d1d5db\"/>",
+                x(span.added_at),
+                middle - 3.0,
+                (x(end) - x(span.added_at)).max(1.0)
+            )
+            .unwrap();
+            if in_range(span.added_at) {
+                writeln!(
+                    svg,
+                    "<circle cx=\"{:.1}\" cy=\"{middle:.1}\" r=\"4.5\" fill=\"{}\"><title>+ {} at {} by user {}</title></circle>",
+                    x(span.added_at),
+                    user_color(span.added_by),
+                    escape(&lane.target),
+                    span.added_at,
+                    span.added_by
+                )
+                .unwrap();
+            }
+            if let Some((removed_at, removed_by)) = span.removed.filter(|(at, _)| in_range(*at)) {
+                writeln!(
+                    svg,
+                    "<rect x=\"{:.1}\" y=\"{:.1}\" width=\"8\" height=\"8\" fill=\"{}\" transform=\"rotate(45 {:.1} {middle:.1})\"><title>- {} at {} by user {}</title></rect>",
+                    x(removed_at) - 4.0,
+                    middle - 4.0,
+                    user_color(removed_by),
+                    x(removed_at),
+                    escape(&lane.target),
+                    removed_at,
+                    removed_by
+                )
+                .unwrap();
+            }
+        }
+    }
+    svg.push_str("</svg>\n");
+
+    if truncated > 0 {
+        writeln!(svg, "<p>{truncated} more links not shown.</p>").unwrap();
+    }
+    svg.push_str(&legend(events, in_range));
+    svg
+}
+
+/// Editors of the shown period with their colors and edit counts.
+fn legend(events: &[TimelineEvent], in_range: impl Fn(Timestamp) -> bool) -> String {
+    let mut counts = HashMap::<i64, (usize, usize)>::default();
+    for event in events.iter().filter(|event| in_range(event.timestamp)) {
+        let (adds, removes) = counts.entry(event.user_id).or_default();
+        match event.action {
+            Action::Add => *adds += 1,
+            Action::Remove => *removes += 1,
+        }
+    }
+    let mut counts: Vec<_> = counts.into_iter().collect();
+    counts.sort_by_key(|&(user_id, (adds, removes))| (std::cmp::Reverse(adds + removes), user_id));
+
+    let mut html = String::from(
+        "<p>● added, ◆ removed</p>\n<table>\n<tr><th></th><th>User</th><th>Added</th><th>Removed</th></tr>\n",
+    );
+    for (user_id, (adds, removes)) in counts {
+        writeln!(
+            html,
+            "<tr><td><svg width=\"12\" height=\"12\"><circle cx=\"6\" cy=\"6\" r=\"5\" fill=\"{}\"/></svg></td><td>{user_id}</td><td>{adds}</td><td>{removes}</td></tr>",
+            user_color(user_id)
+        )
+        .unwrap();
+    }
+    html.push_str("</table>\n");
+    html
+}
+
+/// Day-aligned axis ticks, at most about a dozen.
+fn ticks(from: Timestamp, to: Timestamp) -> Vec<Timestamp> {
+    let days = to.duration_since(from).as_hours() / 24;
+    let step = [1, 7, 14, 30, 91, 182, 365]
+        .into_iter()
+        .find(|step| days / step <= 12)
+        .unwrap_or(730);
+    let step = SignedDuration::from_hours(step * 24);
+    let Ok(mut tick) = TimeZone::UTC
+        .to_datetime(from)
+        .date()
+        .to_zoned(TimeZone::UTC)
+        .map(|zoned| zoned.timestamp())
+    else {
+        return Vec::new();
+    };
+    let mut ticks = Vec::new();
+    while tick < to {
+        if tick >= from {
+            ticks.push(tick);
+        }
+        tick += step;
+    }
+    ticks
+}
+
+/// A stable, well-spread color for each editor.
+fn user_color(user_id: i64) -> String {
+    let hash = (user_id as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    let hue = (hash >> 32) % 360;
+    let lightness = 35 + (hash >> 16) % 20;
+    format!("hsl({hue},70%,{lightness}%)")
+}
+
+fn view_url(title: &str, week: Option<&str>) -> String {
+    let mut url = format!("/view/{}", utf8_percent_encode(title, NON_ALPHANUMERIC));
+    if let Some(week) = week {
+        write!(url, "?week={}", utf8_percent_encode(week, NON_ALPHANUMERIC)).unwrap();
+    }
+    url
+}
+
+fn format_date(timestamp: Timestamp) -> String {
+    timestamp.strftime("%Y-%m-%d").to_string()
+}
+
+fn truncate(s: &str, max_chars: usize) -> String {
+    match s.char_indices().nth(max_chars) {
+        Some((i, _)) => format!("{}…", &s[..i]),
+        None => s.to_owned(),
+    }
+}
+
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn page(title: &str, body: &str) -> String {
+    format!(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>{}</title>\n\
+         <style>body{{font-family:sans-serif;margin:1.5em}}table{{border-collapse:collapse}}\
+         td,th{{padding:2px 8px;text-align:left}}tr:nth-child(even){{background:#f3f4f6}}</style>\n\
+         </head><body>\n{body}</body></html>\n",
+        escape(title)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_swimlanes() {
+        let event = |timestamp: &str, action, target: &str, user_id| TimelineEvent {
+            timestamp: timestamp.parse().unwrap(),
+            action,
+            target: target.into(),
+            user_id,
+        };
+        let events = vec![
+            event("2024-01-01T00:00:00Z", Action::Add, "Old link", 1),
+            event("2024-11-04T10:00:00Z", Action::Add, "<script>", 2),
+            event("2024-11-05T10:00:00Z", Action::Remove, "<script>", 3),
+        ];
+        let week: IsoWeek = "2024-45".parse().unwrap();
+        let svg = swimlanes(&events, week.start(), week.end(), Some(week));
+        assert!(svg.contains("&lt;script&gt;"));
+        assert!(!svg.contains("<script>"));
+        assert!(!svg.contains("Old link"), "unchanged links are not shown");
+        assert_eq!(svg.matches("<circle").count(), 1 + 2, "one add and legend");
+    }
+}
@@ -0,0 +1,344 @@
+//! Live ingestion from the Wikimedia EventStreams `recentchange` feed.
+//!
+//! Edits in the chosen namespaces are looked up through the Action API
+//! for their wikitext and merged into `TempDb` like revisions from an
+//! adds-changes dump, so they are picked up by incremental
+//! postprocessing.
+
+use crate::dataset;
+use crate::ingest::temp_db::{TempArticle, TempDb};
+use crate::wiki_api::{self, WikiApi};
+use anyhow::Context;
+use reqwest::Client;
+use serde::Deserialize;
+use std::time::Duration;
+use tokio::select;
+use tokio::signal::ctrl_c;
+
+/// Wait before reconnecting after the stream failed or ended.
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+#[derive(Debug, clap::Args)]
+pub struct StreamArgs {
+    #[clap(
+        long,
+        default_value = "https://stream.wikimedia.org/v2/stream/recentchange"
+    )]
+    stream_url: String,
+    /// Only edits of this wiki, by database name.
+    #[clap(long, default_value = dataset::DEFAULT_WIKI)]
+    wiki: String,
+    /// `api.php` endpoint of the wiki, to fetch wikitext from. Defaults
+    /// to that of the Wikipedia of `--wiki`.
+    #[clap(long)]
+    api_url: Option<String>,
+    /// Namespaces to ingest edits of.
+    #[clap(long, value_delimiter = ',', default_value = "0")]
+    namespaces: Vec<i64>,
+    /// Also ingest edits flagged as bot edits.
+    #[clap(long)]
+    include_bots: bool,
+    /// Stop after ingesting this many edits.
+    #[clap(long)]
+    max_edits: Option<usize>,
+}
+
+/// One server-sent event.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct SseEvent {
+    id: Option<String>,
+    data: String,
+}
+
+/// Splits a server-sent event stream into events, as chunks of it
+/// arrive.
+#[derive(Default)]
+struct SseDecoder {
+    buf: Vec<u8>,
+    event: SseEvent,
+}
+
+impl SseDecoder {
+    fn push(&mut self, chunk: &[u8]) -> Vec<SseEvent> {
+        self.buf.extend_from_slice(chunk);
+        let mut events = Vec::new();
+        while let Some(end) = self.buf.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.buf.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim_end_matches(['\r', '\n']);
+            if line.is_empty() {
+                if !self.event.data.is_empty() {
+                    events.push(std::mem::take(&mut self.event));
+                }
+                continue;
+            }
+            // lines starting with a colon are comments
+            let (field, value) = line.split_once(':').unwrap_or((line, ""));
+            let value = value.strip_prefix(' ').unwrap_or(value);
+            match field {
+                "id" => self.event.id = Some(value.to_owned()),
+                "data" => {
+                    if !self.event.data.is_empty() {
+                        self.event.data.push('\n');
+                    }
+                    self.event.data.push_str(value);
+                }
+                _ => {}
+            }
+        }
+        events
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct RecentChange {
+    #[serde(rename = "type")]
+    kind: String,
+    wiki: String,
+    namespace: i64,
+    title: String,
+    #[serde(default)]
+    bot: bool,
+    revision: Option<RevisionIds>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RevisionIds {
+    new: u64,
+}
+
+pub async fn stream(temp_db: TempDb, args: &StreamArgs) -> anyhow::Result<()> {
+    let temp_db = temp_db.for_wiki(&args.wiki);
+    let client = wiki_api::client()?;
+    let api_url = match &args.api_url {
+        Some(url) => url.clone(),
+        None => wiki_api::api_url(&args.wiki)?,
+    };
+    let api = WikiApi::new(client.clone(), api_url);
+    let mut last_event_id = None;
+    let mut ingested = 0;
+    loop {
+        let result = select! {
+            _ = ctrl_c() => {
+                tracing::info!("SIGINT, exiting");
+                break;
+            }
+            result = run_stream(&client, &api, &temp_db, args, &mut last_event_id, &mut ingested) => result,
+        };
+        if args.max_edits.is_some_and(|max| ingested >= max) {
+            break;
+        }
+        match result {
+            Ok(()) => tracing::info!("stream ended, reconnecting"),
+            Err(e) => tracing::warn!("stream failed, reconnecting: {e:#}"),
+        }
+        tokio::time::sleep(RECONNECT_DELAY).await;
+    }
+    tracing::info!(
+        "ingested {ingested} edits, {} articles awaiting postprocessing",
+        temp_db.dirty_count()?
+    );
+    Ok(())
+}
+
+/// Consumes the stream until it ends, resuming after `last_event_id`.
+async fn run_stream(
+    client: &Client,
+    api: &WikiApi,
+    temp_db: &TempDb,
+    args: &StreamArgs,
+    last_event_id: &mut Option<String>,
+    ingested: &mut usize,
+) -> anyhow::Result<()> {
+    let mut request = client
+        .get(&args.stream_url)
+        .header("Accept", "text/event-stream");
+    if let Some(id) = last_event_id.as_deref() {
+        request = request.header("Last-Event-ID", id);
+    }
+    let mut response = request.send().await?.error_for_status()?;
+    tracing::info!("connected to {}", args.stream_url);
+
+    let mut decoder = SseDecoder::default();
+    while let Some(chunk) = response.chunk().await? {
+        for event in decoder.push(&chunk) {
+            // only resume past an event once it has been merged or skipped,
+            // so a failed fetch is retried after reconnecting
+            let change: RecentChange = match serde_json::from_str(&event.data) {
+                Ok(change) => change,
+                Err(e) => {
+                    tracing::debug!("skipping unparseable event: {e}");
+                    advance(last_event_id, event.id);
+                    continue;
+                }
+            };
+            let Some(revision_id) = wanted_revision(&change, args) else {
+                advance(last_event_id, event.id);
+                continue;
+            };
+            ingest_revision(api, temp_db, revision_id)
+                .await
+                .with_context(|| format!("failed to ingest revision {revision_id}"))?;
+            advance(last_event_id, event.id);
+            tracing::debug!("ingested revision {revision_id} of {}", change.title);
+            *ingested += 1;
+            if args.max_edits.is_some_and(|max| *ingested >= max) {
+                return Ok(());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Records `id` as the last handled event, keeping the previous ID for
+/// events that carry none.
+fn advance(last_event_id: &mut Option<String>, id: Option<String>) {
+    if id.is_some() {
+        *last_event_id = id;
+    }
+}
+
+/// The new revision of an edit or page creation to ingest, if any.
+fn wanted_revision(change: &RecentChange, args: &StreamArgs) -> Option<u64> {
+    let wanted = matches!(change.kind.as_str(), "edit" | "new")
+        && change.wiki == args.wiki
+        && args.namespaces.contains(&change.namespace)
+        && (args.include_bots || !change.bot);
+    wanted
+        .then(|| change.revision.as_ref().map(|revision| revision.new))
+        .flatten()
+}
+
+async fn ingest_revision(api: &WikiApi, temp_db: &TempDb, revision_id: u64) -> anyhow::Result<()> {
+    let revisions = api.revisions_by_id(&[revision_id]).await?;
+    let Some(revision) = revisions.first() else {
+        // deleted or suppressed before we got to it
+        tracing::debug!("revision {revision_id} is not available");
+        return Ok(());
+    };
+    let article = TempArticle {
+        title: revision.title.clone(),
+        revisions: vec![revision.to_temp_revision()],
+    };
+    let temp_db = temp_db.clone();
+    tokio::task::spawn_blocking(move || temp_db.merge_article_batch([article])).await??;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::Router;
+    use axum::extract::Query;
+    use axum::routing::get;
+    use foldhash::HashMap;
+
+    /// Replays a recorded `recentchange` stream and serves recorded
+    /// Action API responses by revision ID.
+    async fn stand_in() -> String {
+        let api_responses: HashMap<String, serde_json::Value> =
+            serde_json::from_str(include_str!("../test_api_revisions.json")).unwrap();
+        let app = Router::new()
+            .route(
+                "/stream",
+                get(|| async {
+                    (
+                        [("content-type", "text/event-stream")],
+                        include_str!("../test_recentchange.sse"),
+                    )
+                }),
+            )
+            .route(
+                "/api.php",
+                get(
+                    move |Query(params): Query<HashMap<String, String>>| async move {
+                        axum::Json(api_responses[&params["revids"]].clone())
+                    },
+                ),
+            );
+        wiki_api::serve_stand_in(app).await
+    }
+
+    #[tokio::test]
+    async fn test_stream_fixture() {
+        let url = stand_in().await;
+        let path = std::env::temp_dir().join(format!("stream-test-{}.redb", std::process::id()));
+        let (temp_db, _shutdown) = TempDb::open_at(&path).unwrap();
+        let args = StreamArgs {
+            stream_url: format!("{url}/stream"),
+            wiki: "enwiki".to_owned(),
+            api_url: Some(format!("{url}/api.php")),
+            namespaces: vec![0],
+            include_bots: false,
+            max_edits: Some(2),
+        };
+        stream(temp_db.clone(), &args).await.unwrap();
+
+        let article = temp_db.get_article("Macy's").unwrap().unwrap();
+        assert_eq!(article.revisions.len(), 1);
+        assert_eq!(article.revisions[0].id, 1189000001);
+        assert_eq!(
+            article.revisions[0].links,
+            ["Herald Square", "New York City", "department store"]
+        );
+        let article = temp_db.get_article("Herald Square").unwrap().unwrap();
+        assert_eq!(article.revisions[0].links, ["Manhattan"]);
+        assert_ne!(article.revisions[0].user_id, 0);
+        assert_eq!(temp_db.dirty_count().unwrap(), 2);
+        drop(temp_db);
+        std::fs::remove_file(path).ok();
+    }
+
+    #[tokio::test]
+    async fn test_failed_fetch_is_retried() {
+        let url = stand_in().await;
+        let path = std::env::temp_dir().join(format!("stream-retry-{}.redb", std::process::id()));
+        let (temp_db, _shutdown) = TempDb::open_at(&path).unwrap();
+        let args = StreamArgs {
+            stream_url: format!("{url}/stream"),
+            wiki: "enwiki".to_owned(),
+            api_url: None,
+            namespaces: vec![0],
+            include_bots: false,
+            max_edits: None,
+        };
+        let client = wiki_api::client().unwrap();
+        let api = WikiApi::new(client.clone(), format!("{url}/missing"));
+        let mut last_event_id = None;
+        let mut ingested = 0;
+        let result = run_stream(
+            &client,
+            &api,
+            &temp_db,
+            &args,
+            &mut last_event_id,
+            &mut ingested,
+        )
+        .await;
+
+        // the first wanted edit failed, so resuming must replay it
+        assert!(result.is_err());
+        assert_eq!(ingested, 0);
+        assert_eq!(
+            last_event_id.as_deref(),
+            Some(
+                r#"[{"topic":"eqiad.mediawiki.recentchange","partition":0,"timestamp":1700000000004}]"#
+            )
+        );
+        drop(temp_db);
+        std::fs::remove_file(path).ok();
+    }
+
+    #[test]
+    fn test_sse_decoder() {
+        let mut decoder = SseDecoder::default();
+        assert!(decoder.push(b":ok\n\nid: 1\nda").is_empty());
+        assert_eq!(
+            decoder.push(b"ta: {}\r\n\r\n"),
+            [SseEvent {
+                id: Some("1".to_owned()),
+                data: "{}".to_owned()
+            }]
+        );
+    }
+}
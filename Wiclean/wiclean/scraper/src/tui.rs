@@ -0,0 +1,420 @@
+//! Terminal UI for browsing link histories in `TempDb`, for use over
+//! SSH where the `serve` pages are out of reach.
+//!
+//! Type to search titles and press Enter to open an article. In the
+//! history view, `u` and `w` filter by user and week, `c` clears the
+//! filters, Enter opens the history of the selected link target and
+//! Esc goes back.
+
+use crate::dataset::{self, Action};
+use crate::history::{self, TimelineEvent};
+use crate::ingest::temp_db::TempDb;
+use crate::week::IsoWeek;
+use compact_str::CompactString;
+use ratatui::DefaultTerminal;
+use ratatui::Frame;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Style, Stylize};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, List, ListState, Paragraph, Row, Table, TableState};
+
+const SEARCH_LIMIT: usize = 200;
+
+#[derive(Debug, clap::Args)]
+pub struct TuiArgs {
+    /// Wiki to browse, by database name.
+    #[clap(long, default_value = dataset::DEFAULT_WIKI)]
+    wiki: String,
+}
+
+pub fn run_tui(temp_db: &TempDb, args: &TuiArgs) -> anyhow::Result<()> {
+    let mut terminal = ratatui::init();
+    let result = App::new(temp_db.for_wiki(&args.wiki)).run(&mut terminal);
+    ratatui::restore();
+    result
+}
+
+struct App {
+    temp_db: TempDb,
+    query: String,
+    results: Vec<CompactString>,
+    results_state: ListState,
+    /// Opened histories, the current one last.
+    stack: Vec<HistoryView>,
+    prompt: Option<Prompt>,
+    status: String,
+}
+
+/// A filter being typed in the history view.
+struct Prompt {
+    filter: Filter,
+    input: String,
+}
+
+#[derive(Clone, Copy)]
+enum Filter {
+    User,
+    Week,
+}
+
+struct HistoryView {
+    title: CompactString,
+    events: Vec<TimelineEvent>,
+    /// Indexes of the events passing the filters.
+    shown: Vec<usize>,
+    state: TableState,
+    user: Option<i64>,
+    week: Option<IsoWeek>,
+}
+
+impl HistoryView {
+    fn new(title: CompactString, events: Vec<TimelineEvent>) -> Self {
+        let mut view = Self {
+            title,
+            events,
+            shown: Vec::new(),
+            state: TableState::default(),
+            user: None,
+            week: None,
+        };
+        view.apply_filters();
+        view
+    }
+
+    fn apply_filters(&mut self) {
+        self.shown = self
+            .events
+            .iter()
+            .enumerate()
+            .filter(|(_, event)| {
+                self.user.is_none_or(|user| event.user_id == user)
+                    && self
+                        .week
+                        .is_none_or(|week| IsoWeek::of(event.timestamp) == week)
+            })
+            .map(|(i, _)| i)
+            .collect();
+        self.state.select((!self.shown.is_empty()).then_some(0));
+    }
+
+    fn selected(&self) -> Option<&TimelineEvent> {
+        let i = *self.shown.get(self.state.selected()?)?;
+        Some(&self.events[i])
+    }
+}
+
+impl App {
+    fn new(temp_db: TempDb) -> Self {
+        Self {
+            temp_db,
+            query: String::new(),
+            results: Vec::new(),
+            results_state: ListState::default(),
+            stack: Vec::new(),
+            prompt: None,
+            status: String::new(),
+        }
+    }
+
+    fn run(mut self, terminal: &mut DefaultTerminal) -> anyhow::Result<()> {
+        loop {
+            terminal.draw(|frame| self.draw(frame))?;
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            if key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL) {
+                return Ok(());
+            }
+            let quit = if self.prompt.is_some() {
+                self.on_prompt_key(key);
+                false
+            } else if self.stack.is_empty() {
+                self.on_search_key(key)?
+            } else {
+                self.on_history_key(key)?
+            };
+            if quit {
+                return Ok(());
+            }
+        }
+    }
+
+    fn on_search_key(&mut self, key: KeyEvent) -> anyhow::Result<bool> {
+        match key.code {
+            KeyCode::Esc => return Ok(true),
+            KeyCode::Char(c) => {
+                self.query.push(c);
+                self.search()?;
+            }
+            KeyCode::Backspace => {
+                self.query.pop();
+                self.search()?;
+            }
+            KeyCode::Down => self.results_state.select_next(),
+            KeyCode::Up => self.results_state.select_previous(),
+            KeyCode::Enter => {
+                let selected = self
+                    .results_state
+                    .selected()
+                    .and_then(|i| self.results.get(i))
+                    .cloned();
+                let title = selected.unwrap_or_else(|| self.query.as_str().into());
+                self.open(&title)?;
+            }
+            _ => {}
+        }
+        Ok(false)
+    }
+
+    fn on_history_key(&mut self, key: KeyEvent) -> anyhow::Result<bool> {
+        let view = self.stack.last_mut().expect("history view is open");
+        match key.code {
+            KeyCode::Char('q') => return Ok(true),
+            KeyCode::Esc | KeyCode::Backspace => {
+                self.stack.pop();
+                self.status.clear();
+            }
+            KeyCode::Char('/') => {
+                self.stack.clear();
+                self.status.clear();
+            }
+            KeyCode::Down | KeyCode::Char('j') => view.state.select_next(),
+            KeyCode::Up | KeyCode::Char('k') => view.state.select_previous(),
+            KeyCode::PageDown => view.state.scroll_down_by(20),
+            KeyCode::PageUp => view.state.scroll_up_by(20),
+            KeyCode::Home | KeyCode::Char('g') => view.state.select_first(),
+            KeyCode::End | KeyCode::Char('G') => view.state.select_last(),
+            KeyCode::Char('u') => {
+                self.prompt = Some(Prompt {
+                    filter: Filter::User,
+                    input: view.user.map(|user| user.to_string()).unwrap_or_default(),
+                });
+            }
+            KeyCode::Char('w') => {
+                self.prompt = Some(Prompt {
+                    filter: Filter::Week,
+                    input: view.week.map(|week| week.to_string()).unwrap_or_default(),
+                });
+            }
+            KeyCode::Char('c') => {
+                view.user = None;
+                view.week = None;
+                view.apply_filters();
+            }
+            KeyCode::Enter => {
+                if let Some(target) = view.selected().map(|event| event.target.clone()) {
+                    self.open(&target)?;
+                }
+            }
+            _ => {}
+        }
+        Ok(false)
+    }
+
+    fn on_prompt_key(&mut self, key: KeyEvent) {
+        let Some(prompt) = &mut self.prompt else {
+            return;
+        };
+        match key.code {
+            KeyCode::Esc => self.prompt = None,
+            KeyCode::Char(c) => prompt.input.push(c),
+            KeyCode::Backspace => {
+                prompt.input.pop();
+            }
+            KeyCode::Enter => {
+                let Some(prompt) = self.prompt.take() else {
+                    return;
+                };
+                let view = self.stack.last_mut().expect("history view is open");
+                let input = prompt.input.trim();
+                let parsed: anyhow::Result<()> = match prompt.filter {
+                    Filter::User if input.is_empty() => {
+                        view.user = None;
+                        Ok(())
+                    }
+                    Filter::User => input
+                        .parse()
+                        .map(|user| view.user = Some(user))
+                        .map_err(anyhow::Error::from),
+                    Filter::Week if input.is_empty() => {
+                        view.week = None;
+                        Ok(())
+                    }
+                    Filter::Week => input.parse().map(|week| view.week = Some(week)),
+                };
+                match parsed {
+                    Ok(()) => {
+                        view.apply_filters();
+                        self.status.clear();
+                    }
+                    Err(e) => self.status = format!("invalid filter: {e:#}"),
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn search(&mut self) -> anyhow::Result<()> {
+        self.results = if self.query.is_empty() {
+            Vec::new()
+        } else {
+            self.temp_db.search_titles(&self.query, SEARCH_LIMIT)?
+        };
+        self.results_state
+            .select((!self.results.is_empty()).then_some(0));
+        Ok(())
+    }
+
+    fn open(&mut self, title: &str) -> anyhow::Result<()> {
+        match self.temp_db.get_article(title)? {
+            Some(article) => {
+                let events = history::link_timeline(&article);
+                self.status = format!(
+                    "{} revisions, {} link events",
+                    article.revisions.len(),
+                    events.len()
+                );
+                self.stack.push(HistoryView::new(article.title, events));
+            }
+            None => self.status = format!("no article titled {title:?}"),
+        }
+        Ok(())
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [main, status] =
+            Layout::vertical([Constraint::Min(0), Constraint::Length(1)]).areas(frame.area());
+        let help = match (&self.prompt, self.stack.is_empty()) {
+            (Some(_), _) => "Enter apply  Esc cancel  (empty clears the filter)",
+            (None, true) => "type to search  ↑↓ select  Enter open  Esc quit",
+            (None, false) => {
+                "↑↓ scroll  Enter open target  u user  w week  c clear  Esc back  / search  q quit"
+            }
+        };
+        let status_line = if self.status.is_empty() {
+            Line::from(help.dark_gray())
+        } else {
+            Line::from(vec![
+                self.status.as_str().yellow(),
+                "  ".into(),
+                help.dark_gray(),
+            ])
+        };
+        frame.render_widget(Paragraph::new(status_line), status);
+
+        if self.stack.is_empty() {
+            self.draw_search(frame, main);
+        } else {
+            self.draw_history(frame, main);
+        }
+    }
+
+    fn draw_search(&mut self, frame: &mut Frame, area: Rect) {
+        let [input, results] =
+            Layout::vertical([Constraint::Length(3), Constraint::Min(0)]).areas(area);
+        frame.render_widget(
+            Paragraph::new(self.query.as_str()).block(Block::bordered().title("Search titles")),
+            input,
+        );
+        let list = List::new(self.results.iter().map(|title| title.as_str()))
+            .block(Block::bordered().title(format!("{} matches", self.results.len())))
+            .highlight_style(Style::new().reversed());
+        frame.render_stateful_widget(list, results, &mut self.results_state);
+    }
+
+    fn draw_history(&mut self, frame: &mut Frame, area: Rect) {
+        let [filters, table] =
+            Layout::vertical([Constraint::Length(3), Constraint::Min(0)]).areas(area);
+        let view = self.stack.last_mut().expect("history view is open");
+
+        let describe = |value: Option<String>| value.unwrap_or_else(|| "any".to_owned());
+        let mut filter_text = format!(
+            "user: {}   week: {}   {} of {} events",
+            describe(view.user.map(|user| user.to_string())),
+            describe(view.week.map(|week| week.to_string())),
+            view.shown.len(),
+            view.events.len()
+        );
+        if let Some(prompt) = &self.prompt {
+            let name = match prompt.filter {
+                Filter::User => "user id",
+                Filter::Week => "week (YYYY-W)",
+            };
+            filter_text = format!("{name}: {}▏", prompt.input);
+        }
+        let breadcrumbs = self
+            .stack
+            .iter()
+            .map(|view| view.title.as_str())
+            .collect::<Vec<_>>()
+            .join(" › ");
+        frame.render_widget(
+            Paragraph::new(filter_text).block(Block::bordered().title(breadcrumbs)),
+            filters,
+        );
+
+        let view = self.stack.last_mut().expect("history view is open");
+        let rows = view.shown.iter().map(|&i| {
+            let event = &view.events[i];
+            let (symbol, color) = match event.action {
+                Action::Add => ("+", Color::Green),
+                Action::Remove => ("-", Color::Red),
+            };
+            Row::new(vec![
+                event.timestamp.strftime("%Y-%m-%d %H:%M:%S").to_string(),
+                IsoWeek::of(event.timestamp).to_string(),
+                event.user_id.to_string(),
+                symbol.to_owned(),
+                event.target.to_string(),
+            ])
+            .style(Style::new().fg(color))
+        });
+        let widths = [
+            Constraint::Length(19),
+            Constraint::Length(8),
+            Constraint::Length(20),
+            Constraint::Length(1),
+            Constraint::Min(10),
+        ];
+        let table_widget = Table::new(rows, widths)
+            .header(Row::new(["time (UTC)", "week", "user", "", "target"]).bold())
+            .block(Block::bordered())
+            .row_highlight_style(Style::new().reversed());
+        frame.render_stateful_widget(table_widget, table, &mut view.state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filters() {
+        let event = |timestamp: &str, user_id| TimelineEvent {
+            timestamp: timestamp.parse().unwrap(),
+            action: Action::Add,
+            target: "Michigan".into(),
+            user_id,
+        };
+        let mut view = HistoryView::new(
+            "Detroit".into(),
+            vec![
+                event("2024-11-04T00:00:00Z", 1),
+                event("2024-11-05T00:00:00Z", 2),
+                event("2024-11-12T00:00:00Z", 1),
+            ],
+        );
+        assert_eq!(view.shown, [0, 1, 2]);
+        view.user = Some(1);
+        view.apply_filters();
+        assert_eq!(view.shown, [0, 2]);
+        view.week = Some("2024-45".parse().unwrap());
+        view.apply_filters();
+        assert_eq!(view.shown, [0]);
+        assert_eq!(view.selected().unwrap().user_id, 1);
+    }
+}
@@ -0,0 +1,105 @@
+//! ISO 8601 year-week buckets used to group edits.
+//!
+//! Spark's `concat_ws("-", year(ts), weekofyear(ts))` combines the
+//! calendar year with the ISO week number, which mislabels the days
+//! around new year (e.g. 2024-12-30 becomes `2024-1`). Here the year
+//! is always the ISO week-numbering year.
+
+use anyhow::Context;
+use jiff::civil::{Date, ISOWeekDate, Weekday};
+use jiff::tz::TimeZone;
+use jiff::{SignedDuration, Timestamp};
+use std::fmt;
+use std::str::FromStr;
+
+/// An ISO week, formatted as `YYYY-W` without zero padding
+/// (`2024-1`, `2024-45`) to match the notebooks and `examples.txt`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct IsoWeek {
+    pub year: i16,
+    pub week: i8,
+}
+
+impl IsoWeek {
+    pub fn of(timestamp: Timestamp) -> Self {
+        Self::of_date(TimeZone::UTC.to_datetime(timestamp).date())
+    }
+
+    pub fn of_date(date: Date) -> Self {
+        let iso = date.iso_week_date();
+        Self {
+            year: iso.year(),
+            week: iso.week(),
+        }
+    }
+
+    /// Monday 00:00 UTC at the start of the week.
+    pub fn start(self) -> Timestamp {
+        ISOWeekDate::new(self.year, self.week, Weekday::Monday)
+            .expect("invalid ISO week")
+            .date()
+            .to_zoned(TimeZone::UTC)
+            .expect("ISO week out of range")
+            .timestamp()
+    }
+
+    /// Start of the following week.
+    pub fn end(self) -> Timestamp {
+        self.start() + SignedDuration::from_hours(7 * 24)
+    }
+}
+
+impl fmt::Display for IsoWeek {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.year, self.week)
+    }
+}
+
+impl FromStr for IsoWeek {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (year, week) = s
+            .trim()
+            .split_once('-')
+            .with_context(|| format!("week {s:?} is not of the form YYYY-W"))?;
+        let year: i16 = year.parse().with_context(|| format!("bad year in {s:?}"))?;
+        let week: i8 = week.parse().with_context(|| format!("bad week in {s:?}"))?;
+        ISOWeekDate::new(year, week, Weekday::Monday)
+            .with_context(|| format!("{s:?} is not a valid ISO week"))?;
+        Ok(Self { year, week })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jiff::civil::date;
+
+    #[test]
+    fn test_new_year_weeks() {
+        // Monday of ISO week 1 of 2025
+        assert_eq!(IsoWeek::of_date(date(2024, 12, 30)).to_string(), "2025-1");
+        // Sunday still in the last ISO week of 2020
+        assert_eq!(IsoWeek::of_date(date(2021, 1, 3)).to_string(), "2020-53");
+        assert_eq!(IsoWeek::of_date(date(2024, 11, 5)).to_string(), "2024-45");
+
+        let week: IsoWeek = "2025-1".parse().unwrap();
+        assert_eq!(week.start().to_string(), "2024-12-30T00:00:00Z");
+        assert_eq!(week.end().to_string(), "2025-01-06T00:00:00Z");
+    }
+
+    #[test]
+    fn test_parse() {
+        let week: IsoWeek = "2025-1".parse().unwrap();
+        assert_eq!(
+            week,
+            IsoWeek {
+                year: 2025,
+                week: 1
+            }
+        );
+        assert!("2024-54".parse::<IsoWeek>().is_err());
+        assert!("2024".parse::<IsoWeek>().is_err());
+    }
+}
@@ -0,0 +1,285 @@
+//! Minimal client for the MediaWiki Action API, for fetching
+//! revisions without going through a dump.
+
+use crate::ingest::parser;
+use crate::ingest::temp_db::TempArticleRevision;
+use anyhow::Context;
+use compact_str::CompactString;
+use jiff::Timestamp;
+use reqwest::Client;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// Wikimedia asks API clients to identify themselves.
+pub const USER_AGENT: &str = "wikiscrape5 (Wiclean link history research)";
+
+/// Seconds of database replication lag above which the API should turn
+/// requests away, as asked of non-interactive clients.
+const MAXLAG: &str = "5";
+const MAX_LAG_RETRIES: usize = 10;
+/// Wait after a `maxlag` error if the response does not say how long.
+const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(5);
+/// Revisions per request; the API allows at most 50 with content.
+const REVISIONS_PER_REQUEST: &str = "50";
+
+/// Base URL of a Wikipedia by database name, e.g.
+/// `https://de.wikipedia.org` for `dewiki`.
+pub fn site_url(wiki: &str) -> anyhow::Result<String> {
+    let language = wiki
+        .strip_suffix("wiki")
+        .filter(|language| !language.is_empty())
+        .with_context(|| format!("{wiki:?} is not the database name of a Wikipedia"))?;
+    Ok(format!(
+        "https://{}.wikipedia.org",
+        language.replace('_', "-")
+    ))
+}
+
+/// `api.php` endpoint of a Wikipedia by database name.
+pub fn api_url(wiki: &str) -> anyhow::Result<String> {
+    Ok(format!("{}/w/api.php", site_url(wiki)?))
+}
+
+pub fn client() -> anyhow::Result<Client> {
+    Ok(Client::builder()
+        .user_agent(USER_AGENT)
+        .connect_timeout(Duration::from_secs(10))
+        .read_timeout(Duration::from_secs(60))
+        .build()?)
+}
+
+/// Serves `app` on a local port in the background, standing in for a
+/// Wikimedia server in tests. Returns the base URL. Also installs the
+/// TLS provider [`client`] needs, so call this before building one.
+#[cfg(test)]
+pub async fn serve_stand_in(app: axum::Router) -> String {
+    rustls::crypto::aws_lc_rs::default_provider()
+        .install_default()
+        .ok();
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    format!("http://{addr}")
+}
+
+/// A revision with its wikitext, as returned by `prop=revisions`.
+#[derive(Debug, Clone)]
+pub struct ApiRevision {
+    pub title: CompactString,
+    pub id: u64,
+    pub timestamp: Timestamp,
+    pub user_id: i64,
+    pub text: String,
+}
+
+impl ApiRevision {
+    /// Extracts links and the infobox type the same way dump ingestion does.
+    pub fn to_temp_revision(&self) -> TempArticleRevision {
+        TempArticleRevision::from_text(self.id, self.user_id, self.timestamp, &self.text)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Response {
+    query: Option<Query>,
+    error: Option<ErrorBody>,
+    /// Parameters to send along to get the next page of results.
+    #[serde(rename = "continue")]
+    continuation: Option<BTreeMap<String, serde_json::Value>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ErrorBody {
+    code: String,
+    info: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Query {
+    #[serde(default)]
+    pages: Vec<Page>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Page {
+    title: CompactString,
+    #[serde(default)]
+    missing: bool,
+    #[serde(default)]
+    revisions: Vec<Revision>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Revision {
+    revid: u64,
+    timestamp: Timestamp,
+    user: Option<String>,
+    userid: Option<i64>,
+    #[serde(default)]
+    anon: bool,
+    slots: Option<Slots>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Slots {
+    main: Slot,
+}
+
+#[derive(Debug, Deserialize)]
+struct Slot {
+    content: Option<String>,
+}
+
+impl Revision {
+    fn into_api_revision(self, title: &CompactString) -> ApiRevision {
+        // anonymous edits get the same hashed IDs as in the dumps, and
+        // hidden users count as user 0 like missing contributors do
+        let user_id = match (self.anon, &self.user) {
+            (true, Some(ip)) => parser::ip_user_id(ip),
+            _ => self.userid.unwrap_or(0),
+        };
+        ApiRevision {
+            title: title.clone(),
+            id: self.revid,
+            timestamp: self.timestamp,
+            user_id,
+            text: self
+                .slots
+                .and_then(|slots| slots.main.content)
+                .unwrap_or_default(),
+        }
+    }
+}
+
+pub struct WikiApi {
+    client: Client,
+    url: String,
+}
+
+impl WikiApi {
+    /// `url` is the `api.php` endpoint of the wiki.
+    pub fn new(client: Client, url: impl Into<String>) -> Self {
+        Self {
+            client,
+            url: url.into(),
+        }
+    }
+
+    /// Fetches revisions with their wikitext by ID, up to 50 at a time.
+    pub async fn revisions_by_id(&self, ids: &[u64]) -> anyhow::Result<Vec<ApiRevision>> {
+        let ids = ids.iter().map(u64::to_string).collect::<Vec<_>>().join("|");
+        let response = self
+            .query(&[
+                ("prop", "revisions"),
+                ("revids", &ids),
+                ("rvprop", "ids|timestamp|user|userid|content"),
+                ("rvslots", "main"),
+            ])
+            .await?;
+        Ok(revisions_of(response))
+    }
+
+    /// Fetches all revisions of an article since `since`, oldest first,
+    /// following continuations.
+    pub async fn article_history(
+        &self,
+        title: &str,
+        since: Timestamp,
+    ) -> anyhow::Result<Vec<ApiRevision>> {
+        let since = since.to_string();
+        let mut continuation = BTreeMap::<String, String>::new();
+        let mut revisions = Vec::new();
+        loop {
+            let mut params = vec![
+                ("prop", "revisions"),
+                ("titles", title),
+                ("rvprop", "ids|timestamp|user|userid|content"),
+                ("rvslots", "main"),
+                ("rvdir", "newer"),
+                ("rvstart", &since),
+                ("rvlimit", REVISIONS_PER_REQUEST),
+            ];
+            params.extend(
+                continuation
+                    .iter()
+                    .map(|(key, value)| (key.as_str(), value.as_str())),
+            );
+            let mut response = self.query(&params).await?;
+            if let Some(page) = response
+                .query
+                .as_ref()
+                .and_then(|query| query.pages.first())
+            {
+                anyhow::ensure!(!page.missing, "no article titled {title:?}");
+            }
+            let next = response.continuation.take();
+            revisions.extend(revisions_of(response));
+            tracing::debug!("fetched {} revisions of {title}", revisions.len());
+            match next {
+                Some(next) => {
+                    continuation = next
+                        .into_iter()
+                        .map(|(key, value)| {
+                            let value = match value {
+                                serde_json::Value::String(value) => value,
+                                value => value.to_string(),
+                            };
+                            (key, value)
+                        })
+                        .collect();
+                }
+                None => break,
+            }
+        }
+        Ok(revisions)
+    }
+
+    /// Sends a query, waiting and retrying while the servers are lagged.
+    async fn query(&self, params: &[(&str, &str)]) -> anyhow::Result<Response> {
+        for _ in 0..MAX_LAG_RETRIES {
+            let response = self
+                .client
+                .get(&self.url)
+                .query(&[
+                    ("action", "query"),
+                    ("format", "json"),
+                    ("formatversion", "2"),
+                    ("maxlag", MAXLAG),
+                ])
+                .query(params)
+                .send()
+                .await?
+                .error_for_status()?;
+            let retry_after = response
+                .headers()
+                .get("retry-after")
+                .and_then(|value| value.to_str().ok()?.parse().ok())
+                .map_or(DEFAULT_RETRY_AFTER, Duration::from_secs);
+            let body = response.bytes().await?;
+            let response: Response =
+                serde_json::from_slice(&body).context("invalid API response")?;
+            match &response.error {
+                Some(error) if error.code == "maxlag" => {
+                    tracing::info!("{}, retrying in {retry_after:?}", error.info);
+                    tokio::time::sleep(retry_after).await;
+                }
+                Some(error) => anyhow::bail!("API error {}: {}", error.code, error.info),
+                None => return Ok(response),
+            }
+        }
+        anyhow::bail!("servers stayed lagged after {MAX_LAG_RETRIES} attempts")
+    }
+}
+
+fn revisions_of(response: Response) -> Vec<ApiRevision> {
+    let mut revisions = Vec::new();
+    for page in response.query.map(|query| query.pages).unwrap_or_default() {
+        revisions.extend(
+            page.revisions
+                .into_iter()
+                .map(|revision| revision.into_api_revision(&page.title)),
+        );
+    }
+    revisions
+}